@@ -3,13 +3,13 @@ use std::fs;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 
-const HEADER: &'static str = "<!-- Auto generated by build.rs + README_TEMPLATE.md -->";
+const HEADER: &str = "<!-- Auto generated by build.rs + README_TEMPLATE.md -->";
 
 fn main() -> std::io::Result<()> {
-    if let Ok(_) = std::env::var("DOCS_RS") {
+    if std::env::var("DOCS_RS").is_ok() {
         return Ok(());
     }
-    if let Err(_) = std::env::var("REBUILD_README") {
+    if std::env::var("REBUILD_README").is_err() {
         return Ok(());
     }
     let out = fs::File::create("README.md")?;
@@ -31,7 +31,7 @@ fn main() -> std::io::Result<()> {
         if line.starts_with("@DOC ") {
             let (_, key) = line.split_at(5);
             if let Some(doc) = dict.get(key) {
-                writeln!(readme, "{}", readme_transformation(&doc, &info))?;
+                writeln!(readme, "{}", readme_transformation(doc, &info))?;
             } else {
                 writeln!(readme, "MISSING DOCS: {} not found", key)?;
             }
@@ -49,6 +49,7 @@ fn main() -> std::io::Result<()> {
 #[derive(Debug)]
 struct Snippet {
     raw: String,
+    #[allow(dead_code)]
     file_path: Vec<String>,
 }
 
@@ -83,8 +84,8 @@ fn find_snippets_in_file(
     let mut state = SearchState::OutsideSnippet;
     let mut key = None;
     let mut snippet = String::new();
-    const START_MARKER: &'static str = "// @ START-DOC ";
-    const END_MARKER: &'static str = "// @ END-DOC";
+    const START_MARKER: &str = "// @ START-DOC ";
+    const END_MARKER: &str = "// @ END-DOC";
     for (line_number, line) in BufReader::new(file).lines().enumerate() {
         let line = line?;
         let line = line.trim();
@@ -23,6 +23,32 @@
 //! This is particularly useful when targeting the web. However, Nuts can be used on other platforms, too.
 //! In fact, Nuts has no dependencies aside from std.
 // @ END-DOC CRATE
+//!
+//! ## Threads
+//! Each thread that uses Nuts has its own, independent state: activities, domains, subscriptions, and the message queue.
+//! A message published on one thread never reaches an activity created on another thread.
+//! In return, nothing handled by Nuts has to be `Send` or `Sync`, activities and messages can freely contain types like `Rc`.
+//!
+//! There is no shared mode in which activities are visible to all threads.
+//! To get data from a worker thread into the activities, send it over a channel and publish it on the thread that owns the activities.
+//! Such messages must be `Send`, as required by the channel.
+//! The few values that are meant to leave the thread, like a [`DomainSnapshot`](struct.DomainSnapshot.html) or [`ActivityMetadata`](struct.ActivityMetadata.html), are `Send` themselves.
+//!
+//! Calling Nuts on the wrong thread by accident is easy to miss, since it silently operates on an empty state.
+//! Use [`bind_to_current_thread`](fn.bind_to_current_thread.html) to turn this into a panic in debug builds.
+//!
+//! ```rust
+//! struct Progress(u32);
+//! let ui = nuts::new_activity(0u32);
+//! ui.subscribe(|percent, progress: &Progress| *percent = progress.0);
+//!
+//! let (sender, receiver) = std::sync::mpsc::channel();
+//! std::thread::spawn(move || sender.send(Progress(100)).unwrap());
+//! for progress in receiver {
+//!     nuts::publish(progress);
+//! }
+//! assert_eq!(Some(100), ui.read(|percent| *percent));
+//! ```
 
 // code quality
 #![forbid(unsafe_code)]
@@ -38,17 +64,30 @@
 
 mod nut;
 
-pub use crate::nut::iac::managed_state::{DefaultDomain, DomainEnumeration, DomainState};
+pub use crate::nut::iac::managed_state::{
+    AccessReport, Changed, DefaultDomain, DomainAccess, DomainDiffSnapshot, DomainEnumeration,
+    DomainId, DomainSnapshot, DomainState, DomainTransaction, DomainView,
+};
 use core::any::Any;
 pub use nut::activity::*;
+pub use nut::error::NutsError;
+pub use nut::exec::observer::DispatchEvent;
+pub use nut::exec::panic::HandlerPanic;
+pub use nut::exec::trace::{Delivery, DeliveryTrace};
+pub use nut::iac::capture::{Capture, CapturedMessage};
 pub use nut::iac::filter::*;
+pub use nut::iac::publish::{
+    DispatchSet, DomainReach, PipeId, RequiredMessage, SelfRepublishPolicy, Sequence,
+};
+pub use nut::iac::registry::BoxedHandler;
+pub use nut::iac::subscription::SubscriptionId;
+pub use nut::iac::variants::{MessageVariants, VariantSubscriber};
+pub use nut::metadata::{ActivityInfo, ActivityMetadata};
+pub use nut::wiring::WiringIssue;
 
 use nut::iac::managed_state::*;
 use nut::iac::topic::*;
 
-/// A method on an activity. Can be registered dynamically on activities at runtime.
-pub struct Method<ACTIVITY>(dyn Fn(&mut ACTIVITY, Option<&mut DomainState>));
-
 /// Consumes a struct and registers it as an Activity.
 ///
 /// `nuts::new_activity(...)` is the simplest method to create a new activity.
@@ -58,6 +97,8 @@ pub struct Method<ACTIVITY>(dyn Fn(&mut ACTIVITY, Option<&mut DomainState>));
 /// An `ActivityId` is returned, which is a handle to the newly registered activity.
 /// Use it to register callbacks on the activity.
 ///
+/// The activity has no domain, unless a default domain has been set with [`set_default_domain`](fn.set_default_domain.html).
+///
 /// ### Example:
 // @ START-DOC NEW_ACTIVITY
 /// ```rust
@@ -92,7 +133,40 @@ pub fn new_activity<A>(activity: A) -> ActivityId<A>
 where
     A: Activity,
 {
-    nut::new_activity(activity, DomainId::default(), LifecycleStatus::Active)
+    nut::new_activity(activity, nut::default_domain(), LifecycleStatus::Active)
+}
+
+/// Registers an activity that is also owned outside of nuts, for example a UI widget.
+///
+/// Nuts keeps a clone of the `Rc` and borrows the `RefCell` mutably for each handler call.
+/// If the activity is borrowed outside of nuts while a message is delivered to it, the handler call is skipped.
+/// In debug builds, a warning is printed to stderr in that case.
+///
+/// `on_delete` callbacks are only called if nuts holds the last reference to the activity when it is deleted.
+///
+/// ### Example
+/// ```rust
+/// use std::{cell::RefCell, rc::Rc};
+/// struct Widget { clicks: u32 }
+/// struct Click;
+///
+/// let widget = Rc::new(RefCell::new(Widget { clicks: 0 }));
+/// let activity = nuts::new_shared_activity(widget.clone());
+/// activity.subscribe(|widget, _: &Click| widget.clicks += 1);
+///
+/// nuts::publish(Click);
+/// assert_eq!(1, widget.borrow().clicks);
+///
+/// // Skipped, the widget is borrowed
+/// let borrowed = widget.borrow();
+/// nuts::publish(Click);
+/// assert_eq!(1, borrowed.clicks);
+/// ```
+pub fn new_shared_activity<A>(shared: std::rc::Rc<std::cell::RefCell<A>>) -> ActivityId<A>
+where
+    A: Activity,
+{
+    nut::new_shared_activity(shared, nut::default_domain(), LifecycleStatus::Active)
 }
 
 /// Consumes a struct that is registered as an Activity that has access to the specified domain.
@@ -140,6 +214,93 @@ where
     nut::new_activity(activity, DomainId::new(domain), LifecycleStatus::Active)
 }
 
+/// Requires data to be stored in the domain before activities are added to it.
+///
+/// Handlers of a domained activity, including `on_enter`, usually expect the domain to be initialized.
+/// After calling this function, adding an activity to the domain while it contains no data is considered a bug,
+/// which is detected by a debug assertion. In release builds, no check is performed.
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// nuts::ensure_domain_before_activity(&DefaultDomain);
+/// nuts::store_to_domain(&DefaultDomain, 0u32);
+/// // Would fail the assertion without the line above
+/// nuts::new_domained_activity((), &DefaultDomain);
+/// ```
+pub fn ensure_domain_before_activity<D>(domain: &D)
+where
+    D: DomainEnumeration,
+{
+    nut::ensure_domain_before_activity(DomainId::new(domain))
+}
+
+/// Sets the domain that is assigned to activities created with [`new_activity`](fn.new_activity.html).
+///
+/// This is useful when all activities share a single domain, as `new_activity` can then be used instead of `new_domained_activity`.
+///
+/// The setting is global (for the current thread) and only affects activities created after the call.
+/// Activities that have been registered earlier keep their domain.
+///
+/// ### Example
+/// ```rust
+/// use nuts::{domain_enum, DomainEnumeration};
+/// #[derive(Clone, Copy)]
+/// enum MyDomain {
+///     Main,
+/// }
+/// domain_enum!(MyDomain);
+///
+/// nuts::set_default_domain(&MyDomain::Main);
+/// nuts::store_to_domain(&MyDomain::Main, 42usize);
+/// let activity = nuts::new_activity(());
+/// activity.subscribe_domained(|_, domain, _msg: &()| assert_eq!(42, *domain.get::<usize>()));
+/// nuts::publish(());
+/// ```
+pub fn set_default_domain<D>(domain: &D)
+where
+    D: DomainEnumeration,
+{
+    nut::set_default_domain(DomainId::new(domain))
+}
+
+/// Calls a function on an activity directly and returns its result, like a method call across the activity boundary.
+///
+/// Other than with messages, the call happens immediately, even when called from inside a handler.
+/// Returns `None` if the activity has been deleted or if it is already borrowed.
+/// This is the case while one of its own handlers is executing, thus an activity cannot call itself this way.
+///
+/// ### Example
+/// ```rust
+/// struct Calculator { calls: u32 }
+/// let calculator = nuts::new_activity(Calculator { calls: 0 });
+/// let caller = nuts::new_activity(());
+/// caller.subscribe(move |_, n: &u32| {
+///     let square = nuts::call(calculator, |calc, n: u32| { calc.calls += 1; n * n }, *n);
+///     assert_eq!(Some(9), square);
+/// });
+/// nuts::publish(3u32);
+/// ```
+pub fn call<B, F, ARG, RET>(id: ActivityId<B>, method: F, arg: ARG) -> Option<RET>
+where
+    B: Activity,
+    F: FnOnce(&mut B, ARG) -> RET,
+{
+    nut::call_activity(id, method, arg)
+}
+
+/// Registers a container of long-lived services, such as a renderer, that handlers can access.
+///
+/// Handlers registered with [`subscribe_with_services`](struct.ActivityId.html#method.subscribe_with_services) receive a reference to the services.
+/// In contrast to domains, which store passive data keyed by type, the services are a single object chosen by the user.
+/// Use a struct to register multiple services at once.
+///
+/// Setting services again replaces the previous container.
+/// This function is only valid outside of activities.
+pub fn set_services(services: Box<dyn Any>) {
+    nut::set_services(services)
+}
+
 /// Puts the data object to the domain, which can be accessed by all associated activities.
 ///
 /// This function is only valid outside of activities.
@@ -153,6 +314,417 @@ where
     nut::write_domain(domain, data).expect("You cannot use `store_to_domain` after initialization.")
 }
 
+/// Grants scoped access to a domain from outside of activities, for example to read results after publishing.
+///
+/// Writes inside the closure are reported to the [domain mirror](fn.set_domain_mirror.html) once it returns.
+/// The closure must not publish messages or otherwise call into nuts.
+///
+/// # Panics
+/// Panics if called from inside an activity, where the domain may already be borrowed by a running handler.
+/// Use a domained subscription or [`defer_domain_write`](fn.defer_domain_write.html) there instead.
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// #[derive(Default)]
+/// struct Total(u32);
+/// let activity = nuts::new_domained_activity((), &DefaultDomain);
+/// activity.subscribe_domained(|_, domain, n: &u32| domain.get_or_default::<Total>().0 += n);
+/// nuts::publish(1u32);
+/// nuts::publish(2u32);
+/// let total = nuts::with_domain(&DefaultDomain, |domain| domain.get::<Total>().0);
+/// assert_eq!(3, total);
+/// ```
+pub fn with_domain<D, R>(domain: &D, f: impl FnOnce(&mut DomainState) -> R) -> R
+where
+    D: DomainEnumeration,
+{
+    nut::with_domain(DomainId::new(domain), f)
+}
+
+/// Registers an observer that is called after each message has been dispatched to its subscribers.
+///
+/// The [`DispatchEvent`](struct.DispatchEvent.html) reports the message type, the number of handlers that have been called,
+/// and whether the message was delivered right away or from the queue.
+/// Messages published by handlers are dispatched and reported separately, after the message that published them.
+/// Lifecycle events like `on_enter` are not reported.
+///
+/// The time spent in the handlers is only measured if a clock is available, see [`set_clock`](fn.set_clock.html).
+/// Without an observer, dispatching has no additional cost.
+///
+/// The observer is called while the delivery is still ongoing, hence messages it publishes are queued and observed as well.
+/// Setting an observer again replaces the previous one, but not from inside the observer itself.
+///
+/// ### Example
+/// ```rust
+/// use std::{cell::RefCell, rc::Rc};
+/// struct Frame;
+/// let events = Rc::new(RefCell::new(Vec::new()));
+/// let log = events.clone();
+/// nuts::set_dispatch_observer(move |event| log.borrow_mut().push((event.type_name, event.subscribers, event.queued)));
+///
+/// let renderer = nuts::new_activity(());
+/// renderer.subscribe(|_, _: &Frame| nuts::publish(1u32));
+/// nuts::publish(Frame);
+/// assert_eq!(
+///     vec![(std::any::type_name::<Frame>(), 1, false), ("u32", 0, true)],
+///     *events.borrow()
+/// );
+/// ```
+pub fn set_dispatch_observer<F>(f: F)
+where
+    F: Fn(DispatchEvent) + 'static,
+{
+    nut::set_dispatch_observer(Box::new(f))
+}
+
+/// Replaces the clock used by nuts to measure time, it must return the current time in milliseconds.
+///
+/// By default, `std::time::Instant` is used, which is not available on all platforms, e.g. `wasm32-unknown-unknown`.
+/// On such platforms, set a clock before using any timing feature, such as [`set_slow_handler_threshold_ms`](fn.set_slow_handler_threshold_ms.html).
+/// A custom clock can also be used to control time in tests.
+pub fn set_clock<F>(clock: F)
+where
+    F: Fn() -> u64 + 'static,
+{
+    nut::set_clock(Box::new(clock))
+}
+
+/// Sets the execution time in milliseconds above which a handler is reported to [`on_slow_handler`](fn.on_slow_handler.html).
+///
+/// Handlers are only measured once both the threshold and the callback have been set.
+/// Until then, this feature adds no overhead.
+pub fn set_slow_handler_threshold_ms(threshold_ms: u64) {
+    nut::set_slow_handler_threshold_ms(threshold_ms)
+}
+
+/// Catches panics of handlers and reports them to the callback, instead of unwinding through `publish`.
+///
+/// After a caught panic, the message is still delivered to the remaining subscribers, and queued messages are processed as usual.
+/// The panicking handler is not counted as a delivery.
+/// Without a panic handler, panics in handlers unwind through the call to `publish` that led to the delivery.
+///
+/// The panic hook of the standard library still runs before the callback, use `std::panic::set_hook` to change what it prints.
+/// Panics can only be caught if the binary is compiled with `panic = "unwind"`, which is the default.
+///
+/// ### Example
+/// ```rust
+/// struct Load(&'static str);
+/// let activity = nuts::new_activity(Vec::new());
+/// activity.subscribe(|_, load: &Load| assert!(!load.0.is_empty(), "empty path"));
+/// activity.subscribe(|loaded, load: &Load| loaded.push(load.0));
+///
+/// nuts::set_panic_handler(|panic| {
+///     assert_eq!(Some("empty path"), panic.message());
+/// });
+/// nuts::publish(Load(""));
+/// assert_eq!(Some(vec![""]), activity.read(|loaded| loaded.clone()));
+/// ```
+pub fn set_panic_handler<F>(f: F)
+where
+    F: Fn(&HandlerPanic) + 'static,
+{
+    nut::set_panic_handler(Box::new(f))
+}
+
+/// Registers a callback that is called after a handler took longer than the threshold set with [`set_slow_handler_threshold_ms`](fn.set_slow_handler_threshold_ms.html).
+///
+/// The callback receives the activity, the name of the message type, and the elapsed time in milliseconds.
+///
+/// ### Example
+/// ```rust
+/// struct Render;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Render| std::thread::sleep(std::time::Duration::from_millis(20)));
+///
+/// nuts::set_slow_handler_threshold_ms(10);
+/// nuts::on_slow_handler(|activity, type_name, ms| {
+///     println!("{:?} took {}ms to handle {}", activity, ms, type_name)
+/// });
+/// nuts::publish(Render);
+/// ```
+pub fn on_slow_handler<F>(f: F)
+where
+    F: Fn(UncheckedActivityId, &'static str, u64) + 'static,
+{
+    nut::on_slow_handler(Box::new(f))
+}
+
+/// Registers a callback that is notified about every write to any domain, e.g. to maintain a shadow copy of all domain state.
+///
+/// The callback receives the domain and the `TypeId` of the object that has been written.
+/// Storing an object counts as a write, as does mutable access inside a handler, e.g. through `DomainState::get_mut`, and clearing a domain.
+/// Writes inside handlers are reported once the handler has returned, each type at most once per handler call.
+///
+/// The callback must not call into nuts itself. Setting a mirror again replaces the previous one.
+///
+/// ### Example
+/// ```rust
+/// use nuts::{DefaultDomain, DomainId};
+/// use std::any::TypeId;
+/// nuts::set_domain_mirror(|domain, type_id| {
+///     assert_eq!(DomainId::new(&DefaultDomain), domain);
+///     assert_eq!(TypeId::of::<u32>(), type_id);
+/// });
+/// nuts::store_to_domain(&DefaultDomain, 1u32);
+/// ```
+pub fn set_domain_mirror<F>(f: F)
+where
+    F: Fn(DomainId, core::any::TypeId) + 'static,
+{
+    nut::set_domain_mirror(Box::new(f))
+}
+
+/// Puts the data object to the domain once the message currently being delivered has reached all subscribers.
+///
+/// Unlike [`store_to_domain`](fn.store_to_domain.html), this can be used inside activities,
+/// for example from a handler that only has shared access to the domain.
+/// The write is queued like a published message, subscribers of the current message still see the old value.
+/// Outside of activities, the data is stored immediately.
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// struct Level(u32);
+/// struct LevelUp;
+/// nuts::store_to_domain(&DefaultDomain, Level(1));
+/// let activity = nuts::new_domained_activity((), &DefaultDomain);
+/// activity.subscribe_domained(|_, domain, _: &LevelUp| {
+///     let next = domain.get::<Level>().0 + 1;
+///     nuts::defer_domain_write(&DefaultDomain, Level(next));
+/// });
+/// nuts::publish(LevelUp);
+/// ```
+pub fn defer_domain_write<D, T>(domain: &D, data: T)
+where
+    D: DomainEnumeration,
+    T: Any,
+{
+    nut::defer_domain_write(DomainId::new(domain), data)
+}
+
+/// Registers a callback to be run by [`shutdown`](fn.shutdown.html).
+///
+/// Callbacks with a higher priority run first, callbacks with the same priority run in the order they have been registered.
+/// The order is independent of activities and their creation order.
+///
+/// ### Example
+/// ```rust
+/// nuts::register_teardown(0, || println!("flush persistence"));
+/// nuts::register_teardown(10, || println!("close network connections"));
+/// nuts::shutdown();
+/// // Output:
+/// // close network connections
+/// // flush persistence
+/// ```
+pub fn register_teardown(priority: i32, f: impl FnOnce() + 'static) {
+    nut::register_teardown(priority, Box::new(f))
+}
+
+/// Shuts down all activities in a defined order.
+///
+/// First, the callbacks registered with [`register_teardown`](fn.register_teardown.html) are called in priority order.
+/// They can still publish messages, which are delivered as usual.
+/// Afterwards, all activities are deleted, as with [`unregister_by_predicate`](fn.unregister_by_predicate.html), and all domains are cleared.
+///
+/// Registered callbacks are only called once. Nuts can be used again after the shutdown.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+pub fn shutdown() {
+    nut::shutdown()
+}
+
+/// Removes a single subscription, other subscriptions of the same activity are not affected.
+///
+/// Removing a subscription that has already been removed has no effect.
+/// When called from inside an activity, the subscription is removed after the current message has been delivered to all subscribers.
+///
+/// ### Example
+/// ```rust
+/// struct Tick;
+/// let activity = nuts::new_activity(0u32);
+/// let stream = activity.subscribe(|count, _: &Tick| *count += 1);
+/// nuts::publish(Tick);
+/// nuts::unsubscribe(stream);
+/// nuts::unsubscribe(stream);
+/// nuts::publish(Tick);
+/// assert_eq!(Some(1), activity.read(|count| *count));
+/// ```
+pub fn unsubscribe(id: SubscriptionId) {
+    nut::unsubscribe(id)
+}
+
+/// Deletes all activities for which the predicate returns true and removes all their subscriptions.
+///
+/// Use it to tear down a group of activities in one consistent operation, for example all activities of a plugin before it is reloaded.
+/// Active activities have their `on_leave` subscriptions called before they are deleted, as with [`set_status`](struct.ActivityId.html#method.set_status).
+/// Other activities are not affected.
+///
+/// When called from inside an activity, the teardown is deferred until the current message has been delivered to all subscribers.
+///
+/// ### Example
+/// ```rust
+/// struct Ping;
+/// let plugin = nuts::new_activity(());
+/// plugin.subscribe(|_, _: &Ping| panic!("plugin has been unloaded"));
+/// let plugin_id: nuts::UncheckedActivityId = plugin.into();
+///
+/// nuts::unregister_by_predicate(move |id| id == plugin_id);
+/// nuts::publish(Ping);
+/// assert!(nuts::subscribed_types().is_empty());
+/// ```
+pub fn unregister_by_predicate<F>(predicate: F)
+where
+    F: Fn(UncheckedActivityId) -> bool + 'static,
+{
+    nut::unregister_by_predicate(Box::new(predicate))
+}
+
+/// Deletes all activities of a domain and removes all data stored in the domain.
+///
+/// Active activities have their `on_leave` subscriptions called before they are deleted, as with [`set_status`](struct.ActivityId.html#method.set_status).
+/// Activities of other domains and their data are not affected.
+///
+/// When called from inside an activity, the teardown is deferred until the current message has been delivered to all subscribers.
+///
+/// ### Example
+/// ```rust
+/// use nuts::{domain_enum, DomainEnumeration};
+/// #[derive(Clone, Copy)]
+/// enum MyDomain { Scene, Ui }
+/// domain_enum!(MyDomain);
+///
+/// struct Ping;
+/// let scene = nuts::new_domained_activity((), &MyDomain::Scene);
+/// scene.subscribe(|_, _: &Ping| panic!("deleted activities receive no messages"));
+/// nuts::store_to_domain(&MyDomain::Scene, 1u32);
+///
+/// nuts::clear_domain(&MyDomain::Scene);
+/// nuts::publish(Ping);
+/// ```
+pub fn clear_domain<D>(domain: &D)
+where
+    D: DomainEnumeration,
+{
+    nut::clear_domain(DomainId::new(domain))
+}
+
+/// Registers a type to be included in domain snapshots created by [`snapshot_domain`](fn.snapshot_domain.html).
+///
+/// The type must be `Clone`, because the snapshot stores a copy of the value.
+/// It must also be `Send` and `Sync`, such that snapshots can be shared with other threads.
+pub fn register_snapshot_type<T>()
+where
+    T: Any + Clone + Send + Sync,
+{
+    nut::register_snapshot_type::<T>()
+}
+
+/// Creates an immutable copy of the current content of a domain.
+///
+/// Only values of types that have been registered with [`register_snapshot_type`](fn.register_snapshot_type.html) are copied.
+/// The snapshot is not updated when the domain changes afterwards.
+///
+/// This function is only valid outside of activities.
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// nuts::register_snapshot_type::<usize>();
+/// nuts::store_to_domain(&DefaultDomain, 1usize);
+/// let snapshot = nuts::snapshot_domain(&DefaultDomain);
+/// nuts::store_to_domain(&DefaultDomain, 2usize);
+/// assert_eq!(Some(&1), snapshot.try_get::<usize>());
+/// ```
+pub fn snapshot_domain<D>(domain: &D) -> DomainSnapshot
+where
+    D: DomainEnumeration,
+{
+    nut::snapshot_domain(DomainId::new(domain))
+}
+
+/// Registers a type to be included in snapshots created by [`snapshot_domain_for_diff`](fn.snapshot_domain_for_diff.html).
+///
+/// The type must be `Clone` to be copied into the snapshot and `PartialEq` to detect modified values.
+pub fn register_diffable<T>()
+where
+    T: Any + Clone + PartialEq,
+{
+    nut::register_diffable::<T>()
+}
+
+/// Copies the current values of all diffable types in a domain, such that it can be compared to a later state.
+///
+/// Only values of types that have been registered with [`register_diffable`](fn.register_diffable.html) are copied.
+/// Use [`DomainDiffSnapshot::diff`](struct.DomainDiffSnapshot.html#method.diff) to list the changes between two snapshots,
+/// for example to track down which handler changed the state unexpectedly.
+///
+/// This function is only valid outside of activities.
+///
+/// ### Example
+/// ```rust
+/// use nuts::{Changed, DefaultDomain};
+/// nuts::register_diffable::<u32>();
+/// nuts::register_diffable::<String>();
+/// nuts::store_to_domain(&DefaultDomain, 1u32);
+/// let before = nuts::snapshot_domain_for_diff(&DefaultDomain);
+///
+/// nuts::store_to_domain(&DefaultDomain, 2u32);
+/// nuts::store_to_domain(&DefaultDomain, "new".to_owned());
+/// let after = nuts::snapshot_domain_for_diff(&DefaultDomain);
+///
+/// assert_eq!(
+///     vec![
+///         Changed::Added(std::any::type_name::<String>()),
+///         Changed::Modified(std::any::type_name::<u32>()),
+///     ],
+///     before.diff(&after)
+/// );
+/// ```
+pub fn snapshot_domain_for_diff<D>(domain: &D) -> DomainDiffSnapshot
+where
+    D: DomainEnumeration,
+{
+    nut::snapshot_domain_for_diff(DomainId::new(domain))
+}
+
+/// Starts recording which domain types are read and written by each activity.
+///
+/// Profiling adds some overhead to every domain access, it is meant for analysis, not for production builds.
+/// The results are available through [`domain_access_report`](fn.domain_access_report.html).
+pub fn enable_domain_profiling() {
+    nut::enable_domain_profiling()
+}
+
+/// Reports the domain types accessed by activities in their handlers since profiling has been enabled.
+///
+/// Activities that do not conflict in their accesses are candidates for running in parallel, once that is supported.
+/// Returns an empty report if [`enable_domain_profiling`](fn.enable_domain_profiling.html) has not been called.
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// struct Score(u32);
+/// struct Tick;
+/// nuts::enable_domain_profiling();
+/// nuts::store_to_domain(&DefaultDomain, Score(0));
+/// let writer = nuts::new_domained_activity((), &DefaultDomain);
+/// writer.subscribe_domained(|_, domain, _: &Tick| domain.get_mut::<Score>().0 += 1);
+/// let idle = nuts::new_domained_activity((), &DefaultDomain);
+/// idle.subscribe(|_, _: &Tick| {});
+/// nuts::publish(Tick);
+///
+/// let report = nuts::domain_access_report();
+/// let access = report.activity(writer).unwrap();
+/// assert_eq!(vec![std::any::type_name::<Score>()], access.writes().collect::<Vec<_>>());
+/// assert!(report.activity(idle).is_none());
+/// assert!(!report.conflicts(writer, idle));
+/// ```
+pub fn domain_access_report() -> AccessReport {
+    nut::domain_access_report()
+}
+
 /// Send the message to all subscribed activities
 ///
 // @ START-DOC PUBLISH
@@ -196,7 +768,1030 @@ where
 /// // Start of 3
 /// // End of 3
 /// ```
+///
+/// All queued messages wait in a single queue, regardless of their type.
+/// They are delivered in exactly the order in which they have been published, also across nested handlers.
+/// The only exceptions are messages that are held back on purpose, such as those of [paused types](fn.pause_type.html),
+/// [deferred](fn.publish_deferred.html) messages, and lifecycle events like `on_leave`, which are delivered immediately.
 // @ END-DOC PUBLISH_ADVANCED
+/// ### Domain Data Within a Cascade
+/// Domain data is never copied for a dispatch. Every handler borrows the domain when it is called and releases it when it returns.
+/// Thus, a domain write made by a handler is visible to all handlers of messages it publishes, in every later generation of the cascade.
 pub fn publish<A: Any>(a: A) {
     nut::publish_custom(a)
 }
+
+/// Publishes a message on a named topic, to disambiguate messages of the same type.
+///
+/// Only subscriptions registered with [`subscribe_on_topic`](struct.ActivityId.html#method.subscribe_on_topic) under the same name receive the message.
+/// Plain [`publish`](fn.publish.html) and [`subscribe`](struct.ActivityId.html#method.subscribe) use a default topic without a name, which never sees named messages.
+/// Subscriptions to [`subscribe_any`](struct.ActivityId.html#method.subscribe_any) observe messages of all topics.
+///
+/// Captures, aliases, and the message history only apply to the unnamed topic.
+///
+/// ### Example
+/// ```rust
+/// let dashboard = nuts::new_activity(Vec::<String>::new());
+/// dashboard.subscribe_on_topic("metrics", |lines, line: &String| lines.push(line.clone()));
+///
+/// nuts::publish_on_topic("metrics", "fps: 60".to_owned());
+/// nuts::publish_on_topic("chat", "hello".to_owned());
+/// nuts::publish("unnamed".to_owned());
+/// assert_eq!(Some(vec!["fps: 60".to_owned()]), dashboard.read(|lines| lines.clone()));
+/// ```
+pub fn publish_on_topic<A: Any>(name: &'static str, a: A) {
+    nut::publish_on_topic(name, a)
+}
+
+/// Binds nuts to the calling thread, to catch accidental use from other threads.
+///
+/// All state of nuts is thread-local.
+/// Publishing on another thread therefore operates on a separate and usually empty state, the message silently reaches nobody.
+/// After this call, any use of nuts from another thread panics with a message explaining this.
+///
+/// The check is only performed in debug builds and it applies to the whole process.
+/// The binding cannot be undone.
+///
+/// # Panics
+/// Panics if nuts has already been bound to another thread.
+///
+/// ### Example
+/// ```rust
+/// struct Render;
+/// nuts::bind_to_current_thread();
+/// let renderer = nuts::new_activity(());
+/// renderer.subscribe(|_, _: &Render| {});
+/// nuts::publish(Render);
+///
+/// let worker = std::thread::spawn(|| nuts::publish(Render));
+/// # #[cfg(debug_assertions)]
+/// assert!(worker.join().is_err());
+/// ```
+pub fn bind_to_current_thread() {
+    nut::thread_guard::bind_to_current_thread()
+}
+
+/// Publishes the message returned by `f`, but only calls `f` if someone could receive the message.
+///
+/// Use it for messages that are expensive to construct, e.g. large snapshots for a debug overlay that is usually inactive.
+/// Subscriptions are ignored if their activity would filter out the message, e.g. because it is inactive.
+/// Subscriptions with [`subscribe_any`](struct.ActivityId.html#method.subscribe_any), aliases, and captures count as receivers.
+///
+/// Once constructed, the message is published like with [`publish`](fn.publish.html).
+/// When called from inside an activity, it is queued.
+///
+/// ### Example
+/// ```rust
+/// struct Snapshot(Vec<u8>);
+/// let overlay = nuts::new_activity(0usize);
+/// overlay.subscribe(|bytes, snapshot: &Snapshot| *bytes = snapshot.0.len());
+/// overlay.set_status(nuts::LifecycleStatus::Inactive);
+///
+/// nuts::publish_with(|| -> Snapshot { unreachable!("nobody is listening") });
+///
+/// overlay.set_status(nuts::LifecycleStatus::Active);
+/// nuts::publish_with(|| Snapshot(vec![0; 1024]));
+/// assert_eq!(Some(1024), overlay.read(|bytes| *bytes));
+/// ```
+pub fn publish_with<MSG: Any>(f: impl FnOnce() -> MSG) {
+    nut::publish_with(f)
+}
+
+/// Publishes several messages of the same type, in order.
+///
+/// The result is the same as calling [`publish`](fn.publish.html) for each message,
+/// including that the messages published by subscribers of one message are delivered before the next message.
+/// Outside of activities, the overhead of starting a delivery is paid once per batch instead of once per message.
+/// Inside activities, the messages are queued.
+///
+/// ### Example
+/// ```rust
+/// struct Particle(u32);
+/// let simulation = nuts::new_activity(0u32);
+/// simulation.subscribe(|sum, particle: &Particle| *sum += particle.0);
+///
+/// nuts::publish_batch((1..=4).map(Particle).collect());
+/// assert_eq!(Some(10), simulation.read(|sum| *sum));
+/// ```
+pub fn publish_batch<MSG: Any>(msgs: Vec<MSG>) {
+    nut::publish_batch(msgs)
+}
+
+/// Returns the generation of the message that is currently delivered.
+///
+/// Messages published from outside of activities are generation 0.
+/// A message published by a handler of a generation `n` message is of generation `n + 1`.
+/// Outside of handlers, 0 is returned.
+///
+/// A growing generation number indicates a long cascade of messages, which may never end.
+///
+/// ### Example
+/// ```rust
+/// struct Countdown(u32);
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, msg: &Countdown| {
+///     assert_eq!(3 - msg.0 as usize, nuts::cascade_generation());
+///     if msg.0 > 0 {
+///         nuts::publish(Countdown(msg.0 - 1));
+///     }
+/// });
+/// nuts::publish(Countdown(3));
+/// ```
+pub fn cascade_generation() -> usize {
+    nut::cascade_generation()
+}
+
+/// Keeps the last `depth` published messages of type `MSG`, to replay them to subscriptions made with [`subscribe_with_history`](struct.ActivityId.html#method.subscribe_with_history).
+///
+/// Only messages published after this call are recorded.
+/// Calling it again changes the depth, dropping the oldest messages if necessary.
+/// A depth of 0 disables the history and drops all recorded messages.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+pub fn set_history<MSG: Any + Clone>(depth: usize) {
+    nut::set_history::<MSG>(depth)
+}
+
+/// Publishes a borrowed message, without moving or cloning it.
+///
+/// The message is delivered synchronously, all subscribers are called before this function returns.
+/// Because the borrow cannot be queued, calling it from inside an activity is rejected with a panic.
+/// Events queued earlier, for example with [`enqueue`](fn.enqueue.html), are delivered first.
+/// Messages published by the subscribers are queued as usual and are also delivered before this function returns.
+///
+/// Only subscriptions made with [`subscribe`](struct.ActivityId.html#method.subscribe) and [`subscribe_domained`](struct.ActivityId.html#method.subscribe_domained) receive the message.
+/// Subscriptions that need mutable access or ownership of the message are skipped.
+/// Strict mode and the dead letter handler apply as for `publish`.
+///
+/// Some features need to keep the message and therefore ignore borrowed messages:
+///  - Message captures and awaited responses do not intercept it.
+///  - Aliases do not convert it.
+///  - The history of the type does not record it.
+///
+/// # Panics
+/// Panics if called from inside an activity, or if the message type is paused with [`pause_type`](fn.pause_type.html).
+/// In strict mode, panics if nobody subscribed to the message type.
+///
+/// ### Example
+/// ```rust
+/// struct Frame { pixels: Vec<u8> }
+/// let activity = nuts::new_activity(0usize);
+/// activity.subscribe(|bytes, frame: &Frame| *bytes += frame.pixels.len());
+///
+/// let frame = Frame { pixels: vec![0; 1024] };
+/// nuts::publish_ref(&frame);
+/// assert_eq!(Some(1024), activity.read(|bytes| *bytes));
+/// ```
+pub fn publish_ref<MSG: Any>(msg: &MSG) {
+    nut::publish_ref(msg)
+}
+
+/// Publishes a message only to the subscriptions of a single activity, all other subscribers are ignored.
+///
+/// The subscription filters apply as usual, an inactive activity does not receive the message.
+/// If the activity has no subscription for the message type, the message is silently dropped.
+/// Like with [`publish`](fn.publish.html), the message is queued when called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct Focus;
+/// let first = nuts::new_activity(0u32);
+/// let second = nuts::new_activity(0u32);
+/// first.subscribe(|count, _: &Focus| *count += 1);
+/// second.subscribe(|count, _: &Focus| *count += 1);
+///
+/// nuts::publish_to(second, Focus);
+/// assert_eq!(Some(0), first.read(|count| *count));
+/// assert_eq!(Some(1), second.read(|count| *count));
+/// ```
+pub fn publish_to<A: Activity, MSG: Any>(id: ActivityId<A>, msg: MSG) {
+    nut::publish_to(id.into(), msg)
+}
+
+/// Same as [`publish_to`](fn.publish_to.html), but delivers the message even if the activity is currently not active.
+///
+/// The lifecycle status of the activity is not changed.
+/// Nothing happens if the activity has been deleted.
+///
+/// ### Example
+/// ```rust
+/// struct Urgent;
+/// let activity = nuts::new_activity(0u32);
+/// activity.subscribe(|count, _: &Urgent| *count += 1);
+/// activity.set_status(nuts::LifecycleStatus::Inactive);
+///
+/// nuts::publish(Urgent);
+/// nuts::publish_to_including_inactive(activity, Urgent);
+/// assert_eq!(Some(1), activity.read(|count| *count));
+/// ```
+pub fn publish_to_including_inactive<A: Activity, MSG: Any>(id: ActivityId<A>, msg: MSG) {
+    nut::publish_to_including_inactive(id.into(), msg)
+}
+
+/// Makes `parent` the parent domain of `child`, forming a hierarchy of domains.
+///
+/// The hierarchy is used by [`publish_to_domain`](fn.publish_to_domain.html) with [`DomainReach::IncludeAncestors`](enum.DomainReach.html).
+/// Setting the parent again replaces the previous parent.
+///
+/// # Panics
+/// Panics if `parent` is `child` or one of its descendants.
+pub fn set_domain_parent<D: DomainEnumeration>(child: &D, parent: &D) {
+    nut::set_domain_parent(DomainId::new(child), DomainId::new(parent))
+}
+
+/// Publishes a message only to activities in the given domain and, depending on `reach`, its ancestor domains.
+///
+/// Like with [`publish`](fn.publish.html), the message is queued when called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// #[macro_use] extern crate nuts;
+/// use nuts::{domain_enum, DomainEnumeration, DomainReach};
+/// #[derive(Clone, Copy)]
+/// enum Scope { App, Window }
+/// domain_enum!(Scope);
+/// struct Close;
+///
+/// nuts::set_domain_parent(&Scope::Window, &Scope::App);
+/// let app = nuts::new_domained_activity(0u32, &Scope::App);
+/// let window = nuts::new_domained_activity(0u32, &Scope::Window);
+/// app.subscribe(|count, _: &Close| *count += 1);
+/// window.subscribe(|count, _: &Close| *count += 1);
+///
+/// nuts::publish_to_domain(&Scope::Window, Close, DomainReach::SelfOnly);
+/// assert_eq!(Some(0), app.read(|count| *count));
+/// nuts::publish_to_domain(&Scope::Window, Close, DomainReach::IncludeAncestors);
+/// assert_eq!(Some(1), app.read(|count| *count));
+/// assert_eq!(Some(2), window.read(|count| *count));
+/// ```
+pub fn publish_to_domain<D: DomainEnumeration, MSG: Any>(domain: &D, msg: MSG, reach: DomainReach) {
+    nut::publish_to_domain(DomainId::new(domain), msg, reach)
+}
+
+/// Creates a direct link from one activity to another, for messages of type `MSG`.
+///
+/// Messages sent with [`send_pipe`](fn.send_pipe.html) are delivered only to the handler `f` of activity `to`.
+/// Normal subscriptions to `MSG` never see them, and publishing `MSG` does not reach the pipe.
+/// This avoids the fan-out over all subscribers of a type for hot point-to-point paths.
+///
+/// Otherwise, the handler behaves like a subscription made with [`subscribe`](struct.ActivityId.html#method.subscribe), it is only called while `to` is active.
+///
+/// ### Example
+/// ```rust
+/// struct Producer;
+/// struct Consumer { received: Vec<u32> }
+/// struct Tick;
+/// let producer = nuts::new_activity(Producer);
+/// let consumer = nuts::new_activity(Consumer { received: vec![] });
+/// let pipe = nuts::pipe(producer.into(), consumer, |consumer, sample: &u32| {
+///     consumer.received.push(*sample)
+/// });
+/// producer.subscribe(move |_, _: &Tick| nuts::send_pipe(pipe, 42u32));
+/// nuts::publish(Tick);
+/// ```
+pub fn pipe<MSG, B, F>(from: UncheckedActivityId, to: ActivityId<B>, f: F) -> PipeId<MSG>
+where
+    MSG: Any,
+    B: Activity,
+    F: Fn(&mut B, &MSG) + 'static,
+{
+    nut::pipe(from, to, f)
+}
+
+/// Sends a message through a pipe created with [`pipe`](fn.pipe.html).
+///
+/// Like with [`publish`](fn.publish.html), the message is queued when called from inside an activity.
+///
+/// # Panics
+/// Panics if called from inside an activity other than the sending activity of the pipe.
+pub fn send_pipe<MSG: Any>(pipe: PipeId<MSG>, msg: MSG) {
+    nut::send_pipe(pipe, msg)
+}
+
+/// Registers a message type under a name, to subscribe to it with [`subscribe_by_name`](fn.subscribe_by_name.html).
+///
+/// Registering the same type again under the same name has no effect.
+///
+/// # Panics
+/// Panics if the name is already registered for another type.
+pub fn register_message_type<MSG: Any>(name: &str) {
+    nut::register_message_type::<MSG>(name)
+}
+
+/// Subscribes a handler to the message type registered under `name`, to wire subscriptions from data loaded at runtime.
+///
+/// The handler receives the activity and the message as `dyn Any`.
+/// Otherwise, it behaves like a subscription made with [`subscribe`](struct.ActivityId.html#method.subscribe), it is only called while the activity is active.
+///
+/// # Panics
+/// Panics if no message type has been registered with the name.
+/// Like all subscriptions, it panics when called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct Reload;
+/// nuts::register_message_type::<Reload>("reload");
+///
+/// let activity = nuts::new_activity(0u32);
+/// // e.g. read from a configuration file
+/// let route = "reload";
+/// nuts::subscribe_by_name(
+///     activity.into(),
+///     route,
+///     Box::new(|activity, _msg| *activity.downcast_mut::<u32>().unwrap() += 1),
+/// );
+/// nuts::publish(Reload);
+/// ```
+pub fn subscribe_by_name(
+    activity: UncheckedActivityId,
+    name: &str,
+    handler: BoxedHandler,
+) -> SubscriptionId {
+    nut::subscribe_by_name(activity, name, handler)
+}
+
+/// Starts a sequence of messages with different types, to be published in order.
+///
+/// ### Example
+/// ```rust
+/// struct Load;
+/// struct Render;
+/// let activity = nuts::new_activity(Vec::<&str>::new());
+/// activity.subscribe(|log, _: &Load| {
+///     log.push("load");
+///     nuts::publish(1u32);
+/// });
+/// activity.subscribe(|log, _: &u32| log.push("loaded"));
+/// activity.subscribe(|log, _: &Render| log.push("render"));
+///
+/// // The cascade of `Load` is processed before `Render` is published
+/// nuts::sequence().then(Load).then(Render).run();
+/// activity.subscribe(|log, _: &()| assert_eq!(*log, ["load", "loaded", "render"]));
+/// nuts::publish(());
+/// ```
+pub fn sequence() -> Sequence {
+    Sequence::default()
+}
+
+/// Queues a message without delivering it right away, to be processed later with [`process_deferred_limited`](fn.process_deferred_limited.html).
+///
+/// Queued messages are delivered at the latest with the next call to `publish`, which always empties the queue.
+/// Inside activities, this is the same as `publish`.
+pub fn enqueue<A: Any>(a: A) {
+    nut::enqueue(a)
+}
+
+/// Queues a message and returns a future that resolves once the message and all messages published in response have been delivered.
+///
+/// Polling the future outside of activities processes all queued events right away and resolves immediately.
+/// If it is polled from inside an activity, it resolves once the ongoing broadcast and the queue have been processed, after which the waker is woken.
+/// This allows async code to `.await` until nuts has settled.
+///
+/// Like [`enqueue`](fn.enqueue.html), the message is not delivered before the future is polled,
+/// unless another call to `publish` processes the queue first.
+///
+/// ### Example
+/// ```rust
+/// # fn block_on(mut f: impl std::future::Future<Output = ()> + Unpin) {
+/// #     use std::task::{Context, Poll, Wake, Waker};
+/// #     struct NoopWaker;
+/// #     impl Wake for NoopWaker { fn wake(self: std::sync::Arc<Self>) {} }
+/// #     let waker = Waker::from(std::sync::Arc::new(NoopWaker));
+/// #     while std::pin::Pin::new(&mut f).poll(&mut Context::from_waker(&waker)).is_pending() {}
+/// # }
+/// struct Save;
+/// struct Saved;
+/// let activity = nuts::new_activity(false);
+/// activity.subscribe(|_, _: &Save| nuts::publish(Saved));
+/// activity.subscribe(|saved, _: &Saved| *saved = true);
+///
+/// let settled = nuts::publish_async(Save);
+/// assert_eq!(Some(false), activity.read(|saved| *saved));
+/// block_on(settled);
+/// assert_eq!(Some(true), activity.read(|saved| *saved));
+/// ```
+pub fn publish_async<MSG: Any>(msg: MSG) -> impl core::future::Future<Output = ()> + Unpin {
+    nut::enqueue(msg);
+    nut::iac::publish::Settled
+}
+
+/// Processes at most `max` queued events and returns how many have been processed.
+///
+/// Every event taken from the queue counts towards the limit.
+/// Messages published by handlers are appended to the queue, hence they count on their own and may be processed in a later call.
+/// Lifecycle changes and deferred domain writes are queued events, too.
+///
+/// Use this with [`enqueue`](fn.enqueue.html) to spread the delivery of messages over multiple frames.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct Job(u32);
+/// let worker = nuts::new_activity(());
+/// worker.subscribe(|_, job: &Job| println!("Processing job {}", job.0));
+/// for i in 0..5 {
+///     nuts::enqueue(Job(i));
+/// }
+/// // Once per frame
+/// assert_eq!(2, nuts::process_deferred_limited(2));
+/// assert_eq!(2, nuts::process_deferred_limited(2));
+/// assert_eq!(1, nuts::process_deferred_limited(2));
+/// ```
+pub fn process_deferred_limited(max: usize) -> usize {
+    nut::process_deferred_limited(max)
+}
+
+/// Holds back a message until the next call to [`run_deferred`](fn.run_deferred.html).
+///
+/// Unlike [`enqueue`](fn.enqueue.html), the message is not delivered by the next `publish`.
+/// It stays in a separate queue that is only emptied by `run_deferred`, which a game loop would call once per frame.
+/// This also applies when called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct Spawn;
+/// let world = nuts::new_activity(0u32);
+/// world.subscribe(|spawned, _: &Spawn| *spawned += 1);
+///
+/// nuts::publish_deferred(Spawn);
+/// nuts::publish(());
+/// assert_eq!(Some(0), world.read(|spawned| *spawned));
+///
+/// // In the next frame
+/// assert_eq!(1, nuts::run_deferred());
+/// assert_eq!(Some(1), world.read(|spawned| *spawned));
+/// ```
+pub fn publish_deferred<A: Any>(a: A) {
+    nut::publish_deferred(a)
+}
+
+/// Publishes all messages held back with [`publish_deferred`](fn.publish_deferred.html), in the order they were held back.
+///
+/// Each message is delivered like with [`publish`](fn.publish.html), including all messages published by its subscribers.
+/// Messages held back with `publish_deferred` during this call wait for the next call, such that a handler can reschedule itself once per tick.
+/// Returns the number of messages that have been published.
+pub fn run_deferred() -> usize {
+    nut::run_deferred()
+}
+
+/// Same as [`publish`](fn.publish.html) but the size of the message is limited to `MAX` bytes at compile-time.
+///
+/// This can be used to keep large messages off the stack during the synchronous delivery.
+///
+/// ### Example
+/// ```rust
+/// struct Small(u32);
+/// nuts::publish_bounded::<16, _>(Small(0));
+/// ```
+/// A message that is too large is rejected by the compiler.
+/// ```compile_fail
+/// struct Large([u8; 1024]);
+/// nuts::publish_bounded::<16, _>(Large([0; 1024]));
+/// ```
+pub fn publish_bounded<const MAX: usize, MSG: Any>(msg: MSG) {
+    const {
+        assert!(
+            core::mem::size_of::<MSG>() <= MAX,
+            "Message exceeds the maximum size"
+        )
+    };
+    nut::publish_custom(msg)
+}
+
+/// Pauses the delivery of messages of type `MSG`.
+///
+/// Messages of that type published while paused are held back until [`resume_type`](fn.resume_type.html) is called.
+/// Other message types are delivered as usual.
+/// Pausing an already paused type has no effect.
+///
+/// ### Example
+/// ```rust
+/// struct ModelChanged(u32);
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, msg: &ModelChanged| assert_eq!(msg.0, 3));
+///
+/// nuts::pause_type::<ModelChanged>();
+/// for i in 1..=3 {
+///     nuts::publish(ModelChanged(i));
+/// }
+/// // Only `ModelChanged(3)` is delivered
+/// nuts::resume_type::<ModelChanged>(true);
+/// ```
+pub fn pause_type<MSG: Any>() {
+    nut::pause_topic(Topic::message::<MSG>())
+}
+
+/// Resumes the delivery of messages of type `MSG` after a call to [`pause_type`](fn.pause_type.html).
+///
+/// The messages held back in the meantime are delivered in the order they have been published.
+/// If `coalesce` is true, only the last of them is delivered and the others are dropped.
+pub fn resume_type<MSG: Any>(coalesce: bool) {
+    nut::resume_topic(Topic::message::<MSG>(), coalesce)
+}
+
+/// Same as [`publish`](fn.publish.html) but returns an error instead of panicking when the state of Nuts is not accessible.
+///
+/// Use this when publishing from places that may run while the thread is shutting down, such as `Drop` implementations.
+///
+/// # Errors
+/// Returns [`NutsError::StorageUnavailable`](enum.NutsError.html#variant.StorageUnavailable) if the thread-local storage cannot be accessed.
+pub fn try_publish<A: Any>(a: A) -> Result<(), NutsError> {
+    nut::try_publish_custom(a)
+}
+
+/// Same as [`publish`](fn.publish.html) but fails if nobody subscribed to the message type.
+///
+/// Unlike [strict mode](fn.set_strict_unhandled.html), this applies only to types that implement [`RequiredMessage`](trait.RequiredMessage.html),
+/// and the caller is forced to deal with the missing subscriber.
+/// On success, the number of subscriptions to the message type is returned, including subscriptions of inactive activities.
+/// Aliases defined with [`alias_message_with`](fn.alias_message_with.html) do not count as subscriptions.
+///
+/// # Errors
+/// Returns [`NutsError::Unhandled`](enum.NutsError.html#variant.Unhandled) and drops the message if no activity has subscribed to its type.
+///
+/// ### Example
+/// ```rust
+/// struct Shutdown;
+/// impl nuts::RequiredMessage for Shutdown {}
+///
+/// assert_eq!(Err(nuts::NutsError::Unhandled), nuts::publish_required(Shutdown));
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Shutdown| {});
+/// assert_eq!(Ok(1), nuts::publish_required(Shutdown));
+/// ```
+pub fn publish_required<MSG: RequiredMessage>(msg: MSG) -> Result<usize, NutsError> {
+    nut::publish_required(msg)
+}
+
+/// Same as [`publish`](fn.publish.html) but returns the errors of all subscriptions created with [`subscribe_checked`](struct.ActivityId.html#method.subscribe_checked).
+///
+/// A failing subscriber does not stop the delivery, all remaining subscribers are still called.
+/// Errors are collected for the whole cascade of the message, including messages published by the subscribers.
+/// Only errors of type `E` are returned, other errors are dropped.
+/// Ordinary subscribers never contribute errors.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct Input(&'static str);
+/// let parser = nuts::new_activity(Vec::new());
+/// parser.subscribe_checked(|numbers, input: &Input| {
+///     numbers.push(input.0.parse::<u32>()?);
+///     Ok::<(), std::num::ParseIntError>(())
+/// });
+/// let logger = nuts::new_activity(0u32);
+/// logger.subscribe(|inputs, _: &Input| *inputs += 1);
+///
+/// assert!(nuts::publish_checked::<_, std::num::ParseIntError>(Input("12")).is_empty());
+/// let errors = nuts::publish_checked::<_, std::num::ParseIntError>(Input("twelve"));
+/// assert_eq!(1, errors.len());
+/// assert_eq!(Some(2), logger.read(|inputs| *inputs));
+/// ```
+pub fn publish_checked<MSG: Any, E: Any>(msg: MSG) -> Vec<E> {
+    nut::publish_checked(msg)
+}
+
+/// Same as [`publish`](fn.publish.html) but returns the number of subscribers that have actually been called.
+///
+/// Subscribers that filtered out the message, e.g. because their activity is inactive, are not counted.
+/// Subscribers reached through an [alias](fn.alias_message_with.html) are counted, those of messages published by the subscribers are not.
+/// A message that is held back, like one of a [paused type](fn.pause_type.html), counts as zero.
+/// Events that have been queued earlier are delivered first and are not counted.
+///
+/// # Panics
+/// Panics if called from inside an activity, since the message would only be queued and the count is not known yet.
+///
+/// ### Example
+/// ```rust
+/// struct Click;
+/// let button = nuts::new_activity(());
+/// button.subscribe(|_, _: &Click| {});
+/// let hidden = nuts::new_activity(());
+/// hidden.subscribe(|_, _: &Click| {});
+///
+/// assert_eq!(2, nuts::publish_counted(Click));
+/// hidden.set_status(nuts::LifecycleStatus::Inactive);
+/// assert_eq!(1, nuts::publish_counted(Click));
+/// ```
+pub fn publish_counted<MSG: Any>(msg: MSG) -> usize {
+    nut::publish_counted(msg)
+}
+
+/// Defines an alias from one message type to another, using a conversion function.
+///
+/// Every time a message of type `OLD` is published afterwards, it is converted to `NEW` and published again.
+/// The converted message is delivered after the original and before any other message that has been published in the meantime.
+/// Subscribers of both types are thus reached by publishing `OLD`.
+///
+/// This is useful to smooth migrations when renaming a message type.
+/// Aliases are not transitive, an alias defined for `NEW` is not applied to messages converted from `OLD`.
+///
+/// ### Example
+/// ```rust
+/// struct OldMsg(u32);
+/// struct NewMsg(u64);
+/// nuts::alias_message_with(|old: &OldMsg| NewMsg(old.0 as u64));
+///
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, msg: &NewMsg| assert_eq!(msg.0, 7));
+/// // Also reaches the subscriber of `NewMsg`
+/// nuts::publish(OldMsg(7));
+/// ```
+pub fn alias_message_with<OLD, NEW, F>(f: F)
+where
+    OLD: Any,
+    NEW: Any,
+    F: Fn(&OLD) -> NEW + 'static,
+{
+    nut::alias_message(f)
+}
+
+/// Checks the registered activities for structural problems, such as an activity in a domain that contains no data.
+///
+/// Call it after the initial setup, to detect misconfigurations before the first message is published.
+///
+/// # Errors
+/// Returns all issues found, see [`WiringIssue`](enum.WiringIssue.html).
+///
+/// ### Example
+/// ```rust
+/// use nuts::DefaultDomain;
+/// nuts::new_domained_activity((), &DefaultDomain);
+/// assert!(nuts::validate_wiring().is_err());
+/// nuts::store_to_domain(&DefaultDomain, 7u32);
+/// assert!(nuts::validate_wiring().is_ok());
+/// ```
+pub fn validate_wiring() -> Result<(), Vec<WiringIssue>> {
+    nut::validate_wiring()
+}
+
+/// Finds cycles in the publish relationships declared with [`subscribe_publishing`](struct.ActivityId.html#method.subscribe_publishing).
+///
+/// Each cycle is a list of message type names, where a handler for each type publishes the next one and the last one leads back to the first.
+/// Such a cycle can cause an endless cascade of messages.
+/// Subscriptions without a declaration are not considered.
+///
+/// ### Example
+/// ```rust
+/// struct Ping;
+/// struct Pong;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe_publishing::<Ping, Pong>(|_, _| nuts::publish(Pong));
+/// activity.subscribe_publishing::<Pong, Ping>(|_, _| nuts::publish(Ping));
+/// assert_eq!(
+///     vec![vec![std::any::type_name::<Ping>(), std::any::type_name::<Pong>()]],
+///     nuts::detect_cycles()
+/// );
+/// ```
+pub fn detect_cycles() -> Vec<Vec<&'static str>> {
+    nut::detect_cycles()
+}
+
+/// Creates a snapshot of the ids, type names, statuses, and subscriptions of all activities.
+///
+/// The snapshot contains no closures or activity data, it can be sent to another thread, for example to show it in a debugging GUI.
+///
+/// ### Example
+/// ```rust
+/// struct Player;
+/// let player = nuts::new_activity(Player);
+/// player.subscribe(|_, _: &u32| {});
+///
+/// let metadata = nuts::export_metadata();
+/// std::thread::spawn(move || {
+///     let info = &metadata.activities()[0];
+///     assert_eq!(std::any::type_name::<Player>(), info.type_name());
+///     assert_eq!(&["u32"], info.subscriptions());
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub fn export_metadata() -> ActivityMetadata {
+    nut::export_metadata()
+}
+
+/// Lists the names of all message types that have at least one subscriber, sorted alphabetically.
+///
+/// Lifecycle subscriptions, such as `on_enter`, are not included.
+/// Use it to document or inspect which messages an application listens to.
+///
+/// ### Example
+/// ```rust
+/// struct Click;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Click| {});
+/// assert_eq!(vec![std::any::type_name::<Click>()], nuts::subscribed_types());
+/// ```
+pub fn subscribed_types() -> Vec<&'static str> {
+    nut::subscribed_types()
+}
+
+/// Returns the number of subscriptions to a message type.
+///
+/// Subscriptions of inactive activities are included, named topics (see [`publish_on_topic`](fn.publish_on_topic.html)) are not.
+/// Can be called from inside handlers.
+///
+/// ### Example
+/// ```rust
+/// struct Click;
+/// assert_eq!(0, nuts::subscriber_count::<Click>());
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Click| {});
+/// activity.subscribe(|_, _: &Click| {});
+/// assert_eq!(2, nuts::subscriber_count::<Click>());
+/// ```
+pub fn subscriber_count<MSG: Any>() -> usize {
+    nut::subscriber_count::<MSG>()
+}
+
+/// Returns true if at least one activity has subscribed to the message type.
+///
+/// Use it to skip constructing an expensive message that nobody listens to.
+/// Like [`subscriber_count`](fn.subscriber_count.html), subscriptions of inactive activities are included.
+///
+/// ### Example
+/// ```rust
+/// struct Report(String);
+/// if nuts::has_subscribers::<Report>() {
+///     nuts::publish(Report(format!("{:?}", std::env::args())));
+/// }
+/// ```
+pub fn has_subscribers<MSG: Any>() -> bool {
+    subscriber_count::<MSG>() > 0
+}
+
+/// Returns the number of activities that have not been deleted, regardless of their status.
+///
+/// An activity deleted from inside a handler is counted until the deletion has been applied, after the handler returned.
+///
+/// ### Example
+/// ```rust
+/// let before = nuts::activity_count();
+/// let activity = nuts::new_activity(());
+/// assert_eq!(before + 1, nuts::activity_count());
+/// activity.set_status(nuts::LifecycleStatus::Deleted);
+/// assert_eq!(before, nuts::activity_count());
+/// ```
+pub fn activity_count() -> usize {
+    nut::activity_count()
+}
+
+/// Registers a handler that receives all published messages of types that nobody has subscribed to.
+///
+/// The handler is called with the name of the message type and the message itself, which can be downcast to its concrete type.
+/// Messages that are converted by an alias (see [`alias_message_with`](fn.alias_message_with.html)) are not considered unhandled.
+/// Subscribers that filter out the message, e.g. because they are inactive, still count as subscribers.
+///
+/// Setting a handler again replaces the previous one.
+///
+/// ### Example
+/// ```rust
+/// struct Forgotten(u32);
+/// nuts::set_dead_letter(|type_name, msg| {
+///     if let Some(forgotten) = msg.downcast_ref::<Forgotten>() {
+///         println!("Nobody handled {} with value {}", type_name, forgotten.0);
+///     }
+/// });
+/// nuts::publish(Forgotten(42));
+/// ```
+pub fn set_dead_letter<F>(f: F)
+where
+    F: Fn(&'static str, &dyn Any) + 'static,
+{
+    nut::set_dead_letter(Box::new(f))
+}
+
+/// Lists all subscriptions that have not been called so far, together with the name of the subscribed message type.
+///
+/// Calls that have been filtered out, e.g. because the activity was inactive, do not count as a call.
+/// Subscriptions to lifecycle changes are listed with the names `on_enter` and `on_leave`.
+///
+/// The list is sorted by activity.
+/// Use it to find subscriptions that are never triggered, which may indicate dead code.
+pub fn unfired_subscriptions() -> Vec<(UncheckedActivityId, &'static str)> {
+    nut::unfired_subscriptions()
+}
+
+/// Publishes a command and returns the first response of type `RESP` published while the command is processed.
+///
+/// All messages published as a consequence of the command are delivered before this function returns.
+/// The first message of type `RESP` is taken out of the delivery and returned, later messages of that type are delivered as usual.
+/// Returns `None` if no response has been published.
+///
+/// # Panics
+/// Panics if called from inside an activity, or if `CMD` and `RESP` are the same type.
+///
+/// ### Example
+/// ```rust
+/// struct Sum(u32, u32);
+/// struct SumResult(u32);
+/// let calculator = nuts::new_activity(());
+/// calculator.subscribe(|_, cmd: &Sum| nuts::publish(SumResult(cmd.0 + cmd.1)));
+///
+/// let result = nuts::publish_await_response::<_, SumResult>(Sum(2, 3));
+/// assert_eq!(Some(5), result.map(|r| r.0));
+/// ```
+pub fn publish_await_response<CMD: Any, RESP: Any>(cmd: CMD) -> Option<RESP> {
+    nut::publish_await_response(cmd)
+}
+
+/// Publishes a message to all subscriptions created with [`subscribe_with_response`](struct.ActivityId.html#method.subscribe_with_response) and returns their responses.
+///
+/// The message is not cloned, every subscriber borrows the same message.
+/// The responses are in the order in which the subscribers are called.
+/// Inactive activities are skipped and do not contribute a response.
+/// This is a [`publish_fold`](fn.publish_fold.html) with a `Vec<R>` as accumulator, the same rules apply.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct WhoIsThere;
+/// let alice = nuts::new_activity("Alice");
+/// let bob = nuts::new_activity("Bob");
+/// alice.subscribe_with_response(|name, _: &WhoIsThere| *name);
+/// bob.subscribe_with_response(|name, _: &WhoIsThere| *name);
+/// assert_eq!(vec!["Alice", "Bob"], nuts::publish_and_collect::<_, &str>(WhoIsThere));
+/// ```
+pub fn publish_and_collect<MSG: Any, R: Any>(msg: MSG) -> Vec<R> {
+    nut::publish_fold(msg, Vec::new())
+}
+
+/// Publishes a message to all subscriptions created with [`subscribe_fold`](struct.ActivityId.html#method.subscribe_fold) and returns the final accumulator.
+///
+/// Each subscriber receives the message and the accumulator as modified by the previous subscribers.
+/// Subscribers are called in their usual order, the initial accumulator is `acc`.
+/// The message is delivered immediately and messages published by the subscribers are delivered before this function returns.
+///
+/// # Panics
+/// Panics if called from inside an activity.
+///
+/// ### Example
+/// ```rust
+/// struct CountVotes;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe_fold(|_, _: &CountVotes, votes: &mut u32| *votes += 1);
+/// activity.subscribe_fold(|_, _: &CountVotes, votes: &mut u32| *votes += 2);
+/// assert_eq!(3, nuts::publish_fold(CountVotes, 0u32));
+/// ```
+pub fn publish_fold<MSG: Any, ACC: Any>(msg: MSG, acc: ACC) -> ACC {
+    nut::publish_fold(msg, acc)
+}
+
+/// Same as [`publish`](fn.publish.html) but the message is also recorded with its `Debug` representation while a trace is active.
+///
+/// See [`trace_publish`](fn.trace_publish.html) and [`DeliveryTrace::messages`](struct.DeliveryTrace.html#method.messages).
+///
+/// ### Example
+/// ```rust
+/// #[derive(Debug)]
+/// struct Moved { x: i32 }
+/// struct Tick;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Tick| nuts::publish_dbg(Moved { x: 3 }));
+///
+/// let trace = nuts::trace_publish(Tick);
+/// assert_eq!(["Moved { x: 3 }"], trace.messages());
+/// ```
+pub fn publish_dbg<MSG: Any + core::fmt::Debug>(msg: MSG) {
+    nut::publish_dbg(msg)
+}
+
+/// Returns the current subscriptions to messages of type `MSG` as a set that can be invoked manually.
+///
+/// This is meant for custom execution strategies built on top of Nuts, for example a scheduler that decides when to deliver a message.
+/// The subscriptions also stay registered for normal delivery with `publish`.
+///
+/// ### Example
+/// ```rust
+/// struct Tick;
+/// let activity = nuts::new_activity(0u32);
+/// activity.subscribe(|ticks, _: &Tick| *ticks += 1);
+///
+/// let dispatch = nuts::take_dispatch::<Tick>();
+/// assert_eq!(1, dispatch.len());
+/// dispatch.dispatch(Tick);
+/// assert_eq!(Some(1), activity.read(|ticks| *ticks));
+/// ```
+pub fn take_dispatch<MSG: Any>() -> DispatchSet<MSG> {
+    nut::take_dispatch()
+}
+
+/// Publishes a message and records every resulting call of a subscription handler.
+///
+/// The trace includes the deliveries of all messages published in cascade, i.e. by handlers of the message and their successors.
+/// Deliveries are listed in the order they happened.
+///
+/// # Panics
+/// Panics if called from inside an activity, since the full cascade can only be observed at the top level.
+///
+/// ### Example
+/// ```rust
+/// struct Ping;
+/// struct Pong;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Ping| nuts::publish(Pong));
+/// activity.subscribe(|_, _: &Pong| {});
+///
+/// let trace = nuts::trace_publish(Ping);
+/// assert_eq!(2, trace.deliveries().len());
+/// ```
+pub fn trace_publish<A: Any>(a: A) -> DeliveryTrace {
+    nut::trace_publish(a)
+}
+
+/// Runs the closure while intercepting all published messages, intended for testing.
+///
+/// All messages published at the top level, i.e. not from inside a subscription handler, are recorded in the capture instead of being delivered.
+/// The capture is passed to the closure, inspect it there after running the code under test.
+/// After the closure returns, messages are delivered as usual again.
+///
+/// ### Example
+/// ```rust
+/// struct Clicked;
+/// let activity = nuts::new_activity(());
+/// activity.subscribe(|_, _: &Clicked| panic!("not called while capturing"));
+///
+/// nuts::capture(|capture| {
+///     nuts::publish(Clicked);
+///     assert_eq!(1, capture.len());
+///     assert!(capture.take()[0].downcast_ref::<Clicked>().is_some());
+/// });
+/// ```
+pub fn capture<F, R>(f: F) -> R
+where
+    F: FnOnce(&Capture) -> R,
+{
+    nut::capture(f)
+}
+
+/// Defines how to handle messages published by a handler of the same message type.
+///
+/// The queue prevents a stack overflow in that case, but a handler that always publishes its own type keeps nuts busy forever.
+/// With [`SelfRepublishPolicy::Limit`](enum.SelfRepublishPolicy.html#variant.Limit), the count is reset when the queue has been emptied,
+/// typically once the top-level `publish` returns.
+///
+/// ### Example
+/// ```rust
+/// use nuts::SelfRepublishPolicy;
+/// struct Retry;
+/// let activity = nuts::new_activity(0u32);
+/// activity.subscribe(|tries, _: &Retry| {
+///     *tries += 1;
+///     nuts::publish(Retry);
+/// });
+/// nuts::set_self_republish_policy(SelfRepublishPolicy::Limit(3));
+/// nuts::publish(Retry);
+/// assert_eq!(Some(4), activity.read(|tries| *tries));
+/// ```
+pub fn set_self_republish_policy(policy: SelfRepublishPolicy) {
+    nut::set_self_republish_policy(policy)
+}
+
+/// Defines how to handle messages published from `Drop` implementations while the library tears down activities or domain data.
+///
+/// Activities are dropped when they are deleted, domain data is dropped when a domain is cleared.
+/// By default, messages published during such a teardown are queued and delivered afterwards.
+/// See [`DropPublishPolicy`](enum.DropPublishPolicy.html) for the alternatives.
+///
+/// ### Example
+/// ```rust
+/// struct Unloaded;
+/// struct Plugin;
+/// impl Drop for Plugin {
+///     fn drop(&mut self) {
+///         nuts::publish(Unloaded);
+///     }
+/// }
+/// let plugin = nuts::new_activity(Plugin);
+/// nuts::set_drop_publish_policy(nuts::DropPublishPolicy::Ignore);
+/// // Plugin::drop publishes, but the message is ignored
+/// plugin.set_status(nuts::LifecycleStatus::Deleted);
+/// ```
+pub fn set_drop_publish_policy(policy: DropPublishPolicy) {
+    nut::set_drop_publish_policy(policy)
+}
+
+/// Enables or disables strict mode for unhandled messages. Strict mode is disabled by default.
+///
+/// In strict mode, [`publish`](fn.publish.html) panics when it is called with a message type that no activity has subscribed to.
+/// Use this during development to catch messages that have no consumer.
+///
+/// A subscription counts even if the subscribing activity is currently inactive.
+///
+/// ### Example
+/// ```rust,should_panic
+/// struct DeadMessage;
+/// nuts::set_strict_unhandled(true);
+/// // Panics, nobody listens to `DeadMessage`
+/// nuts::publish(DeadMessage);
+/// ```
+pub fn set_strict_unhandled(strict: bool) {
+    nut::set_strict_unhandled(strict)
+}
@@ -4,23 +4,64 @@
 //! library developers as well as users if they want to understand more how this library works.
 
 pub(crate) mod activity;
+pub(crate) mod error;
 pub(crate) mod exec;
+pub(crate) mod hash;
 pub(crate) mod iac;
+pub(crate) mod metadata;
+pub(crate) mod thread_guard;
+pub(crate) mod wiring;
 
 #[cfg(test)]
 mod test;
 
+use crate::nut::exec::observer::DispatchObserver;
+use crate::nut::exec::panic::PanicHandler;
+use crate::nut::exec::timing::{Clock, HandlerTiming, SlowHandlerCallback};
+use crate::nut::exec::trace::DeliveryTrace;
 use crate::nut::exec::Deferred;
 use crate::*;
 use core::any::Any;
 use core::sync::atomic::AtomicBool;
 use exec::fifo::ThreadLocalFifo;
+use hash::FxHashMap;
+use iac::capture::AwaitedResponse;
 use iac::managed_state::*;
-use std::cell::RefCell;
+use iac::publish::{
+    BroadcastInfo, DeadLetterHandler, DeferredMessage, Fold, History, Idle, MessageAlias,
+};
+use iac::registry::RegisteredType;
+use iac::subscription::{Subscription, TopicSubscriptions};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::rc::Rc;
+use wiring::PublishGraph;
 
 thread_local!(static NUT: Nut = Nut::new());
 
+/// Accesses the thread-local nut.
+///
+/// # Panics
+/// Panics with a descriptive message if the thread-local storage is not available.
+fn with_nut<F, R>(f: F) -> R
+where
+    F: FnOnce(&Nut) -> R,
+{
+    match try_with_nut(f) {
+        Ok(r) => r,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Accesses the thread-local nut, the fallible version.
+fn try_with_nut<F, R>(f: F) -> Result<R, NutsError>
+where
+    F: FnOnce(&Nut) -> R,
+{
+    thread_guard::check_thread();
+    NUT.try_with(f).map_err(|_| NutsError::StorageUnavailable)
+}
+
 /// A nut stores thread-local state and provides an easy interface to access it.
 ///
 /// To allow nested access to the nut, it is a read-only structure.
@@ -39,29 +80,103 @@ struct Nut {
     /// Mutable access only from outside of handlers, preferably before first publish call.
     /// Read-only access afterwards.
     /// (This restriction might change in the future)
-    subscriptions: RefCell<HashMap<Topic, ActivityHandlerContainer>>,
+    subscriptions: RefCell<FxHashMap<Topic, TopicSubscriptions>>,
     /// FIFO queue for published messages and other events that cannot be processed immediately.
     /// Atomically accessed mutably between closure dispatches.
     deferred_events: ThreadLocalFifo<Deferred>,
+    /// Receiving ends of pipes, see `nuts::pipe`
+    pipes: RefCell<Vec<Subscription>>,
+    /// Deliveries to subscriptions created with `subscribe_deferred`, processed once `deferred_events` is empty.
+    idle_events: ThreadLocalFifo<BroadcastInfo>,
+    /// Messages held back until the user calls `nuts::run_deferred`
+    deferred_messages: RefCell<Vec<DeferredMessage>>,
     /// A flag that marks if a broadcast is currently on-going
     executing: AtomicBool,
+    /// Woken when the executing flag is released, see `nuts::publish_async`
+    settle_wakers: RefCell<Vec<core::task::Waker>>,
+    /// The activity whose handler is currently executing, recorded as sender of published messages
+    current_activity: Cell<Option<UncheckedActivityId>>,
+    /// Generation of the message currently delivered, see `nuts::cascade_generation`
+    current_generation: Cell<usize>,
+    /// Topic of the message currently delivered
+    current_topic: Cell<Option<Topic>>,
+    self_republish_policy: Cell<SelfRepublishPolicy>,
+    /// Messages published by handlers of their own type since the queue was last empty
+    self_republished: Cell<usize>,
+    /// Callbacks run by `nuts::shutdown`, with their priority
+    teardowns: RefCell<Vec<(i32, TeardownFn)>>,
+    /// Set while activities or domain data are dropped, see `nuts::set_drop_publish_policy`
+    tearing_down: Cell<bool>,
+    drop_publish_policy: Cell<DropPublishPolicy>,
+    /// If set, publishing a message without any subscriber for its type panics.
+    strict_unhandled: Cell<bool>,
+    /// Receives published messages that nobody subscribed to, see `nuts::set_dead_letter`
+    dead_letter: RefCell<Option<DeadLetterHandler>>,
+    /// Message types registered by name, see `nuts::register_message_type`
+    message_registry: RefCell<HashMap<String, RegisteredType>>,
+    /// Publish relationships declared with `subscribe_publishing`
+    publish_graph: RefCell<PublishGraph>,
+    /// Recent messages replayed to late subscribers, see `nuts::set_history`
+    histories: RefCell<FxHashMap<Topic, History>>,
+    /// Conversions from one message type to another, applied on every publish.
+    aliases: RefCell<FxHashMap<Topic, Vec<MessageAlias>>>,
+    /// Parent of each domain in the domain hierarchy, see `nuts::set_domain_parent`
+    domain_parents: RefCell<HashMap<DomainId, DomainId>>,
+    /// Domain assigned to activities created with `nuts::new_activity`
+    default_domain: Cell<DomainId>,
+    /// Messages held back because delivery for their topic is paused
+    paused_topics: RefCell<FxHashMap<Topic, Vec<BroadcastInfo>>>,
+    /// Catches panics of handlers, see `nuts::set_panic_handler`
+    panic_handler: RefCell<Option<PanicHandler>>,
+    /// Measures handlers to detect slow ones, see `nuts::set_slow_handler_threshold_ms`
+    timing: RefCell<HandlerTiming>,
+    /// Receives a report of every dispatched message, see `nuts::set_dispatch_observer`
+    dispatch_observer: RefCell<Option<DispatchObserver>>,
+    /// Number of handler calls so far, to count the subscribers reached by a dispatch
+    handler_calls: Cell<usize>,
+    /// Records all deliveries while set, see `nuts::trace_publish`
+    trace: RefCell<Option<DeliveryTrace>>,
+    /// Intercepts top-level publishes while set, see `nuts::capture`
+    capture: RefCell<Option<Capture>>,
+    /// Intercepts the first response while set, see `nuts::publish_await_response`
+    awaited_response: RefCell<Option<AwaitedResponse>>,
+    /// Errors returned by subscriptions created with `subscribe_checked`, only collected during `nuts::publish_checked`
+    checked_errors: RefCell<Option<Vec<Box<dyn Any>>>>,
+    /// Handler calls for the message published with `nuts::publish_counted`, only counted while set
+    counted_calls: Cell<Option<usize>>,
+    /// Registered with `ActivityId::add_method`
+    methods: RefCell<MethodRegistry>,
 }
 
 /// A method that can be called by the `ActivityManager`.
 /// These handlers are created by the library and not part of the public interface.
-type Handler = Box<dyn Fn(&mut ActivityContainer, &mut ManagedState)>;
+///
+/// Returns false if the handler decided to skip the call, e.g. because required data is missing.
+type Handler = Box<dyn Fn(&ActivityContainer, &mut ManagedState) -> bool>;
+
+/// Same as `Handler` but for a borrowed message, which is passed in directly.
+type RefHandler = Box<dyn Fn(&ActivityContainer, &mut ManagedState, &dyn Any) -> bool>;
 
 impl Nut {
     fn new() -> Self {
         Default::default()
     }
-    fn push_closure<A: 'static>(&self, topic: Topic, id: ActivityId<A>, closure: Handler) {
-        self.subscriptions
+    fn push_subscription(&self, topic: Topic, sub: Subscription) -> SubscriptionId {
+        if let Some(type_id) = sub.predicate_type() {
+            assert!(
+                topic.message_type() == Some(type_id),
+                "The predicate of the subscription filter expects another message type than `{}`.",
+                sub.type_name
+            );
+        }
+        let index = self
+            .subscriptions
             .try_borrow_mut()
             .expect("Tried to add a new listener from inside a listener, which is not allowed.")
             .entry(topic)
-            .or_insert_with(Default::default)[id]
-            .push(closure);
+            .or_default()
+            .push(sub);
+        SubscriptionId { topic, index }
     }
 }
 
@@ -73,58 +188,483 @@ pub(crate) fn new_activity<A>(
 where
     A: Activity,
 {
-    NUT.with(|nut| {
+    with_nut(|nut| {
         let err = "Adding new activities from inside an activity is not allowed.";
-        nut.managed_state
+        let mut managed_state = nut.managed_state.try_borrow_mut().expect(err);
+        managed_state.prepare(domain_index);
+        managed_state.debug_assert_ready_for_activity(domain_index);
+        drop(managed_state);
+        nut.activities
             .try_borrow_mut()
             .expect(err)
-            .prepare(domain_index);
+            .add(activity, domain_index, status)
+    })
+}
+
+pub(crate) fn new_shared_activity<A>(
+    shared: Rc<RefCell<A>>,
+    domain_index: DomainId,
+    status: LifecycleStatus,
+) -> ActivityId<A>
+where
+    A: Activity,
+{
+    with_nut(|nut| {
+        let err = "Adding new activities from inside an activity is not allowed.";
+        let mut managed_state = nut.managed_state.try_borrow_mut().expect(err);
+        managed_state.prepare(domain_index);
+        managed_state.debug_assert_ready_for_activity(domain_index);
+        drop(managed_state);
         nut.activities
             .try_borrow_mut()
             .expect(err)
-            .add(activity, domain_index, status)
+            .add_shared(shared, domain_index, status)
+    })
+}
+
+pub(crate) fn ensure_domain_before_activity(domain: DomainId) {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Domain ordering cannot be changed from inside an activity.")
+            .require_data_before_activity(domain)
     })
 }
 
+pub(crate) fn default_domain() -> DomainId {
+    with_nut(|nut| nut.default_domain.get())
+}
+
+pub(crate) fn set_default_domain(domain: DomainId) {
+    with_nut(|nut| nut.default_domain.set(domain))
+}
+
+pub(crate) fn register_teardown(priority: i32, f: TeardownFn) {
+    with_nut(|nut| nut.register_teardown(priority, f))
+}
+
+pub(crate) fn shutdown() {
+    with_nut(|nut| nut.shutdown())
+}
+
+pub(crate) fn unsubscribe(id: SubscriptionId) {
+    with_nut(|nut| nut.unsubscribe(id))
+}
+
+pub(crate) fn unregister_by_predicate(predicate: exec::ActivityPredicate) {
+    with_nut(|nut| nut.unregister(predicate))
+}
+
+pub(crate) fn clear_domain(domain: DomainId) {
+    with_nut(|nut| nut.clear_domain(domain))
+}
+
 pub(crate) fn publish_custom<A: Any>(a: A) {
-    NUT.with(|nut| nut.publish(a))
+    with_nut(|nut| nut.publish(a))
+}
+
+pub(crate) fn cascade_generation() -> usize {
+    with_nut(|nut| nut.current_generation.get())
+}
+
+pub(crate) fn publish_with<MSG: Any>(f: impl FnOnce() -> MSG) {
+    with_nut(|nut| nut.publish_with(f))
+}
+
+pub(crate) fn publish_counted<MSG: Any>(msg: MSG) -> usize {
+    with_nut(|nut| nut.publish_counted(msg))
+}
+
+pub(crate) fn publish_batch<MSG: Any>(msgs: Vec<MSG>) {
+    with_nut(|nut| nut.publish_batch(msgs))
+}
+
+pub(crate) fn enqueue<A: Any>(a: A) {
+    with_nut(|nut| nut.enqueue_message(a))
+}
+
+pub(crate) fn poll_settled(cx: &mut core::task::Context<'_>) -> core::task::Poll<()> {
+    with_nut(|nut| nut.poll_settled(cx))
+}
+
+pub(crate) fn publish_deferred<MSG: Any>(msg: MSG) {
+    with_nut(|nut| nut.publish_deferred(msg))
+}
+
+pub(crate) fn run_deferred() -> usize {
+    with_nut(|nut| nut.run_deferred())
+}
+
+pub(crate) fn process_deferred_limited(max: usize) -> usize {
+    with_nut(|nut| nut.process_deferred_limited(max))
+}
+
+pub(crate) fn set_dispatch_observer(f: DispatchObserver) {
+    with_nut(|nut| nut.set_dispatch_observer(f))
+}
+
+pub(crate) fn publish_checked<MSG: Any, E: Any>(msg: MSG) -> Vec<E> {
+    with_nut(|nut| nut.publish_checked(msg))
+}
+
+pub(crate) fn report_checked_error(err: Box<dyn Any>) {
+    with_nut(|nut| nut.report_checked_error(err))
+}
+
+pub(crate) fn publish_required<MSG: RequiredMessage>(msg: MSG) -> Result<usize, NutsError> {
+    with_nut(|nut| nut.publish_required(msg))
+}
+
+pub(crate) fn try_publish_custom<A: Any>(a: A) -> Result<(), NutsError> {
+    try_with_nut(|nut| nut.publish(a))
+}
+
+pub(crate) fn publish_await_response<CMD: Any, RESP: Any>(cmd: CMD) -> Option<RESP> {
+    with_nut(|nut| nut.publish_await_response(cmd))
+}
+
+pub(crate) fn take_dispatch<MSG: Any>() -> DispatchSet<MSG> {
+    with_nut(|nut| nut.take_dispatch())
+}
+
+pub(crate) fn dispatch_selected<MSG: Any>(
+    topic: Topic,
+    indices: Vec<usize>,
+    msg: MSG,
+) -> Option<MSG> {
+    with_nut(|nut| nut.dispatch_selected(topic, indices, msg))
+}
+
+pub(crate) fn publish_fold<MSG: Any, ACC: Any>(msg: MSG, acc: ACC) -> ACC {
+    with_nut(|nut| nut.publish_fold(msg, acc))
+}
+
+pub(crate) fn publish_dbg<MSG: Any + core::fmt::Debug>(msg: MSG) {
+    with_nut(|nut| nut.publish_dbg(msg))
+}
+
+pub(crate) fn trace_publish<A: Any>(a: A) -> DeliveryTrace {
+    with_nut(|nut| nut.trace_publish(a))
+}
+
+pub(crate) fn capture<F, R>(f: F) -> R
+where
+    F: FnOnce(&Capture) -> R,
+{
+    with_nut(|nut| nut.capture(f))
+}
+
+pub(crate) fn set_dead_letter(f: DeadLetterHandler) {
+    with_nut(|nut| nut.set_dead_letter(f))
+}
+
+pub(crate) fn set_drop_publish_policy(policy: DropPublishPolicy) {
+    with_nut(|nut| nut.drop_publish_policy.set(policy))
+}
+
+pub(crate) fn set_self_republish_policy(policy: SelfRepublishPolicy) {
+    with_nut(|nut| nut.self_republish_policy.set(policy))
+}
+
+pub(crate) fn set_strict_unhandled(strict: bool) {
+    with_nut(|nut| nut.strict_unhandled.set(strict))
+}
+
+pub(crate) fn alias_message<OLD, NEW, F>(f: F)
+where
+    OLD: Any,
+    NEW: Any,
+    F: Fn(&OLD) -> NEW + 'static,
+{
+    with_nut(|nut| nut.add_alias(f))
+}
+
+pub(crate) fn pause_topic(topic: Topic) {
+    with_nut(|nut| nut.pause_topic(topic))
+}
+
+pub(crate) fn resume_topic(topic: Topic, coalesce: bool) {
+    with_nut(|nut| nut.resume_topic(topic, coalesce))
 }
 
-pub(crate) fn register<A, F, MSG>(id: ActivityId<A>, f: F, filter: SubscriptionFilter)
+pub(crate) fn register<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
 where
     A: Activity,
     F: Fn(&mut A, &MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_closure::<_, _, MSG>(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    register_with_priority(id, f, filter, 0)
 }
-pub(crate) fn register_mut<A, F, MSG>(id: ActivityId<A>, f: F, filter: SubscriptionFilter)
+pub(crate) fn register_with_priority<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+    priority: i32,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &MSG) + 'static,
+    MSG: Any,
+{
+    register_on_topic(id, f, filter, priority, Topic::message::<MSG>())
+}
+pub(crate) fn register_on_topic<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+    priority: i32,
+    topic: Topic,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &MSG) + 'static,
+    MSG: Any,
+{
+    with_nut(|nut| {
+        let f = Rc::new(f);
+        let by_ref = ManagedState::pack_closure_by_ref::<_, _, MSG>(f.clone(), id);
+        let closure = ManagedState::pack_closure::<_, _, MSG>(move |a, msg| f(a, msg), id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>())
+            .with_ref_handler(by_ref)
+            .with_priority(priority);
+        nut.push_subscription(topic, sub)
+    })
+}
+/// Swaps the handler of a subscription, returns false if the subscription no longer exists
+pub(crate) fn replace_subscription<A, F, MSG>(id: SubscriptionId, f: F) -> bool
+where
+    A: Activity,
+    F: Fn(&mut A, &MSG) + 'static,
+    MSG: Any,
+{
+    with_nut(|nut| {
+        assert!(
+            id.topic.message_type() == Some(core::any::TypeId::of::<MSG>()),
+            "The subscription expects another message type than `{}`.",
+            std::any::type_name::<MSG>()
+        );
+        let mut subscriptions = nut
+            .subscriptions
+            .try_borrow_mut()
+            .expect("Tried to replace a listener from inside a listener, which is not allowed.");
+        let sub = match subscriptions
+            .get_mut(&id.topic)
+            .and_then(|handlers| handlers.get_mut(id.index))
+        {
+            Some(sub) => sub,
+            None => return false,
+        };
+        let activity = nut
+            .activities
+            .borrow()
+            .typed_id::<A>(sub.activity)
+            .unwrap_or_else(|| {
+                panic!(
+                    "The subscription belongs to another activity type than `{}`.",
+                    std::any::type_name::<A>()
+                )
+            });
+        let once = sub.once();
+        let f = Rc::new(move |a: &mut A, msg: &MSG| {
+            if once.as_ref().is_none_or(|once| once.consume()) {
+                f(a, msg)
+            }
+        });
+        let by_ref = ManagedState::pack_closure_by_ref::<_, _, MSG>(f.clone(), activity);
+        let closure = ManagedState::pack_closure::<_, _, MSG>(move |a, msg| f(a, msg), activity);
+        sub.replace_handler(closure, by_ref);
+        true
+    })
+}
+pub(crate) fn set_once(id: SubscriptionId, once: Rc<activity::Once>) {
+    with_nut(|nut| {
+        if let Some(sub) = nut
+            .subscriptions
+            .borrow_mut()
+            .get_mut(&id.topic)
+            .and_then(|handlers| handlers.get_mut(id.index))
+        {
+            sub.set_once(once);
+        }
+    })
+}
+pub(crate) fn register_any<A, F>(id: ActivityId<A>, f: F) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &dyn Any) + 'static,
+{
+    with_nut(|nut| {
+        let f = Rc::new(f);
+        let by_ref = ManagedState::pack_closure_any_by_ref(f.clone(), id);
+        let closure = ManagedState::pack_closure_any(f, id);
+        let sub = Subscription::new(
+            id.into(),
+            Default::default(),
+            closure,
+            std::any::type_name::<dyn Any>(),
+        )
+        .with_ref_handler(by_ref);
+        nut.push_subscription(Topic::Wildcard, sub)
+    })
+}
+pub(crate) fn register_deferred<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &MSG) + 'static,
+    MSG: Any + Clone,
+{
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure(move |a, idle: &Idle<MSG>| f(a, &idle.0), id);
+        let idle_sub = Subscription::new(
+            id.into(),
+            SubscriptionFilter::no_filter(),
+            closure,
+            std::any::type_name::<MSG>(),
+        );
+        let idle = nut.push_subscription(Topic::message::<Idle<MSG>>(), idle_sub);
+        let closure = ManagedState::pack_closure(
+            move |_: &mut A, msg: &MSG| with_nut(|nut| nut.push_idle(msg.clone(), idle.index)),
+            id,
+        );
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>())
+            .with_companion(idle);
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+pub(crate) fn register_mut<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
 where
     A: Activity,
     F: Fn(&mut A, &mut MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_closure_mut::<_, _, MSG>(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure_mut::<_, _, MSG>(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+pub(crate) fn set_history<MSG: Any + Clone>(depth: usize) {
+    with_nut(|nut| nut.set_history::<MSG>(depth))
+}
+
+pub(crate) fn replay_history<MSG: Any + Clone>(subscription: SubscriptionId) {
+    with_nut(|nut| nut.replay_history::<MSG>(subscription))
+}
+
+pub(crate) fn publish_ref<MSG: Any>(msg: &MSG) {
+    with_nut(|nut| nut.publish_ref(msg))
+}
+
+pub(crate) fn publish_on_topic<MSG: Any>(name: &'static str, msg: MSG) {
+    with_nut(|nut| nut.publish_on_topic(name, msg))
+}
+
+pub(crate) fn publish_to<MSG: Any>(id: UncheckedActivityId, msg: MSG) {
+    with_nut(|nut| nut.publish_to(id, msg))
+}
+
+pub(crate) fn publish_to_including_inactive<MSG: Any>(id: UncheckedActivityId, msg: MSG) {
+    with_nut(|nut| nut.publish_to_including_inactive(id, msg))
+}
+
+pub(crate) fn set_domain_parent(child: DomainId, parent: DomainId) {
+    with_nut(|nut| nut.set_domain_parent(child, parent))
+}
+
+pub(crate) fn publish_to_domain<MSG: Any>(domain: DomainId, msg: MSG, reach: DomainReach) {
+    with_nut(|nut| nut.publish_to_domain(domain, msg, reach))
+}
+
+pub(crate) fn pipe<B, MSG, F>(from: UncheckedActivityId, to: ActivityId<B>, f: F) -> PipeId<MSG>
+where
+    B: Activity,
+    MSG: Any,
+    F: Fn(&mut B, &MSG) + 'static,
+{
+    with_nut(|nut| nut.pipe(from, to, f))
+}
+
+pub(crate) fn send_pipe<MSG: Any>(pipe: PipeId<MSG>, msg: MSG) {
+    with_nut(|nut| nut.send_pipe(pipe, msg))
+}
+
+pub(crate) fn register_message_type<MSG: Any>(name: &str) {
+    with_nut(|nut| nut.register_message_type::<MSG>(name))
+}
+
+pub(crate) fn subscribe_by_name(
+    activity: UncheckedActivityId,
+    name: &str,
+    handler: BoxedHandler,
+) -> SubscriptionId {
+    with_nut(|nut| nut.subscribe_by_name(activity, name, handler))
 }
-pub(crate) fn register_owned<A, F, MSG>(id: ActivityId<A>, f: F, filter: SubscriptionFilter)
+
+pub(crate) fn register_owned<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
 where
     A: Activity,
     F: Fn(&mut A, MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_closure_owned::<_, _, MSG>(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure_owned::<_, _, MSG>(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+
+pub(crate) fn register_with_services<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &dyn Any, &MSG) + 'static,
+    MSG: Any,
+{
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure_with_services(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+
+pub(crate) fn register_fold<A, F, MSG, ACC>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &MSG, &mut ACC) + 'static,
+    MSG: Any,
+    ACC: Any,
+{
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure_mut(
+            move |a, fold: &mut Fold<MSG, ACC>| f(a, &fold.msg, &mut fold.acc),
+            id,
+        );
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<Fold<MSG, ACC>>(), sub)
+    })
 }
 
 /// For subscriptions without payload
@@ -137,50 +677,107 @@ pub(crate) fn register_no_payload<A, F>(
     A: Activity,
     F: Fn(&mut A) + 'static,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_closure::<_, _, ()>(move |a, ()| f(a), id, filter);
-        nut.push_closure(topic, id, closure);
+    with_nut(|nut| {
+        let closure = ManagedState::pack_closure::<_, _, ()>(move |a, ()| f(a), id);
+        let sub = Subscription::new(id.into(), filter, closure, topic.name());
+        nut.push_subscription(topic, sub);
     });
 }
 
-pub(crate) fn register_domained<A, F, MSG>(id: ActivityId<A>, f: F, filter: SubscriptionFilter)
+pub(crate) fn register_domained<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
 where
     A: Activity,
     F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_domained_closure(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    with_nut(|nut| {
+        let f = Rc::new(f);
+        let by_ref = ManagedState::pack_domained_closure_by_ref::<_, _, MSG>(f.clone(), id);
+        let closure =
+            ManagedState::pack_domained_closure(move |a, domain, msg: &MSG| f(a, domain, msg), id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>())
+            .with_ref_handler(by_ref);
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
 }
-pub(crate) fn register_domained_mut<A, F, MSG>(id: ActivityId<A>, f: F, filter: SubscriptionFilter)
+pub(crate) fn register_domained_with<A, F, MSG>(
+    id: ActivityId<A>,
+    extra: DomainId,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &mut DomainState, &DomainState, &MSG) + 'static,
+    MSG: Any,
+{
+    assert!(id.domain_index.index().is_some(), "Activity has no domain");
+    assert_ne!(
+        id.domain_index, extra,
+        "The extra domain must be another domain than the activity's own domain."
+    );
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Tried to add a new listener from inside a listener, which is not allowed.")
+            .prepare(extra);
+        let closure = ManagedState::pack_domained_closure_with(f, id, extra);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+pub(crate) fn register_domained_requires<A, F, MSG, REQ>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
+where
+    A: Activity,
+    F: Fn(&mut A, &REQ, &MSG) + 'static,
+    MSG: Any,
+    REQ: Any,
+{
+    with_nut(|nut| {
+        let closure = ManagedState::pack_domained_closure_requires(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
+}
+pub(crate) fn register_domained_mut<A, F, MSG>(
+    id: ActivityId<A>,
+    f: F,
+    filter: SubscriptionFilter,
+) -> SubscriptionId
 where
     A: Activity,
     F: Fn(&mut A, &mut DomainState, &mut MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_domained_closure_mut(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    with_nut(|nut| {
+        let closure = ManagedState::pack_domained_closure_mut(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
 }
 pub(crate) fn register_domained_owned<A, F, MSG>(
     id: ActivityId<A>,
     f: F,
     filter: SubscriptionFilter,
-) where
+) -> SubscriptionId
+where
     A: Activity,
     F: Fn(&mut A, &mut DomainState, MSG) + 'static,
     MSG: Any,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_domained_closure_owned(f, id, filter);
-        let topic = Topic::message::<MSG>();
-        nut.push_closure(topic, id, closure);
-    });
+    with_nut(|nut| {
+        let closure = ManagedState::pack_domained_closure_owned(f, id);
+        let sub = Subscription::new(id.into(), filter, closure, std::any::type_name::<MSG>());
+        nut.push_subscription(Topic::message::<MSG>(), sub)
+    })
 }
 
 /// For subscriptions without payload but with domain access
@@ -193,9 +790,10 @@ pub(crate) fn register_domained_no_payload<A, F>(
     A: Activity,
     F: Fn(&mut A, &mut DomainState) + 'static,
 {
-    NUT.with(|nut| {
-        let closure = ManagedState::pack_domained_closure(move |a, d, ()| f(a, d), id, filter);
-        nut.push_closure(topic, id, closure);
+    with_nut(|nut| {
+        let closure = ManagedState::pack_domained_closure(move |a, d, ()| f(a, d), id);
+        let sub = Subscription::new(id.into(), filter, closure, topic.name());
+        nut.push_subscription(topic, sub);
     });
 }
 
@@ -207,10 +805,11 @@ where
     A: Activity,
     F: FnOnce(A) + 'static,
 {
-    NUT.with(|nut| {
+    with_nut(|nut| {
         let closure = Box::new(|a: Box<dyn Any>| {
-            let activity = a.downcast().expect("on delete registration has a bug");
-            f(*activity);
+            if let Some(activity) = into_activity(a) {
+                f(activity);
+            }
         });
         nut.activities
             .try_borrow_mut()?
@@ -219,6 +818,16 @@ where
     })
 }
 
+pub(crate) fn register_on_status_change(
+    id: UncheckedActivityId,
+    f: StatusChangeFn,
+) -> Result<(), std::cell::BorrowMutError> {
+    with_nut(|nut| {
+        nut.activities.try_borrow_mut()?.add_on_status_change(id, f);
+        Ok(())
+    })
+}
+
 pub(crate) fn register_domained_on_delete<A, F>(
     id: ActivityId<A>,
     f: F,
@@ -227,14 +836,15 @@ where
     A: Activity,
     F: FnOnce(A, &mut DomainState) + 'static,
 {
-    NUT.with(|nut| {
-        let cloned_id = id.clone();
+    with_nut(|nut| {
+        let cloned_id = id;
         let closure = Box::new(move |a: Box<dyn Any>, managed_state: &mut ManagedState| {
-            let activity = a.downcast().expect("on delete registration has a bug");
-            let domain = managed_state
-                .get_mut(cloned_id.domain_index)
-                .expect("missing domain");
-            f(*activity, domain);
+            if let Some(activity) = into_activity(a) {
+                let domain = managed_state
+                    .get_mut(cloned_id.domain_index)
+                    .expect("missing domain");
+                f(activity, domain);
+            }
         });
         nut.activities
             .try_borrow_mut()?
@@ -243,8 +853,302 @@ where
     })
 }
 
+pub(crate) fn status(id: UncheckedActivityId) -> LifecycleStatus {
+    with_nut(|nut| {
+        nut.activities
+            .try_borrow()
+            .expect("The status cannot be read while it is being changed.")
+            .status(id)
+    })
+}
+
+pub(crate) fn read_activity<A, F, R>(id: ActivityId<A>, f: F) -> Option<R>
+where
+    A: Activity,
+    F: FnOnce(&A) -> R,
+{
+    with_nut(|nut| {
+        let activities = nut.activities.try_borrow().ok()?;
+        activities.try_read(id, f)
+    })
+}
+
+pub(crate) fn with_activity<A, R>(id: ActivityId<A>, f: impl FnOnce(&A) -> R) -> R
+where
+    A: Activity,
+{
+    with_nut(|nut| {
+        let activities = borrow_activities_for::<A>(nut, id);
+        let result = activities.try_read(id, f);
+        result.unwrap_or_else(|| panic_borrowed::<A>())
+    })
+}
+
+pub(crate) fn with_activity_mut<A, R>(id: ActivityId<A>, f: impl FnOnce(&mut A) -> R) -> R
+where
+    A: Activity,
+{
+    with_nut(|nut| {
+        let activities = borrow_activities_for::<A>(nut, id);
+        let result = activities.try_write(id, f);
+        result.unwrap_or_else(|| panic_borrowed::<A>())
+    })
+}
+
+fn borrow_activities_for<A: Activity>(
+    nut: &Nut,
+    id: ActivityId<A>,
+) -> std::cell::Ref<'_, ActivityContainer> {
+    let activities = nut
+        .activities
+        .try_borrow()
+        .expect("Activities cannot be accessed while an activity is being added or deleted.");
+    assert!(
+        activities.contains(id.into()),
+        "Tried to access activity `{}` after it has been deleted.",
+        std::any::type_name::<A>()
+    );
+    activities
+}
+
+fn panic_borrowed<A: Activity>() -> ! {
+    panic!(
+        "Activity `{}` is already borrowed, e.g. because one of its own handlers is executing. \
+        Use `read` or `call` to handle this case without panicking.",
+        std::any::type_name::<A>()
+    )
+}
+
+pub(crate) fn add_method<A: Activity>(id: ActivityId<A>, name: &str, method: Method<A>) {
+    with_nut(|nut| nut.add_method(id, name, method))
+}
+
+pub(crate) fn call_method<A: Activity>(id: ActivityId<A>, name: &str) -> Result<(), NutsError> {
+    with_nut(|nut| nut.call_method(id, name))
+}
+
+pub(crate) fn call_activity<A, F, ARG, RET>(id: ActivityId<A>, f: F, arg: ARG) -> Option<RET>
+where
+    A: Activity,
+    F: FnOnce(&mut A, ARG) -> RET,
+{
+    with_nut(|nut| {
+        let activities = nut.activities.try_borrow().ok()?;
+        activities.try_write(id, |activity| f(activity, arg))
+    })
+}
+
+pub(crate) fn order_subscriptions(
+    first: SubscriptionId,
+    second: SubscriptionId,
+) -> Result<(), NutsError> {
+    assert_eq!(
+        first.topic, second.topic,
+        "Only subscriptions to the same message type can be ordered."
+    );
+    with_nut(|nut| {
+        nut.subscriptions
+            .try_borrow_mut()
+            .expect("Subscriptions cannot be ordered from inside an activity.")
+            .get_mut(&first.topic)
+            .expect("Bug: subscription without topic")
+            .add_constraint(first.index, second.index)
+    })
+}
+
+pub(crate) fn unfired_subscriptions() -> Vec<(UncheckedActivityId, &'static str)> {
+    with_nut(|nut| {
+        let mut unfired: Vec<_> = nut
+            .subscriptions
+            .borrow()
+            .values()
+            .flat_map(|handlers| handlers.iter())
+            .filter(|sub| sub.calls() == 0)
+            .map(|sub| (sub.activity, sub.type_name))
+            .collect();
+        unfired.sort_unstable();
+        unfired
+    })
+}
+
+pub(crate) fn export_metadata() -> ActivityMetadata {
+    with_nut(|nut| nut.export_metadata())
+}
+
+pub(crate) fn subscribed_types() -> Vec<&'static str> {
+    with_nut(|nut| {
+        let mut types: Vec<_> = nut
+            .subscriptions
+            .borrow()
+            .iter()
+            .filter(|(topic, _)| matches!(topic, Topic::Message(_)))
+            .filter_map(|(_, handlers)| handlers.iter().next())
+            .map(|sub| sub.type_name)
+            .collect();
+        types.sort_unstable();
+        types.dedup();
+        types
+    })
+}
+
+pub(crate) fn subscriber_count<MSG: Any>() -> usize {
+    with_nut(|nut| nut.subscriber_count(&Topic::message::<MSG>()))
+}
+
+pub(crate) fn is_subscribed<MSG: Any>(id: UncheckedActivityId) -> bool {
+    with_nut(|nut| nut.is_subscribed(&Topic::message::<MSG>(), id))
+}
+
+pub(crate) fn activity_count() -> usize {
+    with_nut(|nut| nut.activities.borrow().len())
+}
+
+pub(crate) fn validate_wiring() -> Result<(), Vec<WiringIssue>> {
+    with_nut(|nut| nut.validate_wiring())
+}
+
+pub(crate) fn declare_publishes<MSG: Any, PUBLISHES: Any>(
+    subscription: SubscriptionId,
+    activity: UncheckedActivityId,
+) {
+    with_nut(|nut| {
+        nut.publish_graph
+            .borrow_mut()
+            .declare::<MSG, PUBLISHES>(subscription, activity)
+    })
+}
+
+pub(crate) fn detect_cycles() -> Vec<Vec<&'static str>> {
+    with_nut(|nut| nut.publish_graph.borrow().cycles())
+}
+
 pub(crate) fn set_status(id: UncheckedActivityId, status: LifecycleStatus) {
-    NUT.with(|nut| nut.set_status(id, status));
+    with_nut(|nut| nut.set_status(id, status));
+}
+
+pub(crate) fn set_domain_mirror(mirror: DomainMirror) {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("The domain mirror cannot be set from inside an activity.")
+            .set_domain_mirror(mirror)
+    })
+}
+
+pub(crate) fn set_clock(clock: Clock) {
+    with_nut(|nut| {
+        nut.timing
+            .try_borrow_mut()
+            .expect("The clock cannot be set from inside the slow handler callback.")
+            .set_clock(clock)
+    })
+}
+
+pub(crate) fn set_slow_handler_threshold_ms(threshold_ms: u64) {
+    with_nut(|nut| {
+        nut.timing
+            .try_borrow_mut()
+            .expect("The threshold cannot be set from inside the slow handler callback.")
+            .set_threshold_ms(threshold_ms)
+    })
+}
+
+pub(crate) fn set_panic_handler(f: PanicHandler) {
+    with_nut(|nut| nut.set_panic_handler(f))
+}
+
+pub(crate) fn on_slow_handler(f: SlowHandlerCallback) {
+    with_nut(|nut| {
+        nut.timing
+            .try_borrow_mut()
+            .expect("The slow handler callback cannot be set from inside itself.")
+            .set_on_slow_handler(f)
+    })
+}
+
+pub(crate) fn set_services(services: Box<dyn Any>) {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Services cannot be set from inside an activity.")
+            .set_services(services)
+    })
+}
+
+pub(crate) fn register_snapshot_type<T: Any + Clone + Send + Sync>() {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Snapshot types cannot be registered from inside an activity.")
+            .register_snapshot_type::<T>()
+    })
+}
+
+pub(crate) fn snapshot_domain(domain: DomainId) -> DomainSnapshot {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow()
+            .expect("Domains cannot be snapshotted from inside an activity.")
+            .snapshot(domain)
+    })
+}
+
+pub(crate) fn register_diffable<T: Any + Clone + PartialEq>() {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Diffable types cannot be registered from inside an activity.")
+            .register_diffable::<T>()
+    })
+}
+
+pub(crate) fn snapshot_domain_for_diff(domain: DomainId) -> DomainDiffSnapshot {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow()
+            .expect("Domains cannot be snapshotted from inside an activity.")
+            .snapshot_for_diff(domain)
+    })
+}
+
+pub(crate) fn with_domain<R>(id: DomainId, f: impl FnOnce(&mut DomainState) -> R) -> R {
+    with_nut(|nut| {
+        let mut managed_state = nut
+            .managed_state
+            .try_borrow_mut()
+            .expect("`with_domain` cannot be used from inside an activity.");
+        managed_state.prepare(id);
+        let result = f(managed_state.get_mut(id).expect("No domain"));
+        managed_state.notify_mirror();
+        result
+    })
+}
+
+pub(crate) fn enable_domain_profiling() {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow_mut()
+            .expect("Domain profiling cannot be enabled from inside an activity.")
+            .enable_profiling()
+    })
+}
+
+pub(crate) fn domain_access_report() -> AccessReport {
+    with_nut(|nut| {
+        nut.managed_state
+            .try_borrow()
+            .expect("The domain access report cannot be created from inside an activity.")
+            .access_report()
+    })
+}
+
+pub(crate) fn defer_domain_write<T: Any>(domain: DomainId, data: T) {
+    with_nut(|nut| {
+        let write = Box::new(move |domain: &mut DomainState| domain.store(data));
+        nut.deferred_events
+            .push(Deferred::DomainWrite(domain, write));
+        nut.catch_up_deferred_to_quiescence();
+    })
 }
 
 pub(crate) fn write_domain<D, T>(domain: &D, data: T) -> Result<(), std::cell::BorrowMutError>
@@ -252,12 +1156,13 @@ where
     D: DomainEnumeration,
     T: core::any::Any,
 {
-    NUT.with(|nut| {
+    with_nut(|nut| {
         let id = DomainId::new(domain);
         let mut managed_state = nut.managed_state.try_borrow_mut()?;
         managed_state.prepare(id);
         let storage = managed_state.get_mut(id).expect("No domain");
         storage.store(data);
+        managed_state.notify_mirror();
         Ok(())
     })
 }
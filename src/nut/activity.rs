@@ -1,15 +1,21 @@
 mod activity_container;
+mod handler;
 mod lifecycle;
+mod method;
 
 pub(crate) use activity_container::*;
+pub use handler::*;
 pub use lifecycle::*;
+pub use method::Method;
+pub(crate) use method::MethodRegistry;
 
+use crate::nut::iac::variants::VariantHandler;
 use crate::nut::iac::{filter::SubscriptionFilter, managed_state::DomainId};
-use crate::nut::Handler;
 use crate::*;
 use core::any::Any;
-use std::collections::HashMap;
-use std::ops::{Index, IndexMut};
+use std::cell::{Cell, RefCell};
+use std::ops::Index;
+use std::rc::Rc;
 
 // @ START-DOC ACTIVITY
 /// Activities are at the core of Nuts.
@@ -29,6 +35,10 @@ impl<T: Any> Activity for T {}
 /// Can be used to add type-checked closures to the activity, which will be used as event listeners.
 ///
 /// Implements `Copy` and `Clone`
+///
+/// Ids are assigned from a sequential counter, in the order activities are created on a thread.
+/// They are never reused, not even after an activity has been deleted.
+/// The same setup code therefore always produces the same ids, which are ordered by creation.
 pub struct ActivityId<A> {
     pub(crate) id: UncheckedActivityId,
     pub(crate) domain_index: DomainId,
@@ -45,6 +55,15 @@ pub struct UncheckedActivityId {
     index: usize,
 }
 
+#[derive(PartialEq, Eq, Debug, Hash)]
+/// Handle to an activity that may have been deleted, created with [`ActivityId::downgrade`](struct.ActivityId.html#method.downgrade).
+///
+/// Use it in caches and in library code that should not rely on an activity to still exist.
+/// Since ids are never reused, a handle to a deleted activity can never refer to a newer activity by accident.
+pub struct WeakActivityId<A> {
+    id: ActivityId<A>,
+}
+
 impl<A: Activity> ActivityId<A> {
     pub(crate) fn new(index: usize, domain_index: DomainId) -> Self {
         Self {
@@ -73,6 +92,28 @@ impl<A: Activity> ActivityId<A> {
         )
     }
     /// Registers a callback closure that is called when an activity changes from active to inactive.
+    ///
+    /// The closure is called as soon as the status change is applied, before any other queued message is delivered.
+    /// Thus, `on_leave` has always completed when the first message is filtered out because the activity is inactive.
+    /// The same holds for `on_enter` and the first message received in the active state.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Connect;
+    /// struct Data;
+    /// let network = nuts::new_activity(Vec::new());
+    /// network.on_enter(|log| log.push("open socket"));
+    /// network.on_leave(|log| log.push("close socket"));
+    /// network.subscribe(|log, _: &Data| log.push("data"));
+    ///
+    /// let controller = nuts::new_activity(());
+    /// controller.subscribe(move |_, _: &Connect| {
+    ///     network.deactivate();
+    ///     nuts::publish(Data);
+    /// });
+    /// nuts::publish(Connect);
+    /// assert_eq!(Some(vec!["close socket"]), network.read(|log| log.clone()));
+    /// ```
     pub fn on_leave<F>(&self, f: F)
     where
         F: Fn(&mut A) + 'static,
@@ -91,6 +132,52 @@ impl<A: Activity> ActivityId<A> {
             SubscriptionFilter::no_filter(),
         )
     }
+    /// Registers a callback closure that is called when an activity changes to suspended.
+    ///
+    /// If the activity was active before, `on_leave` subscriptions are called first.
+    /// Use it to release cached resources.
+    pub fn on_suspend<F>(&self, f: F)
+    where
+        F: Fn(&mut A) + 'static,
+    {
+        crate::nut::register_no_payload(*self, f, Topic::suspend(), SubscriptionFilter::no_filter())
+    }
+    /// Registers a callback closure that is called when an activity changes from suspended to another status, other than deleted.
+    ///
+    /// If the activity becomes active, `on_enter` subscriptions are called afterwards.
+    pub fn on_resume<F>(&self, f: F)
+    where
+        F: Fn(&mut A) + 'static,
+    {
+        crate::nut::register_no_payload(*self, f, Topic::resume(), SubscriptionFilter::no_filter())
+    }
+    /// Registers a callback that is called with the old and the new status whenever the status of this activity changes.
+    ///
+    /// Unlike `on_enter` and similar subscriptions, the callback has no access to the activity itself.
+    /// That makes it usable for supervisors that watch other activities and react from the outside.
+    /// It is called before any `on_enter`, `on_leave`, `on_suspend`, or `on_resume` subscriptions.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use nuts::LifecycleStatus;
+    /// let worker = nuts::new_activity(());
+    /// worker.on_status_change(move |_old, new| {
+    ///     if new == LifecycleStatus::Suspended {
+    ///         // Restart the worker
+    ///         worker.set_status(LifecycleStatus::Active);
+    ///     }
+    /// });
+    /// worker.set_status(LifecycleStatus::Suspended);
+    /// let metadata = nuts::export_metadata();
+    /// assert_eq!(LifecycleStatus::Active, metadata.activities()[0].status());
+    /// ```
+    pub fn on_status_change<F>(&self, f: F)
+    where
+        F: Fn(LifecycleStatus, LifecycleStatus) + 'static,
+    {
+        crate::nut::register_on_status_change(self.id, Rc::new(f))
+            .expect("Cannot add status change callbacks now");
+    }
     /// Registers a callback closure that is called when an activity is deleted.
     pub fn on_delete<F>(&self, f: F)
     where
@@ -111,6 +198,8 @@ impl<A: Activity> ActivityId<A> {
     /// By default, the activity will only receive calls when it is active.
     /// Use `subscribe_masked` for more control over this behavior.
     ///
+    /// The returned [`SubscriptionId`](struct.SubscriptionId.html) can be used to control the order of subscriptions.
+    ///
     /// ### Example
     // @ START-DOC SUBSCRIBE_EXAMPLE
     /// ```rust
@@ -149,15 +238,94 @@ impl<A: Activity> ActivityId<A> {
     /// }
     /// ```
     // @ END-DOC SUBSCRIBE_EXAMPLE
-    pub fn subscribe<F, MSG>(&self, f: F)
+    pub fn subscribe<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &MSG) + 'static,
         MSG: Any,
     {
         crate::nut::register(*self, f, Default::default())
     }
+    /// Registers a callback closure that is called for every published message, regardless of its type.
+    ///
+    /// The message can be inspected with `downcast_ref`, which makes this useful for debugging and tracing.
+    /// For each published message, these subscriptions are called after all subscriptions to the concrete message type.
+    /// Subscriptions that take ownership of the message, like [`subscribe_owned`](#method.subscribe_owned), leave nothing to observe, the closure is not called in that case.
+    ///
+    /// Only published messages are observed, not lifecycle events or messages sent through pipes or with `publish_to`.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use std::any::Any;
+    /// let tracer = nuts::new_activity(Vec::new());
+    /// tracer.subscribe_any(|log, msg: &dyn Any| {
+    ///     if let Some(n) = msg.downcast_ref::<u32>() {
+    ///         log.push(*n);
+    ///     }
+    /// });
+    /// nuts::publish(7u32);
+    /// nuts::publish("ignored");
+    /// assert_eq!(Some(vec![7]), tracer.read(|log| log.clone()));
+    /// ```
+    pub fn subscribe_any<F>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &dyn Any) + 'static,
+    {
+        crate::nut::register_any(*self, f)
+    }
+    /// Same as [subscribe](#method.subscribe) but with a priority that defines when the closure is called.
+    ///
+    /// Subscriptions with lower numbers are called first, [subscribe](#method.subscribe) uses priority 0.
+    /// Subscriptions with equal priority are called in the order of registration.
+    /// Constraints defined with [`SubscriptionId::after`](struct.SubscriptionId.html#method.after) take precedence over priorities.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use std::{cell::RefCell, rc::Rc};
+    /// struct Frame;
+    /// let calls = Rc::new(RefCell::new(Vec::new()));
+    /// let logger = nuts::new_activity(calls.clone());
+    /// logger.subscribe_with_priority(i32::MAX, |calls, _: &Frame| calls.borrow_mut().push("log"));
+    /// let game = nuts::new_activity(calls.clone());
+    /// game.subscribe(|calls, _: &Frame| calls.borrow_mut().push("update"));
+    ///
+    /// nuts::publish(Frame);
+    /// assert_eq!(vec!["update", "log"], *calls.borrow());
+    /// ```
+    pub fn subscribe_with_priority<F, MSG>(&self, priority: i32, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        crate::nut::register_with_priority(*self, f, Default::default(), priority)
+    }
+    /// Same as [subscribe](#method.subscribe) but only for messages published on the named topic, see [`nuts::publish_on_topic`](fn.publish_on_topic.html).
+    ///
+    /// Subscriptions to different names, or to the unnamed topic of [subscribe](#method.subscribe), never receive each other's messages.
+    pub fn subscribe_on_topic<F, MSG>(&self, name: &'static str, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        crate::nut::register_on_topic(*self, f, Default::default(), 0, Topic::named::<MSG>(name))
+    }
+    /// Same as [subscribe](#method.subscribe) but the closure may fail.
+    ///
+    /// Errors are returned by [`nuts::publish_checked`](fn.publish_checked.html).
+    /// When the message has been published in any other way, the error is dropped.
+    pub fn subscribe_checked<F, MSG, E>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) -> Result<(), E> + 'static,
+        MSG: Any,
+        E: Any,
+    {
+        self.subscribe(move |a: &mut A, msg: &MSG| {
+            if let Err(err) = f(a, msg) {
+                crate::nut::report_checked_error(Box::new(err));
+            }
+        })
+    }
     /// Same as [subscribe](#method.subscribe) but gives mutable access to the message object.
-    pub fn subscribe_mut<F, MSG>(&self, f: F)
+    pub fn subscribe_mut<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &mut MSG) + 'static,
         MSG: Any,
@@ -167,7 +335,7 @@ impl<A: Activity> ActivityId<A> {
     /// Registers a callback closure on an activity with a specific topic to listen to.
     /// This variant takes ownership of the message.
     /// Only subscription per type is allowed. Othwerise, a pnic will occur when publishing.
-    pub fn subscribe_owned<F, MSG>(&self, f: F)
+    pub fn subscribe_owned<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, MSG) + 'static,
         MSG: Any,
@@ -175,6 +343,61 @@ impl<A: Activity> ActivityId<A> {
         crate::nut::register_owned(*self, f, Default::default())
     }
 
+    /// Registers a callback closure that takes part in [`nuts::publish_fold`](fn.publish_fold.html).
+    ///
+    /// The closure receives the message and mutable access to an accumulator of type `ACC`,
+    /// which is passed from one subscriber to the next.
+    /// These subscriptions are not called by a plain `publish`.
+    pub fn subscribe_fold<F, MSG, ACC>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG, &mut ACC) + 'static,
+        MSG: Any,
+        ACC: Any,
+    {
+        crate::nut::register_fold(*self, f, Default::default())
+    }
+
+    /// Registers a callback closure that answers [`nuts::publish_and_collect`](fn.publish_and_collect.html).
+    ///
+    /// The value returned by the closure is added to the collected responses.
+    /// These subscriptions are not called by a plain `publish`.
+    pub fn subscribe_with_response<F, MSG, R>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) -> R + 'static,
+        MSG: Any,
+        R: Any,
+    {
+        self.subscribe_fold(move |a, msg, responses: &mut Vec<R>| responses.push(f(a, msg)))
+    }
+
+    /// Registers a callback closure on an activity with a specific topic to listen to.
+    /// Has read access to the services registered with [`nuts::set_services`](fn.set_services.html).
+    ///
+    /// The services are passed as `&dyn Any`, use `downcast_ref` to access the concrete type.
+    ///
+    /// # Panics
+    /// Panics when a message is delivered and no services have been registered.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Renderer { scale: u32 }
+    /// nuts::set_services(Box::new(Renderer { scale: 2 }));
+    ///
+    /// let activity = nuts::new_activity(());
+    /// activity.subscribe_with_services(|_, services, msg: &u32| {
+    ///     let renderer: &Renderer = services.downcast_ref().unwrap();
+    ///     assert_eq!(6, renderer.scale * msg);
+    /// });
+    /// nuts::publish(3u32);
+    /// ```
+    pub fn subscribe_with_services<F, MSG>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &dyn Any, &MSG) + 'static,
+        MSG: Any,
+    {
+        crate::nut::register_with_services(*self, f, Default::default())
+    }
+
     /// Registers a callback closure on an activity with a specific topic to listen to.
     /// Has mutable access to the `DomainState` object.
     ///
@@ -183,15 +406,157 @@ impl<A: Activity> ActivityId<A> {
     ///
     /// # Panics
     /// Panics if the activity has not been registered with a domain.    
-    pub fn subscribe_domained<F, MSG>(&self, f: F)
+    pub fn subscribe_domained<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
         MSG: Any,
     {
         crate::nut::register_domained(*self, f, Default::default())
     }
+    /// Same as [`subscribe_domained`](#method.subscribe_domained) but the closure can also read the domain `extra`.
+    ///
+    /// # Panics
+    /// Panics if the activity has not been registered with a domain, or if `extra` is the activity's own domain.
+    ///
+    /// ### Example
+    /// ```rust
+    /// #[macro_use] extern crate nuts;
+    /// use nuts::{domain_enum, DomainEnumeration};
+    /// #[derive(Clone, Copy)]
+    /// enum Scope { Config, Game }
+    /// domain_enum!(Scope);
+    /// struct Difficulty(u32);
+    /// struct Spawn;
+    ///
+    /// nuts::store_to_domain(&Scope::Config, Difficulty(3));
+    /// let spawner = nuts::new_domained_activity((), &Scope::Game);
+    /// spawner.subscribe_domained_with(&Scope::Config, |_, game, config, _: &Spawn| {
+    ///     game.store(config.get::<Difficulty>().0 * 10);
+    /// });
+    /// nuts::publish(Spawn);
+    /// ```
+    pub fn subscribe_domained_with<D, F, MSG>(&self, extra: &D, f: F) -> SubscriptionId
+    where
+        D: DomainEnumeration,
+        F: Fn(&mut A, &mut DomainState, &DomainState, &MSG) + 'static,
+        MSG: Any,
+    {
+        crate::nut::register_domained_with(*self, DomainId::new(extra), f, Default::default())
+    }
+    /// Same as [subscribe](#method.subscribe) but the closure is immediately called with the messages recorded by [`nuts::set_history`](fn.set_history.html).
+    ///
+    /// The recorded messages are replayed oldest first, only to this subscription.
+    /// Filters apply as usual, so an inactive activity does not receive the history.
+    ///
+    /// ### Example
+    /// ```rust
+    /// #[derive(Clone)]
+    /// struct Chat(&'static str);
+    /// nuts::set_history::<Chat>(2);
+    /// nuts::publish(Chat("hello"));
+    /// nuts::publish(Chat("how are you?"));
+    /// nuts::publish(Chat("anyone here?"));
+    ///
+    /// let late_joiner = nuts::new_activity(Vec::new());
+    /// late_joiner.subscribe_with_history(|log, msg: &Chat| log.push(msg.0));
+    /// assert_eq!(
+    ///     Some(vec!["how are you?", "anyone here?"]),
+    ///     late_joiner.read(|log| log.clone())
+    /// );
+    /// ```
+    pub fn subscribe_with_history<F, MSG>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any + Clone,
+    {
+        let id = self.subscribe(f);
+        crate::nut::replay_history::<MSG>(id);
+        id
+    }
+    /// Same as [subscribe](#method.subscribe) but the subscription removes itself after the closure has been called once.
+    ///
+    /// Only actual calls count, a message that is filtered out, e.g. because the activity is inactive, does not consume the subscription.
+    /// Messages of the same type published by the closure itself do not reach it anymore.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Handshake;
+    /// let activity = nuts::new_activity(0u32);
+    /// activity.subscribe_once(|count, _: &Handshake| {
+    ///     *count += 1;
+    ///     nuts::publish(Handshake);
+    /// });
+    /// nuts::publish(Handshake);
+    /// nuts::publish(Handshake);
+    /// assert_eq!(Some(1), activity.read(|count| *count));
+    /// ```
+    pub fn subscribe_once<F, MSG>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        let once = Rc::new(Once::default());
+        let guard = once.clone();
+        let id = self.subscribe(move |a, msg| {
+            if guard.consume() {
+                f(a, msg)
+            }
+        });
+        once.attach(id)
+    }
+    /// Same as [`subscribe_once`](#method.subscribe_once) but with access to the domain, like [`subscribe_domained`](#method.subscribe_domained).
+    ///
+    /// # Panics
+    /// Panics if the activity has not been registered with a domain.
+    pub fn subscribe_once_domained<F, MSG>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
+        MSG: Any,
+    {
+        let once = Rc::new(Once::default());
+        let guard = once.clone();
+        let id = self.subscribe_domained(move |a, domain, msg| {
+            if guard.consume() {
+                f(a, domain, msg)
+            }
+        });
+        once.attach(id)
+    }
+    /// Registers a callback closure that is only called if the domain contains a value of type `REQ`.
+    ///
+    /// Instead of the full domain, the closure receives a reference to the required value.
+    /// If no such value is stored in the domain when the message is published, the call is skipped.
+    ///
+    /// # Panics
+    /// Panics if the activity has not been registered with a domain.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use nuts::DefaultDomain;
+    /// struct Config { verbose: bool }
+    /// struct LogLine(&'static str);
+    ///
+    /// let activity = nuts::new_domained_activity((), &DefaultDomain);
+    /// activity.subscribe_requires(|_, config: &Config, line: &LogLine| {
+    ///     if config.verbose {
+    ///         println!("{}", line.0);
+    ///     }
+    /// });
+    /// // Skipped, no config is stored
+    /// nuts::publish(LogLine("skipped"));
+    /// nuts::store_to_domain(&DefaultDomain, Config { verbose: true });
+    /// nuts::publish(LogLine("printed"));
+    /// ```
+    pub fn subscribe_requires<F, MSG, REQ>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &REQ, &MSG) + 'static,
+        MSG: Any,
+        REQ: Any,
+    {
+        crate::nut::register_domained_requires(*self, f, Default::default())
+    }
     /// Same as [`subscribe_domained`](#method.subscribe_domained) but gives mutable access to the message object.
-    pub fn subscribe_domained_mut<F, MSG>(&self, f: F)
+    pub fn subscribe_domained_mut<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &mut DomainState, &mut MSG) + 'static,
         MSG: Any,
@@ -201,7 +566,7 @@ impl<A: Activity> ActivityId<A> {
     /// Registers a callback closure on an activity with a specific topic to listen to and access to the domain.
     /// This variant takes ownership of the message.
     /// Only subscription per type is allowed. Otherwise, a panic will occur when publishing.
-    pub fn subscribe_domained_owned<F, MSG>(&self, f: F)
+    pub fn subscribe_domained_owned<F, MSG>(&self, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &mut DomainState, MSG) + 'static,
         MSG: Any,
@@ -209,8 +574,207 @@ impl<A: Activity> ActivityId<A> {
         crate::nut::register_domained_owned(*self, f, Default::default())
     }
 
+    /// Registers a callback closure that is called with a clone of the message, after all other work has been done.
+    ///
+    /// The closure is not called while the message is delivered.
+    /// Instead, the call is queued and only made once no other messages or events are waiting to be processed.
+    /// Use it for low-priority work, such as logging, that should not delay the delivery of other messages.
+    /// Whether the activity receives the message is decided by its status at the time of the publish.
+    ///
+    /// ### Example
+    /// ```rust
+    /// #[derive(Clone)]
+    /// struct Clicked;
+    /// let activity = nuts::new_activity(Vec::new());
+    /// activity.subscribe_deferred(|log, _: &Clicked| log.push("analytics"));
+    /// activity.subscribe(|log, _: &Clicked| log.push("ui"));
+    /// nuts::publish(Clicked);
+    /// assert_eq!(Some(vec!["ui", "analytics"]), activity.read(|log| log.clone()));
+    /// ```
+    pub fn subscribe_deferred<F, MSG>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any + Clone,
+    {
+        crate::nut::register_deferred(*self, f, Default::default())
+    }
+
+    /// Same as [subscribe](#method.subscribe) but also declares that the handler publishes messages of type `PUBLISHES`.
+    ///
+    /// The declaration is not checked at runtime, it is removed together with the subscription.
+    /// It is used by [`nuts::detect_cycles`](fn.detect_cycles.html) to find message types that could publish each other endlessly.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Ping;
+    /// struct Pong;
+    /// let activity = nuts::new_activity(());
+    /// activity.subscribe_publishing::<Ping, Pong>(|_, _| nuts::publish(Pong));
+    /// assert!(nuts::detect_cycles().is_empty());
+    /// ```
+    pub fn subscribe_publishing<MSG, PUBLISHES>(
+        &self,
+        f: impl Fn(&mut A, &MSG) + 'static,
+    ) -> SubscriptionId
+    where
+        MSG: Any,
+        PUBLISHES: Any,
+    {
+        let id = self.subscribe(f);
+        crate::nut::declare_publishes::<MSG, PUBLISHES>(id, (*self).into());
+        id
+    }
+
+    /// Registers a callback closure for messages of type `FROM`, which are converted to `INTO` before they are passed to the closure.
+    ///
+    /// This allows a handler that works on a general message type to also receive more specific messages, without publishing them twice.
+    /// Only this subscription receives the converted message, it is not published as `INTO`.
+    /// The message is cloned for the conversion, to leave it intact for other subscribers.
+    ///
+    /// The type `FROM` has to be specified explicitly, as in the example below.
+    ///
+    /// ### Example
+    /// ```rust
+    /// #[derive(Clone)]
+    /// struct ButtonClick;
+    /// enum AppEvent { Click }
+    /// impl From<ButtonClick> for AppEvent {
+    ///     fn from(_: ButtonClick) -> Self { AppEvent::Click }
+    /// }
+    ///
+    /// let activity = nuts::new_activity(0u32);
+    /// activity.subscribe_convertible::<ButtonClick, _, _>(|clicks, event: &AppEvent| match event {
+    ///     AppEvent::Click => *clicks += 1,
+    /// });
+    /// nuts::publish(ButtonClick);
+    /// assert_eq!(Some(1), activity.read(|clicks| *clicks));
+    /// ```
+    pub fn subscribe_convertible<FROM, INTO, F>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &INTO) + 'static,
+        FROM: Any + Clone + Into<INTO>,
+    {
+        self.subscribe(move |a: &mut A, msg: &FROM| f(a, &msg.clone().into()))
+    }
+
+    /// Registers a single closure for all message types wrapped by the variants of the enum `E`.
+    ///
+    /// Each message type is still published on its own.
+    /// The message is cloned and wrapped into its variant before the closure is called.
+    /// One subscription per variant is created, all of them are returned.
+    ///
+    /// See [`message_variants!`](macro.message_variants.html) for an example.
+    pub fn subscribe_variants<E, F>(&self, f: F) -> Vec<SubscriptionId>
+    where
+        E: MessageVariants,
+        F: Fn(&mut A, E) + 'static,
+    {
+        let mut subscriber = VariantSubscriber::new(*self, VariantHandler::Plain(Rc::new(f)));
+        E::subscribe_variants(&mut subscriber);
+        subscriber.into_subscriptions()
+    }
+
+    /// Same as [`subscribe_variants`](#method.subscribe_variants) but with mutable access to the `DomainState` object.
+    ///
+    /// # Panics
+    /// Panics if the activity has not been registered with a domain.
+    pub fn subscribe_domained_variants<E, F>(&self, f: F) -> Vec<SubscriptionId>
+    where
+        E: MessageVariants,
+        F: Fn(&mut A, &mut DomainState, E) + 'static,
+    {
+        let mut subscriber = VariantSubscriber::new(*self, VariantHandler::Domained(Rc::new(f)));
+        E::subscribe_variants(&mut subscriber);
+        subscriber.into_subscriptions()
+    }
+
+    /// Registers the activity's implementation of [`Handler<MSG>`](trait.Handler.html) as a subscription to messages of type `MSG`.
+    ///
+    /// This is equivalent to `subscribe(|a, msg: &MSG| a.handle(msg))`.
+    pub fn subscribe_handler<MSG>(&self) -> SubscriptionId
+    where
+        A: Handler<MSG>,
+        MSG: Any,
+    {
+        self.subscribe(|a: &mut A, msg: &MSG| a.handle(msg))
+    }
+
+    /// Registers a callback closure on an activity with a specific topic to listen to,
+    /// which is only called for messages published by the activity `sender`.
+    ///
+    /// Messages published from outside of activities are ignored by this subscription.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Announcement;
+    /// let a = nuts::new_activity(());
+    /// let b = nuts::new_activity(0u32);
+    /// a.subscribe(|_, _: &()| nuts::publish(Announcement));
+    /// b.subscribe_from(a.into(), |counter, _: &Announcement| *counter += 1);
+    ///
+    /// nuts::publish(Announcement);
+    /// nuts::publish(());
+    /// assert_eq!(Some(1), b.read(|counter| *counter));
+    /// ```
+    pub fn subscribe_from<F, MSG>(&self, sender: UncheckedActivityId, f: F) -> SubscriptionId
+    where
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        let filter = SubscriptionFilter {
+            sender: Some(sender),
+            ..Default::default()
+        };
+        crate::nut::register(*self, f, filter)
+    }
+    /// Registers a callback closure that is only called for messages that satisfy `filter`.
+    ///
+    /// The filter is checked before the activity is borrowed, a message that does not pass it is not delivered at all.
+    /// As with the default subscription filter, messages are also ignored while the activity is inactive.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct KeyEvent {
+    ///     code: u32,
+    /// }
+    /// let escape = nuts::new_activity(0u32);
+    /// escape.subscribe_filtered(
+    ///     |event: &KeyEvent| event.code == 27,
+    ///     |presses, _: &KeyEvent| *presses += 1,
+    /// );
+    /// nuts::publish(KeyEvent { code: 27 });
+    /// nuts::publish(KeyEvent { code: 13 });
+    /// assert_eq!(Some(1), escape.read(|presses| *presses));
+    /// ```
+    pub fn subscribe_filtered<P, F, MSG>(&self, filter: P, f: F) -> SubscriptionId
+    where
+        P: Fn(&MSG) -> bool + 'static,
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        self.subscribe_masked(SubscriptionFilter::with_predicate(filter), f)
+    }
+    /// Combines [`subscribe_filtered`](#method.subscribe_filtered) and [`subscribe_once`](#method.subscribe_once).
+    ///
+    /// Messages rejected by `filter` do not consume the subscription, it is removed after the first message that passes.
+    pub fn subscribe_once_filtered<P, F, MSG>(&self, filter: P, f: F) -> SubscriptionId
+    where
+        P: Fn(&MSG) -> bool + 'static,
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        let once = Rc::new(Once::default());
+        let guard = once.clone();
+        let id = self.subscribe_filtered(filter, move |a, msg| {
+            if guard.consume() {
+                f(a, msg)
+            }
+        });
+        once.attach(id)
+    }
+
     /// Registers a callback closure on an activity with a specific topic to listen to with filtering options.
-    pub fn subscribe_masked<F, MSG>(&self, mask: SubscriptionFilter, f: F)
+    pub fn subscribe_masked<F, MSG>(&self, mask: SubscriptionFilter, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &MSG) + 'static,
         MSG: Any,
@@ -218,7 +782,7 @@ impl<A: Activity> ActivityId<A> {
         crate::nut::register(*self, f, mask)
     }
     /// Same as [`subscribe_masked`](#method.subscribe_masked) but gives mutable access to the message object.
-    pub fn subscribe_masked_mut<F, MSG>(&self, mask: SubscriptionFilter, f: F)
+    pub fn subscribe_masked_mut<F, MSG>(&self, mask: SubscriptionFilter, f: F) -> SubscriptionId
     where
         F: Fn(&mut A, &mut MSG) + 'static,
         MSG: Any,
@@ -231,7 +795,11 @@ impl<A: Activity> ActivityId<A> {
     ///
     /// # Panics
     /// Panics if the activity has not been registered with a domain.
-    pub fn subscribe_domained_masked<F, MSG>(&self, mask: SubscriptionFilter, f: F)
+    pub fn subscribe_domained_masked<F, MSG>(
+        &self,
+        mask: SubscriptionFilter,
+        f: F,
+    ) -> SubscriptionId
     where
         F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
         MSG: Any,
@@ -239,21 +807,205 @@ impl<A: Activity> ActivityId<A> {
         crate::nut::register_domained(*self, f, mask)
     }
     /// Same as [`subscribe_domained_masked`](#method.subscribe_domained_masked) but gives mutable access to the message object.
-    pub fn subscribe_domained_masked_mut<F, MSG>(&self, mask: SubscriptionFilter, f: F)
+    pub fn subscribe_domained_masked_mut<F, MSG>(
+        &self,
+        mask: SubscriptionFilter,
+        f: F,
+    ) -> SubscriptionId
     where
         F: Fn(&mut A, &mut DomainState, &mut MSG) + 'static,
         MSG: Any,
     {
         crate::nut::register_domained_mut(*self, f, mask)
     }
+    /// True if the activity has a subscription to messages of type `MSG`, regardless of its status.
+    ///
+    /// Only subscriptions to the message type itself count, not those to named topics or [`subscribe_any`](#method.subscribe_any).
+    /// Can be called from inside and outside of handlers.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Resize;
+    /// let window = nuts::new_activity(());
+    /// if !window.is_subscribed::<Resize>() {
+    ///     window.subscribe(|_, _: &Resize| {});
+    /// }
+    /// assert!(window.is_subscribed::<Resize>());
+    /// ```
+    pub fn is_subscribed<MSG: Any>(&self) -> bool {
+        crate::nut::is_subscribed::<MSG>((*self).into())
+    }
 
     /// Changes the lifecycle status of the activity
     ///
+    /// When called from inside an activity, the change is applied once the current message has been delivered to all subscribers.
+    ///
     /// # Panics
     /// If status is set to Deleted more than once
     pub fn set_status(&self, status: LifecycleStatus) {
         crate::nut::set_status((*self).into(), status);
     }
+    /// Same as `set_status(LifecycleStatus::Active)`
+    pub fn activate(&self) {
+        self.set_status(LifecycleStatus::Active);
+    }
+    /// Same as `set_status(LifecycleStatus::Inactive)`
+    pub fn deactivate(&self) {
+        self.set_status(LifecycleStatus::Inactive);
+    }
+    /// The current lifecycle status of the activity.
+    ///
+    /// Status changes that have been requested inside an activity but not applied yet are not reflected.
+    /// Deleted activities keep reporting `LifecycleStatus::Deleted`.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use nuts::LifecycleStatus;
+    /// struct Pause;
+    /// let activity = nuts::new_activity(());
+    /// activity.subscribe(move |_, _: &Pause| {
+    ///     activity.deactivate();
+    ///     // Applied after the message has been delivered
+    ///     assert_eq!(LifecycleStatus::Active, activity.status());
+    /// });
+    /// nuts::publish(Pause);
+    /// assert_eq!(LifecycleStatus::Inactive, activity.status());
+    /// activity.activate();
+    /// assert_eq!(LifecycleStatus::Active, activity.status());
+    /// ```
+    pub fn status(&self) -> LifecycleStatus {
+        crate::nut::status((*self).into())
+    }
+
+    /// Deletes the activity, drops its private data, and removes all its subscriptions.
+    ///
+    /// If the activity is active, its `on_leave` subscriptions are called before it is deleted.
+    /// When called from inside an activity, including a handler of the activity itself, the deletion is deferred until the current message has been delivered to all subscribers.
+    /// Messages addressed to a deleted activity, e.g. with [`nuts::publish_to`](fn.publish_to.html), are ignored.
+    /// Deleting an activity that has already been deleted has no effect.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Close;
+    /// let dialog = nuts::new_activity(());
+    /// dialog.subscribe(move |_, _: &Close| dialog.delete());
+    /// nuts::publish(Close);
+    /// assert!(dialog.read(|_| ()).is_none());
+    /// ```
+    pub fn delete(self) {
+        let id: UncheckedActivityId = self.into();
+        crate::nut::unregister_by_predicate(Box::new(move |other| other == id));
+    }
+
+    /// Creates a handle that only gives access to the activity for as long as it has not been deleted.
+    ///
+    /// ### Example
+    /// ```rust
+    /// let cached = nuts::new_activity(0u32).downgrade();
+    /// let activity = cached.upgrade().expect("not deleted yet");
+    /// activity.delete();
+    /// assert!(cached.upgrade().is_none());
+    /// ```
+    pub fn downgrade(&self) -> WeakActivityId<A> {
+        WeakActivityId { id: *self }
+    }
+
+    /// Grants read-only access to the activity's private data from outside of its handlers.
+    ///
+    /// This can also be used inside handlers of other activities, e.g. to observe the state of an activity upon receiving a message.
+    ///
+    /// Returns `None` if the activity is currently borrowed mutably, which is the case while one of its own handlers is executing.
+    /// `None` is also returned if the activity has been deleted.
+    ///
+    /// The closure `f` should not publish messages that the same activity has subscribed to, as the activity is still borrowed when the message is delivered.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Counter(usize);
+    /// let counter = nuts::new_activity(Counter(0));
+    /// counter.subscribe(|counter, _msg: &()| counter.0 += 1);
+    /// nuts::publish(());
+    /// assert_eq!(Some(1), counter.read(|counter| counter.0));
+    /// ```
+    pub fn read<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&A) -> R,
+    {
+        crate::nut::read_activity(*self, f)
+    }
+
+    /// Same as [read](#method.read), but panics instead of returning `None`.
+    ///
+    /// Use it where the activity is known to exist and to be idle, e.g. to inspect its state from outside of handlers.
+    ///
+    /// # Panics
+    /// Panics if the activity has been deleted or if it is currently borrowed, which is the case while one of its own handlers is executing.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Counter(usize);
+    /// let counter = nuts::new_activity(Counter(0));
+    /// counter.subscribe(|counter, _msg: &()| counter.0 += 1);
+    /// nuts::publish(());
+    /// assert_eq!(1, counter.with(|counter| counter.0));
+    /// ```
+    pub fn with<R>(&self, f: impl FnOnce(&A) -> R) -> R {
+        crate::nut::with_activity(*self, f)
+    }
+
+    /// Same as [with](#method.with), but grants mutable access to the activity's private data.
+    ///
+    /// # Panics
+    /// Panics if the activity has been deleted or if it is currently borrowed, which is the case while one of its own handlers is executing.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Counter(usize);
+    /// let counter = nuts::new_activity(Counter(0));
+    /// counter.with_mut(|counter| counter.0 = 10);
+    /// assert_eq!(10, counter.with(|counter| counter.0));
+    /// ```
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut A) -> R) -> R {
+        crate::nut::with_activity_mut(*self, f)
+    }
+    /// Registers a method under `name`, which can then be called with [`call_method`](#method.call_method).
+    ///
+    /// A method added with a name that is already registered on this activity replaces the previous one.
+    /// Methods are removed when the activity is deleted.
+    pub fn add_method(&self, name: &str, method: Method<A>) {
+        crate::nut::add_method(*self, name, method)
+    }
+    /// Calls the method registered under `name` immediately, without publishing a message.
+    ///
+    /// The method has access to the domain of the activity.
+    /// Messages it publishes are delivered before this function returns.
+    ///
+    /// # Errors
+    /// Returns [`NutsError::UnknownMethod`](enum.NutsError.html#variant.UnknownMethod) if no method with that name has been added to the activity.
+    ///
+    /// # Panics
+    /// Panics if called from inside an activity or if the activity has been deleted.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use nuts::{DefaultDomain, Method};
+    /// struct Reset;
+    /// let counter = nuts::new_domained_activity(5u32, &DefaultDomain);
+    /// counter.add_method(
+    ///     "reset",
+    ///     Method::new(|count, domain| {
+    ///         *count = 0;
+    ///         domain.unwrap().store(Reset);
+    ///     }),
+    /// );
+    ///
+    /// counter.call_method("reset").unwrap();
+    /// assert_eq!(0, counter.with(|count| *count));
+    /// assert_eq!(Err(nuts::NutsError::UnknownMethod), counter.call_method("undo"));
+    /// ```
+    pub fn call_method(&self, name: &str) -> Result<(), NutsError> {
+        crate::nut::call_method(*self, name)
+    }
 }
 
 impl UncheckedActivityId {
@@ -273,8 +1025,53 @@ impl<A> Clone for ActivityId<A> {
     }
 }
 
-impl<A> Into<UncheckedActivityId> for ActivityId<A> {
-    fn into(self) -> UncheckedActivityId {
-        self.id
+impl<A> Copy for WeakActivityId<A> {}
+impl<A> Clone for WeakActivityId<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: Activity> WeakActivityId<A> {
+    /// Returns the handle to the activity, or `None` if the activity has been deleted.
+    ///
+    /// A deletion requested from inside a handler only takes effect once the handler has returned.
+    pub fn upgrade(&self) -> Option<ActivityId<A>> {
+        if self.id.status() == LifecycleStatus::Deleted {
+            None
+        } else {
+            Some(self.id)
+        }
+    }
+}
+
+impl<A> From<ActivityId<A>> for UncheckedActivityId {
+    fn from(id: ActivityId<A>) -> Self {
+        id.id
+    }
+}
+
+/// Shared state of a subscription created with `subscribe_once`
+#[derive(Default)]
+pub(crate) struct Once {
+    id: Cell<Option<SubscriptionId>>,
+    consumed: Cell<bool>,
+}
+
+impl Once {
+    fn attach(self: Rc<Self>, id: SubscriptionId) -> SubscriptionId {
+        self.id.set(Some(id));
+        crate::nut::set_once(id, self);
+        id
+    }
+    /// Returns true on the first call only and removes the subscription
+    pub(crate) fn consume(&self) -> bool {
+        if self.consumed.replace(true) {
+            return false;
+        }
+        if let Some(id) = self.id.get() {
+            crate::nut::unsubscribe(id);
+        }
+        true
     }
 }
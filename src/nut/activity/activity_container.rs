@@ -4,23 +4,48 @@ use super::*;
 ///
 /// Needs stores a list of dynamic `Any` trait objects, not `Activity` because
 /// trait objects only allow access to methods of that trait, not their super-traits.  
+///
+/// Each activity is wrapped in its own `RefCell`, such that the container itself
+/// only needs to be borrowed immutably while a handler runs.
+/// This allows read access to other activities during a broadcast.
 #[derive(Default)]
 pub(crate) struct ActivityContainer {
-    data: Vec<Option<Box<dyn Any>>>,
+    data: Vec<Option<RefCell<Box<dyn Any>>>>,
     active: Vec<LifecycleStatus>,
+    domains: Vec<DomainId>,
+    type_names: Vec<&'static str>,
+    /// Set for activities added with `add_shared`, for handlers that do not know the activity type
+    shared_access: Vec<Option<SharedAccess>>,
     on_delete: Vec<OnDelete>,
+    on_status_change: Vec<Vec<StatusChangeFn>>,
 }
 
+/// Called with the old and the new status, see `ActivityId::on_status_change`
+pub(crate) type StatusChangeFn = Rc<dyn Fn(LifecycleStatus, LifecycleStatus)>;
+
 enum OnDelete {
     None,
     Simple(Box<dyn FnOnce(Box<dyn Any>)>),
-    WithDomain(Box<dyn FnOnce(Box<dyn Any>, &mut ManagedState)>),
+    WithDomain(DomainedOnDelete),
 }
 
-/// Handlers stored per Activity
-#[derive(Default)]
-pub(crate) struct ActivityHandlerContainer {
-    data: HashMap<usize, Vec<Handler>>,
+type DomainedOnDelete = Box<dyn FnOnce(Box<dyn Any>, &mut ManagedState)>;
+
+/// Calls the closure with the activity inside the `Rc<RefCell<A>>` of a shared activity.
+/// Returns false without calling it if the activity is borrowed outside of nuts.
+type SharedAccess = fn(&dyn Any, &mut dyn FnMut(&mut dyn Any)) -> bool;
+
+fn shared_access<A: Activity>(shared: &dyn Any, f: &mut dyn FnMut(&mut dyn Any)) -> bool {
+    let shared = shared
+        .downcast_ref::<Rc<RefCell<A>>>()
+        .expect("Wrong activity");
+    match shared.try_borrow_mut() {
+        Ok(mut a) => {
+            f(&mut *a);
+            true
+        }
+        Err(_) => false,
+    }
 }
 
 impl ActivityContainer {
@@ -31,11 +56,151 @@ impl ActivityContainer {
         status: LifecycleStatus,
     ) -> ActivityId<A> {
         let i = self.data.len();
-        self.data.push(Some(Box::new(a)));
+        self.data.push(Some(RefCell::new(Box::new(a))));
         self.active.push(status);
+        self.domains.push(domain);
+        self.type_names.push(std::any::type_name::<A>());
+        self.shared_access.push(None);
         self.on_delete.push(OnDelete::None);
+        self.on_status_change.push(Vec::new());
         ActivityId::new(i, domain)
     }
+    /// Adds an activity that is also owned outside of nuts, see `nuts::new_shared_activity`
+    pub(crate) fn add_shared<A: Activity>(
+        &mut self,
+        shared: Rc<RefCell<A>>,
+        domain: DomainId,
+        status: LifecycleStatus,
+    ) -> ActivityId<A> {
+        let id = self.add(shared, domain, status);
+        self.type_names[id.id.index] = std::any::type_name::<A>();
+        self.shared_access[id.id.index] = Some(shared_access::<A>);
+        ActivityId::new(id.id.index, domain)
+    }
+    /// Shared access to an activity.
+    /// Returns `None` if the activity has been deleted or if it is currently borrowed mutably,
+    /// e.g. because one of its handlers is executing or because a shared activity is borrowed outside of nuts.
+    pub(crate) fn try_read<A: Activity, R>(
+        &self,
+        id: ActivityId<A>,
+        f: impl FnOnce(&A) -> R,
+    ) -> Option<R> {
+        let a = self.data[id.id.index].as_ref()?.try_borrow().ok()?;
+        if let Some(a) = a.downcast_ref::<A>() {
+            return Some(f(a));
+        }
+        let shared = a.downcast_ref::<Rc<RefCell<A>>>().expect("Wrong activity"); // deleted and replaced?
+        let a = shared.try_borrow().ok()?;
+        Some(f(&a))
+    }
+    /// Returns false if the activity has been deleted.
+    pub(crate) fn contains(&self, id: UncheckedActivityId) -> bool {
+        self.data[id.index].is_some()
+    }
+    /// Number of activities that have not been deleted
+    pub(crate) fn len(&self) -> usize {
+        self.data.iter().filter(|a| a.is_some()).count()
+    }
+    /// All activities that have not been deleted together with their domain, in order of creation.
+    pub(crate) fn domains(&self) -> impl Iterator<Item = (UncheckedActivityId, DomainId)> + '_ {
+        (0..self.data.len())
+            .map(|index| UncheckedActivityId { index })
+            .filter(move |id| self.contains(*id))
+            .map(move |id| (id, self.domains[id.index]))
+    }
+    /// All activities in the domain that have not been deleted, in order of creation.
+    pub(crate) fn ids_in_domain(&self, domain: DomainId) -> Vec<UncheckedActivityId> {
+        (0..self.data.len())
+            .map(|index| UncheckedActivityId { index })
+            .filter(|id| self.domains[id.index] == domain && self.contains(*id))
+            .collect()
+    }
+    /// Exclusive access to an activity.
+    /// Returns `None` if the activity has been deleted or if it is currently borrowed,
+    /// e.g. because one of its handlers is executing or because a shared activity is borrowed outside of nuts.
+    pub(crate) fn try_write<A: Activity, R>(
+        &self,
+        id: ActivityId<A>,
+        f: impl FnOnce(&mut A) -> R,
+    ) -> Option<R> {
+        let mut a = self.data[id.id.index].as_ref()?.try_borrow_mut().ok()?;
+        if let Some(a) = a.downcast_mut::<A>() {
+            return Some(f(a));
+        }
+        let shared = a.downcast_ref::<Rc<RefCell<A>>>().expect("Wrong activity"); // deleted and replaced?
+        let mut a = shared.try_borrow_mut().ok()?;
+        Some(f(&mut a))
+    }
+    /// Exclusive access to an activity for a handler.
+    ///
+    /// Unlike `try_write`, this panics if the activity is borrowed by nuts already.
+    /// Only shared activities that are borrowed outside of nuts are skipped, returning `None` without calling `f`.
+    pub(crate) fn handle<A: Activity, R>(
+        &self,
+        id: ActivityId<A>,
+        f: impl FnOnce(&mut A) -> R,
+    ) -> Option<R> {
+        let mut a = self[id].borrow_mut();
+        if let Some(a) = a.downcast_mut::<A>() {
+            return Some(f(a));
+        }
+        let shared = a.downcast_ref::<Rc<RefCell<A>>>().expect("Wrong activity"); // deleted and replaced?
+        let result = match shared.try_borrow_mut() {
+            Ok(mut a) => Some(f(&mut a)),
+            Err(_) => self.skip_borrowed_shared(id.id),
+        };
+        result
+    }
+    /// Same as `handle`, for handlers that only know the activity as `dyn Any`.
+    ///
+    /// Shared activities are unwrapped, such that the handler always receives the activity itself.
+    /// Returns false if the call has been skipped.
+    pub(crate) fn handle_untyped(
+        &self,
+        id: UncheckedActivityId,
+        f: impl FnOnce(&mut dyn Any),
+    ) -> bool {
+        let mut a = self[id].borrow_mut();
+        let access = match self.shared_access[id.index] {
+            Some(access) => access,
+            None => {
+                f(a.as_mut());
+                return true;
+            }
+        };
+        let mut f = Some(f);
+        let mut call = |a: &mut dyn Any| {
+            if let Some(f) = f.take() {
+                f(a)
+            }
+        };
+        let called = access(a.as_ref(), &mut call);
+        if !called {
+            self.skip_borrowed_shared::<()>(id);
+        }
+        called
+    }
+    fn skip_borrowed_shared<R>(&self, id: UncheckedActivityId) -> Option<R> {
+        #[cfg(debug_assertions)]
+        eprintln!(
+            "nuts: skipped a handler of `{}`, the shared activity is borrowed outside of nuts",
+            self.type_names[id.index]
+        );
+        #[cfg(not(debug_assertions))]
+        let _ = id;
+        None
+    }
+    /// The typed id of an activity, `None` if it has been deleted or if it is not of type `A`
+    pub(crate) fn typed_id<A: Activity>(&self, id: UncheckedActivityId) -> Option<ActivityId<A>> {
+        (self.contains(id) && self.type_names[id.index] == std::any::type_name::<A>())
+            .then(|| ActivityId::new(id.index, self.domains[id.index]))
+    }
+    pub(crate) fn domain(&self, id: UncheckedActivityId) -> DomainId {
+        self.domains[id.index]
+    }
+    pub(crate) fn type_name(&self, id: UncheckedActivityId) -> &'static str {
+        self.type_names[id.index]
+    }
     pub(crate) fn status(&self, id: UncheckedActivityId) -> LifecycleStatus {
         self.active[id.index]
     }
@@ -49,64 +214,80 @@ impl ActivityContainer {
     ) {
         self.on_delete[id.index] = OnDelete::Simple(f);
     }
-    pub(crate) fn add_domained_on_delete(
-        &mut self,
-        id: UncheckedActivityId,
-        f: Box<dyn FnOnce(Box<dyn Any>, &mut ManagedState)>,
-    ) {
+    pub(crate) fn add_domained_on_delete(&mut self, id: UncheckedActivityId, f: DomainedOnDelete) {
         self.on_delete[id.index] = OnDelete::WithDomain(f);
     }
-    pub(crate) fn delete(&mut self, id: UncheckedActivityId, managed_state: &mut ManagedState) {
+    pub(crate) fn add_on_status_change(&mut self, id: UncheckedActivityId, f: StatusChangeFn) {
+        self.on_status_change[id.index].push(f);
+    }
+    /// Callbacks to be called after the status of the activity changed, in order of registration
+    pub(crate) fn on_status_change(&self, id: UncheckedActivityId) -> Vec<StatusChangeFn> {
+        self.on_status_change[id.index].clone()
+    }
+    /// Takes the activity out of the container, see `DeletedActivity::finish` for dropping it.
+    pub(crate) fn delete(&mut self, id: UncheckedActivityId) -> DeletedActivity {
+        self.on_status_change[id.index].clear();
         let activity = self.data[id.index]
             .take()
-            .expect("Trying to delete a second time");
+            .expect("Trying to delete a second time")
+            .into_inner();
         // Taking ownership to call FnOnce
         let mut on_delete = OnDelete::None;
         std::mem::swap(&mut on_delete, &mut self.on_delete[id.index]);
-        match on_delete {
-            OnDelete::None => panic!("bug in on delete handling"),
-            OnDelete::Simple(f) => f(activity),
-            OnDelete::WithDomain(f) => f(activity, managed_state),
+        DeletedActivity {
+            activity,
+            on_delete,
         }
     }
 }
 
-impl<A: Activity> Index<ActivityId<A>> for ActivityContainer {
-    type Output = dyn Any;
-    fn index(&self, id: ActivityId<A>) -> &Self::Output {
-        self.data[id.id.index]
-            .as_ref()
-            .expect("Missing activity")
-            .as_ref()
-    }
+/// An activity that has been taken out of the container, together with its `on_delete` callback
+pub(crate) struct DeletedActivity {
+    activity: Box<dyn Any>,
+    on_delete: OnDelete,
 }
-impl<A: Activity> IndexMut<ActivityId<A>> for ActivityContainer {
-    fn index_mut(&mut self, id: ActivityId<A>) -> &mut Self::Output {
-        self.data[id.id.index]
-            .as_mut()
-            .expect("Missing activity")
-            .as_mut()
+
+impl DeletedActivity {
+    /// Calls the `on_delete` callback, or drops the activity if there is none.
+    ///
+    /// Call it only after releasing the borrow of the activities, `Drop` implementations may publish messages.
+    /// The managed state is only borrowed for callbacks that need the domain.
+    pub(crate) fn finish(self, managed_state: &RefCell<ManagedState>) {
+        match self.on_delete {
+            OnDelete::None => drop(self.activity),
+            OnDelete::Simple(f) => f(self.activity),
+            OnDelete::WithDomain(f) => f(
+                self.activity,
+                &mut managed_state.try_borrow_mut().expect(
+                    "Bug: This should not be possible to trigger from outside the library.",
+                ),
+            ),
+        }
     }
 }
 
-impl ActivityHandlerContainer {
-    pub fn iter(&self) -> impl Iterator<Item = &Handler> {
-        self.data.values().flat_map(|f| f.iter())
-    }
-    pub fn iter_for(&self, id: UncheckedActivityId) -> impl Iterator<Item = &Handler> {
-        self.data.get(&id.index).into_iter().flat_map(|f| f.iter())
+/// Takes the activity out of its box, or out of its `Rc` if it is a shared activity.
+/// Returns `None` for shared activities that are still referenced outside of nuts.
+pub(crate) fn into_activity<A: Activity>(a: Box<dyn Any>) -> Option<A> {
+    match a.downcast::<A>() {
+        Ok(a) => Some(*a),
+        Err(a) => {
+            let shared = a.downcast::<Rc<RefCell<A>>>().expect("Wrong activity"); // deleted and replaced?
+            Rc::try_unwrap(*shared).ok().map(RefCell::into_inner)
+        }
     }
 }
-impl<A: Activity> Index<ActivityId<A>> for ActivityHandlerContainer {
-    type Output = Vec<Handler>;
-    fn index(&self, id: ActivityId<A>) -> &Self::Output {
-        &self.data[&id.id.index]
+
+impl Index<UncheckedActivityId> for ActivityContainer {
+    type Output = RefCell<Box<dyn Any>>;
+    fn index(&self, id: UncheckedActivityId) -> &Self::Output {
+        self.data[id.index].as_ref().expect("Missing activity")
     }
 }
-impl<A: Activity> IndexMut<ActivityId<A>> for ActivityHandlerContainer {
-    fn index_mut(&mut self, id: ActivityId<A>) -> &mut Self::Output {
-        self.data
-            .entry(id.id.index)
-            .or_insert_with(Default::default)
+
+impl<A: Activity> Index<ActivityId<A>> for ActivityContainer {
+    type Output = RefCell<Box<dyn Any>>;
+    fn index(&self, id: ActivityId<A>) -> &Self::Output {
+        self.data[id.id.index].as_ref().expect("Missing activity")
     }
 }
@@ -0,0 +1,36 @@
+use core::any::Any;
+
+/// Message handler implemented as a method of the activity.
+///
+/// This is an alternative to closures, register it with [`subscribe_handler`](struct.ActivityId.html#method.subscribe_handler).
+/// An activity can implement `Handler` for as many message types as it wants.
+///
+/// ### Example
+/// ```rust
+/// use nuts::Handler;
+/// struct Player { health: u32 }
+/// struct Damage(u32);
+/// struct Heal(u32);
+///
+/// impl Handler<Damage> for Player {
+///     fn handle(&mut self, msg: &Damage) {
+///         self.health = self.health.saturating_sub(msg.0);
+///     }
+/// }
+/// impl Handler<Heal> for Player {
+///     fn handle(&mut self, msg: &Heal) {
+///         self.health += msg.0;
+///     }
+/// }
+///
+/// let player = nuts::new_activity(Player { health: 10 });
+/// player.subscribe_handler::<Damage>();
+/// player.subscribe_handler::<Heal>();
+/// nuts::publish(Damage(5));
+/// nuts::publish(Heal(2));
+/// assert_eq!(Some(7), player.read(|p| p.health));
+/// ```
+pub trait Handler<MSG: Any> {
+    /// Called for each published message of type `MSG`.
+    fn handle(&mut self, msg: &MSG);
+}
@@ -1,16 +1,21 @@
 use super::*;
+use crate::nut::exec::{ActivityPredicate, Deferred};
 use crate::nut::Nut;
 
 // @ START-DOC ACTIVITY_LIFECYCLE
 /// Each activity has a lifecycle status that can be changed using [`set_status`](struct.ActivityId.html#method.set_status).
 /// It starts with `LifecycleStatus::Active`.
-/// In the current version of Nuts, the only other status is `LifecycleStatus::Inactive`.
+/// The other statuses are `LifecycleStatus::Inactive`, `LifecycleStatus::Suspended`, and `LifecycleStatus::Deleted`.
 ///
 /// The inactive status can be used to put activities to sleep temporarily.
 /// While inactive, the activity will not be notified of events it has subscribed to.
 /// A subscription filter can been used to change this behavior.
 /// (See [`subscribe_masked`](struct.ActivityId.html#method.subscribe_masked))
 ///
+/// The suspended status behaves like the inactive status but is meant for activities in the background, which should release cached resources.
+/// When an activity becomes suspended, its [`on_suspend`](struct.ActivityId.html#method.on_suspend) subscriptions are called.
+/// When it changes from suspended to another status (except deleted), its [`on_resume`](struct.ActivityId.html#method.on_resume) subscriptions are called.
+///
 /// If the status of a changes from active to inactive, the activity's [`on_leave`](struct.ActivityId.html#method.on_leave) and [`on_leave_domained`](struct.ActivityId.html#method.on_leave_domained) subscriptions will be called.
 ///
 /// If the status of a changes from inactive to active, the activity's [`on_enter`](struct.ActivityId.html#method.on_enter) and [`on_enter_domained`](struct.ActivityId.html#method.on_enter_domained) subscriptions will be called.
@@ -23,11 +28,32 @@ pub enum LifecycleStatus {
     Active,
     /// Inactive / Sleeping
     Inactive,
+    /// Inactive in the background, with cached resources released
+    Suspended,
     /// Mark for deletion, the activity will be removed and `on_delete` called on it.
     /// Setting to this state twice will cause panics.
     Deleted,
 }
 
+/// Defines what happens to messages published while activities or domain data are dropped by the library.
+///
+/// This affects publish calls from `Drop` implementations that run when activities are deleted or domains are cleared.
+/// Set it with [`nuts::set_drop_publish_policy`](fn.set_drop_publish_policy.html).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum DropPublishPolicy {
+    /// The message is queued and delivered after the teardown has completed. This is the default.
+    #[default]
+    Enqueue,
+    /// The message is silently dropped.
+    Ignore,
+    /// Publishing panics, to find the `Drop` implementation that published.
+    Panic,
+}
+
+/// Callback registered with `nuts::register_teardown`
+pub(crate) type TeardownFn = Box<dyn FnOnce()>;
+
 pub(crate) struct LifecycleChange {
     activity: UncheckedActivityId,
     status: LifecycleStatus,
@@ -42,6 +68,7 @@ impl LifecycleStatus {
         match self {
             Self::Active => true,
             Self::Inactive => false,
+            Self::Suspended => false,
             Self::Deleted => false,
         }
     }
@@ -68,22 +95,161 @@ impl Nut {
                 .try_borrow_mut()
                 .expect("Bug: This should not be possible to trigger from outside the library.")
                 .set_status(lifecycle_change.activity, lifecycle_change.status);
-            if !before.is_active() && lifecycle_change.status.is_active() {
-                self.publish_local(lifecycle_change.activity, Topic::enter(), ());
-            } else if before.is_active() && !lifecycle_change.status.is_active() {
-                self.publish_local(lifecycle_change.activity, Topic::leave(), ());
+            let after = lifecycle_change.status;
+            let callbacks = self
+                .activities
+                .try_borrow()
+                .expect("Bug: This should not be possible to trigger from outside the library.")
+                .on_status_change(lifecycle_change.activity);
+            for f in callbacks {
+                f(before, after);
+            }
+            // Lifecycle events skip the queue, such that no message that is already queued
+            // reaches the activity in its new status before the lifecycle subscriptions have run.
+            let id = lifecycle_change.activity;
+            if before == LifecycleStatus::Suspended && after != LifecycleStatus::Deleted {
+                self.deliver_lifecycle_event(id, Topic::resume());
+            }
+            if before.is_active() && !after.is_active() {
+                self.deliver_lifecycle_event(id, Topic::leave());
+            } else if !before.is_active() && after.is_active() {
+                self.deliver_lifecycle_event(id, Topic::enter());
+            }
+            if after == LifecycleStatus::Suspended {
+                self.deliver_lifecycle_event(id, Topic::suspend());
             }
         }
         if lifecycle_change.status == LifecycleStatus::Deleted {
-            self.activities
+            self.remove_methods(lifecycle_change.activity);
+            self.tear_down(|| {
+                let deleted = self
+                    .activities
+                    .try_borrow_mut()
+                    .expect("Bug: This should not be possible to trigger from outside the library.")
+                    .delete(lifecycle_change.activity);
+                deleted.finish(&self.managed_state);
+            });
+        }
+    }
+    pub(crate) fn clear_domain(&self, domain: DomainId) {
+        self.deferred_events.push(Deferred::ClearDomain(domain));
+        self.catch_up_deferred_to_quiescence();
+    }
+    pub(crate) fn unregister(&self, predicate: ActivityPredicate) {
+        self.deferred_events.push(Deferred::Unregister(predicate));
+        self.catch_up_deferred_to_quiescence();
+    }
+    pub(crate) fn unsubscribe(&self, id: SubscriptionId) {
+        self.deferred_events.push(Deferred::Unsubscribe(id));
+        self.catch_up_deferred_to_quiescence();
+    }
+    /// only access after locking with executing flag
+    pub(crate) fn unchecked_unsubscribe(&self, id: SubscriptionId) {
+        let mut companion = None;
+        if let Some(handlers) = self
+            .subscriptions
+            .try_borrow_mut()
+            .expect("Bug: This should not be possible to trigger from outside the library.")
+            .get_mut(&id.topic)
+        {
+            companion = handlers.get_mut(id.index).and_then(|sub| sub.companion());
+            handlers.remove(id.index);
+        }
+        self.publish_graph.borrow_mut().remove_subscription(id);
+        if let Some(companion) = companion {
+            self.unchecked_unsubscribe(companion);
+        }
+    }
+    /// only access after locking with executing flag
+    pub(crate) fn unchecked_unregister(&self, predicate: &ActivityPredicate) {
+        let ids: Vec<_> = self
+            .activities
+            .try_borrow()
+            .expect("Bug: This should not be possible to trigger from outside the library.")
+            .domains()
+            .map(|(id, _)| id)
+            .filter(|id| predicate(*id))
+            .collect();
+        for activity in &ids {
+            self.unchecked_lifecycle_change(&LifecycleChange {
+                activity: *activity,
+                status: LifecycleStatus::Deleted,
+            });
+        }
+        self.remove_subscriptions_of(&ids);
+    }
+    /// Removes all subscriptions of deleted activities
+    fn remove_subscriptions_of(&self, ids: &[UncheckedActivityId]) {
+        for handlers in self
+            .subscriptions
+            .try_borrow_mut()
+            .expect("Bug: This should not be possible to trigger from outside the library.")
+            .values_mut()
+        {
+            handlers.remove_activities(ids);
+        }
+        self.publish_graph.borrow_mut().remove_activities(ids);
+    }
+    /// only access after locking with executing flag
+    pub(crate) fn unchecked_clear_domain(&self, domain: DomainId) {
+        let ids = self
+            .activities
+            .try_borrow()
+            .expect("Bug: This should not be possible to trigger from outside the library.")
+            .ids_in_domain(domain);
+        for activity in &ids {
+            self.unchecked_lifecycle_change(&LifecycleChange {
+                activity: *activity,
+                status: LifecycleStatus::Deleted,
+            });
+        }
+        self.remove_subscriptions_of(&ids);
+        self.tear_down(|| {
+            self.managed_state
                 .try_borrow_mut()
                 .expect("Bug: This should not be possible to trigger from outside the library.")
-                .delete(
-                    lifecycle_change.activity,
-                    &mut self.managed_state.try_borrow_mut().expect(
-                        "Bug: This should not be possible to trigger from outside the library.",
-                    ),
-                );
+                .clear_domain(domain)
+        });
+    }
+    pub(crate) fn register_teardown(&self, priority: i32, f: TeardownFn) {
+        self.teardowns.borrow_mut().push((priority, f));
+    }
+    pub(crate) fn shutdown(&self) {
+        assert!(
+            !self.is_executing(),
+            "Calling `nuts::shutdown` from inside an activity is not allowed."
+        );
+        let mut teardowns = std::mem::take(&mut *self.teardowns.borrow_mut());
+        // Stable sort, equal priorities keep the registration order
+        teardowns.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+        for (_, f) in teardowns {
+            f();
+        }
+        self.unregister(Box::new(|_| true));
+        self.tear_down(|| {
+            self.managed_state
+                .try_borrow_mut()
+                .expect("Bug: This should not be possible to trigger from outside the library.")
+                .clear_all_domains()
+        });
+    }
+    /// Runs `f` with publishing governed by the drop publish policy
+    fn tear_down(&self, f: impl FnOnce()) {
+        let before = self.tearing_down.replace(true);
+        f();
+        self.tearing_down.set(before);
+    }
+    /// Returns false if the message must be dropped
+    pub(crate) fn check_drop_publish(&self, type_name: &'static str) -> bool {
+        if !self.tearing_down.get() {
+            return true;
+        }
+        match self.drop_publish_policy.get() {
+            DropPublishPolicy::Enqueue => true,
+            DropPublishPolicy::Ignore => false,
+            DropPublishPolicy::Panic => {
+                panic!("Published {} while dropping library owned data", type_name)
+            }
         }
     }
 }
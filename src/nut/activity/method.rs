@@ -0,0 +1,68 @@
+use crate::nut::{borrow_activities_for, panic_borrowed, Nut};
+use crate::*;
+use core::any::Any;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A method on an activity. Can be registered dynamically on activities at runtime.
+///
+/// Register it with [`add_method`](struct.ActivityId.html#method.add_method) and call it with [`call_method`](struct.ActivityId.html#method.call_method).
+/// The closure receives the domain of the activity, `None` if the activity has no domain.
+pub struct Method<ACTIVITY>(MethodFn<ACTIVITY>);
+
+type MethodFn<A> = Box<dyn Fn(&mut A, Option<&mut DomainState>)>;
+
+/// Methods of all activities, by activity and name
+pub(crate) type MethodRegistry = HashMap<UncheckedActivityId, HashMap<String, Rc<dyn Any>>>;
+
+impl<ACTIVITY> Method<ACTIVITY> {
+    /// Wraps a closure to be registered as method.
+    pub fn new(f: impl Fn(&mut ACTIVITY, Option<&mut DomainState>) + 'static) -> Self {
+        Self(Box::new(f))
+    }
+}
+
+impl Nut {
+    pub(crate) fn add_method<A: Activity>(&self, id: ActivityId<A>, name: &str, method: Method<A>) {
+        self.methods
+            .borrow_mut()
+            .entry(id.into())
+            .or_default()
+            .insert(name.to_owned(), Rc::new(method));
+    }
+    pub(crate) fn call_method<A: Activity>(
+        &self,
+        id: ActivityId<A>,
+        name: &str,
+    ) -> Result<(), NutsError> {
+        let method = self
+            .methods
+            .borrow()
+            .get(&id.into())
+            .and_then(|methods| methods.get(name))
+            .cloned()
+            .ok_or(NutsError::UnknownMethod)?
+            .downcast::<Method<A>>()
+            .expect("Bug: method registered for another activity type");
+        self.execute_directly(|| {
+            let outer = self.current_activity.replace(Some(id.into()));
+            let activities = borrow_activities_for(self, id);
+            let mut managed_state = self
+                .managed_state
+                .try_borrow_mut()
+                .expect("Bug: This should not be possible to trigger from outside the library.");
+            activities
+                .try_write(id, |a| {
+                    (method.0)(a, managed_state.get_mut(id.domain_index))
+                })
+                .unwrap_or_else(|| panic_borrowed::<A>());
+            managed_state.end_dispatch(id.into());
+            self.current_activity.set(outer);
+        })
+        .expect("Methods can only be called from outside of activities.");
+        Ok(())
+    }
+    pub(crate) fn remove_methods(&self, id: UncheckedActivityId) {
+        self.methods.borrow_mut().remove(&id);
+    }
+}
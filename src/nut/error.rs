@@ -0,0 +1,44 @@
+use core::fmt;
+
+/// Errors returned by the fallible functions of Nuts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NutsError {
+    /// The thread-local storage that holds all state of Nuts cannot be accessed.
+    ///
+    /// This happens when Nuts is used while the current thread is shutting down,
+    /// for example from a `Drop` implementation of an activity or of a value stored in another thread-local.
+    ///
+    /// On the web, Nuts is usually used from the single main thread, which is never shut down.
+    /// But some wasm configurations with threading support initialize and destroy thread-locals differently,
+    /// which can make the storage unavailable unexpectedly.
+    StorageUnavailable,
+    /// Ordering constraints between subscriptions contradict each other.
+    OrderingCycle,
+    /// A [`DomainView`](struct.DomainView.html) does not grant access to the type.
+    TypeNotAllowed,
+    /// No activity has subscribed to a [`RequiredMessage`](trait.RequiredMessage.html).
+    Unhandled,
+    /// The activity has no method registered under the name, see [`ActivityId::call_method`](struct.ActivityId.html#method.call_method).
+    UnknownMethod,
+}
+
+impl fmt::Display for NutsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StorageUnavailable => write!(
+                f,
+                "The thread-local storage of Nuts is not available, the thread is probably shutting down."
+            ),
+            Self::OrderingCycle => write!(
+                f,
+                "The subscription order cannot be satisfied, the constraints form a cycle."
+            ),
+            Self::TypeNotAllowed => write!(f, "The domain view does not grant access to the type."),
+            Self::Unhandled => write!(f, "Nobody subscribed to the required message."),
+            Self::UnknownMethod => write!(f, "The activity has no method with this name."),
+        }
+    }
+}
+
+impl std::error::Error for NutsError {}
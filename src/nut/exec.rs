@@ -1,13 +1,28 @@
 use crate::nut::activity::LifecycleChange;
+use crate::nut::iac::managed_state::{DomainId, DomainState};
 use crate::nut::iac::publish::BroadcastInfo;
 use crate::nut::Nut;
+use crate::{SubscriptionId, UncheckedActivityId};
+use core::any::Any;
 
 pub(crate) mod fifo;
+pub(crate) mod observer;
+pub(crate) mod panic;
+pub(crate) mod timing;
+pub(crate) mod trace;
 
 pub(crate) enum Deferred {
     Broadcast(BroadcastInfo),
     LifecycleChange(LifecycleChange),
+    ClearDomain(DomainId),
+    DomainWrite(DomainId, DomainWriteFn),
+    Unregister(ActivityPredicate),
+    Unsubscribe(SubscriptionId),
 }
+
+pub(crate) type ActivityPredicate = Box<dyn Fn(UncheckedActivityId) -> bool>;
+
+pub(crate) type DomainWriteFn = Box<dyn FnOnce(&mut DomainState)>;
 use core::sync::atomic::Ordering;
 
 impl Nut {
@@ -22,28 +37,190 @@ impl Nut {
         // A Nut only allows single-threaded access, relaxed ordering is fine.
         if !self.executing.swap(true, Ordering::Relaxed) {
             self.unchecked_catch_up_deferred_to_quiescence();
-            self.executing.store(false, Ordering::Relaxed);
+            self.stop_executing();
+        }
+    }
+
+    /// Delivers a broadcast without going through the queue, if no other event is being processed or waiting.
+    /// Messages published by the subscribers are delivered before this function returns.
+    ///
+    /// Returns the broadcast if it has to be queued instead.
+    // Boxing the returned broadcast would cost an allocation on the path that is meant to save one
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn try_broadcast_directly(
+        &self,
+        broadcast: BroadcastInfo,
+    ) -> Result<(), BroadcastInfo> {
+        if !self.deferred_events.is_empty() || self.executing.swap(true, Ordering::Relaxed) {
+            return Err(broadcast);
+        }
+        self.unchecked_broadcast_observed(broadcast, false);
+        self.unchecked_catch_up_deferred_to_quiescence();
+        self.stop_executing();
+        Ok(())
+    }
+
+    /// Runs `f` like a top-level handler, messages it publishes are delivered before this function returns.
+    ///
+    /// Returns `None` without calling `f` if another event is being processed.
+    pub(crate) fn execute_directly<R>(&self, f: impl FnOnce() -> R) -> Option<R> {
+        if self.executing.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        let result = f();
+        self.unchecked_catch_up_deferred_to_quiescence();
+        self.stop_executing();
+        Some(result)
+    }
+
+    /// True if no other event is being processed or waiting, such that a broadcast can skip the queue.
+    pub(crate) fn can_broadcast_directly(&self) -> bool {
+        self.deferred_events.is_empty() && !self.is_executing()
+    }
+
+    /// Delivers each broadcast like `try_broadcast_directly`, but takes the executing flag only once for all of them.
+    /// The next broadcast is only taken from the iterator once the cascade of the previous one has been processed.
+    ///
+    /// Only call if `can_broadcast_directly` returned true.
+    pub(crate) fn broadcast_batch(&self, broadcasts: impl Iterator<Item = Option<BroadcastInfo>>) {
+        let was_executing = self.executing.swap(true, Ordering::Relaxed);
+        debug_assert!(!was_executing, "Bug: batch started while executing");
+        for broadcast in broadcasts {
+            if let Some(broadcast) = broadcast {
+                self.unchecked_broadcast_observed(broadcast, false);
+                self.unchecked_catch_up_deferred_to_quiescence();
+            }
+            // Each message starts a new cascade, like a separate call to `publish` would
+            self.self_republished.set(0);
+        }
+        self.stop_executing();
+    }
+
+    /// Delivers a broadcast immediately and returns the message afterwards, unless a subscriber took ownership of it.
+    /// Messages published by the subscribers are delivered before this function returns.
+    ///
+    /// # Panics
+    /// Panics if a broadcast is ongoing already. `caller` is used for the panic message.
+    pub(crate) fn broadcast_now(
+        &self,
+        broadcast: BroadcastInfo,
+        caller: &str,
+    ) -> Option<Box<dyn Any>> {
+        assert!(
+            !self.executing.swap(true, Ordering::Relaxed),
+            "`{}` can only be called outside of activities.",
+            caller
+        );
+        let msg = self.unchecked_broadcast_observed(broadcast, false);
+        self.unchecked_catch_up_deferred_to_quiescence();
+        self.stop_executing();
+        msg
+    }
+
+    /// Delivers a borrowed message after all events queued before it and processes the events queued by its subscribers afterwards.
+    ///
+    /// # Panics
+    /// Panics if a broadcast is ongoing already, if the message type is paused, or if strict mode rejects the message.
+    pub(crate) fn publish_ref<MSG: Any>(&self, msg: &MSG) {
+        assert!(
+            !self.is_executing(),
+            "`publish_ref` can only be called outside of activities."
+        );
+        self.check_publish_ref::<MSG>();
+        self.executing.store(true, Ordering::Relaxed);
+        // Keep the FIFO order with respect to events queued earlier
+        while let Some(deferred) = self.deferred_events.pop() {
+            self.unchecked_process(deferred);
         }
+        self.unchecked_broadcast_ref(msg);
+        self.unchecked_catch_up_deferred_to_quiescence();
+        self.stop_executing();
+    }
+
+    /// Processes up to `max` queued events and returns how many have been processed.
+    ///
+    /// # Panics
+    /// Panics if a broadcast is ongoing already.
+    pub(crate) fn process_deferred_limited(&self, max: usize) -> usize {
+        assert!(
+            !self.executing.swap(true, Ordering::Relaxed),
+            "`process_deferred_limited` can only be called outside of activities."
+        );
+        let mut processed = 0;
+        while processed < max {
+            let next = self
+                .deferred_events
+                .pop()
+                .or_else(|| self.idle_events.pop().map(Deferred::Broadcast));
+            match next {
+                Some(deferred) => self.unchecked_process(deferred),
+                None => break,
+            }
+            processed += 1;
+        }
+        self.stop_executing();
+        processed
+    }
+
+    /// Releases the executing flag and wakes all futures waiting for the queue to settle, see `nuts::publish_async`
+    fn stop_executing(&self) {
+        self.executing.store(false, Ordering::Relaxed);
+        self.self_republished.set(0);
+        let wakers = std::mem::take(&mut *self.settle_wakers.borrow_mut());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn is_executing(&self) -> bool {
+        self.executing.load(Ordering::Relaxed)
     }
 
     /// only access after locking with executing flag
+    fn unchecked_domain_write(&self, id: DomainId, write: DomainWriteFn) {
+        let mut managed_state = self
+            .managed_state
+            .try_borrow_mut()
+            .expect("Bug: This should not be possible to trigger from outside the library.");
+        managed_state.prepare(id);
+        write(managed_state.get_mut(id).expect("No domain"));
+        managed_state.notify_mirror();
+    }
     fn unchecked_catch_up_deferred_to_quiescence(&self) {
-        while let Some(deferred) = self.deferred_events.pop() {
-            match deferred {
-                Deferred::Broadcast(b) => self.unchecked_broadcast(b),
-                Deferred::LifecycleChange(lc) => self.unchecked_lifecycle_change(&lc),
+        loop {
+            while let Some(deferred) = self.deferred_events.pop() {
+                self.unchecked_process(deferred);
+            }
+            match self.idle_events.pop() {
+                Some(broadcast) => {
+                    self.unchecked_broadcast_observed(broadcast, true);
+                }
+                None => break,
+            }
+        }
+    }
+    /// only access after locking with executing flag
+    fn unchecked_process(&self, deferred: Deferred) {
+        match deferred {
+            Deferred::Broadcast(b) => {
+                self.unchecked_broadcast_observed(b, true);
             }
+            Deferred::LifecycleChange(lc) => self.unchecked_lifecycle_change(&lc),
+            Deferred::ClearDomain(domain) => self.unchecked_clear_domain(domain),
+            Deferred::DomainWrite(domain, write) => self.unchecked_domain_write(domain, write),
+            Deferred::Unregister(predicate) => self.unchecked_unregister(&predicate),
+            Deferred::Unsubscribe(id) => self.unchecked_unsubscribe(id),
         }
     }
 }
-impl Into<Deferred> for BroadcastInfo {
-    fn into(self) -> Deferred {
-        Deferred::Broadcast(self)
+impl From<BroadcastInfo> for Deferred {
+    fn from(broadcast: BroadcastInfo) -> Self {
+        Deferred::Broadcast(broadcast)
     }
 }
 
-impl Into<Deferred> for LifecycleChange {
-    fn into(self) -> Deferred {
-        Deferred::LifecycleChange(self)
+impl From<LifecycleChange> for Deferred {
+    fn from(lifecycle_change: LifecycleChange) -> Self {
+        Deferred::LifecycleChange(lifecycle_change)
     }
 }
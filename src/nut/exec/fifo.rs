@@ -18,6 +18,9 @@ impl<ITEM> ThreadLocalFifo<ITEM> {
     pub(crate) fn pop(&self) -> Option<ITEM> {
         self.fifo.borrow_mut().pop_front()
     }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fifo.borrow().is_empty()
+    }
 }
 
 impl<ITEM> Default for ThreadLocalFifo<ITEM> {
@@ -0,0 +1,86 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use core::any::{Any, TypeId};
+
+/// Receives a report of every dispatched message, see `nuts::set_dispatch_observer`
+pub(crate) type DispatchObserver = Box<dyn Fn(DispatchEvent)>;
+
+/// Report of a message that has been dispatched, passed to the observer set with [`nuts::set_dispatch_observer`](fn.set_dispatch_observer.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DispatchEvent {
+    /// Type of the dispatched message
+    pub type_id: TypeId,
+    /// Name of the dispatched message type
+    pub type_name: &'static str,
+    /// Number of subscriber handlers that have been called, filtered out subscriptions are not counted
+    pub subscribers: usize,
+    /// True if the message has been delivered from the queue rather than right away, e.g. because it was published while another message was being dispatched
+    pub queued: bool,
+    /// Time spent in the handlers of this message, only measured if a clock has been set with [`nuts::set_clock`](fn.set_clock.html)
+    pub elapsed_ms: Option<u64>,
+}
+
+/// State captured before a dispatch, to complete the `DispatchEvent` afterwards
+#[derive(Clone, Copy)]
+struct Observation {
+    type_id: TypeId,
+    type_name: &'static str,
+    calls_before: usize,
+    start_ms: Option<u64>,
+}
+
+impl Nut {
+    pub(crate) fn set_dispatch_observer(&self, f: DispatchObserver) {
+        *self
+            .dispatch_observer
+            .try_borrow_mut()
+            .expect("The dispatch observer cannot be replaced from inside itself.") = Some(f);
+    }
+    /// only access after locking with executing flag
+    ///
+    /// Same as `unchecked_broadcast`, but the dispatch is reported to the observer, if there is one,
+    /// and counted for `publish_counted`.
+    pub(crate) fn unchecked_broadcast_observed(
+        &self,
+        broadcast: BroadcastInfo,
+        queued: bool,
+    ) -> Option<Box<dyn Any>> {
+        let observation = self.start_observation(&broadcast);
+        let generation = broadcast.generation();
+        let calls_before = self.handler_calls.get();
+        let msg = self.unchecked_broadcast(broadcast);
+        self.count_dispatch(generation, calls_before);
+        if let Some(observation) = observation {
+            self.report_dispatch(observation, queued);
+        }
+        msg
+    }
+    /// Returns `None` if nobody observes the dispatch, which is the case for all builtin events
+    fn start_observation(&self, broadcast: &BroadcastInfo) -> Option<Observation> {
+        if self.dispatch_observer.borrow().is_none() {
+            return None;
+        }
+        Some(Observation {
+            type_id: broadcast.topic().message_type()?,
+            type_name: broadcast.type_name(),
+            calls_before: self.handler_calls.get(),
+            start_ms: self.now_ms(),
+        })
+    }
+    fn report_dispatch(&self, observation: Observation, queued: bool) {
+        let event = DispatchEvent {
+            type_id: observation.type_id,
+            type_name: observation.type_name,
+            subscribers: self.handler_calls.get() - observation.calls_before,
+            queued,
+            elapsed_ms: observation
+                .start_ms
+                .zip(self.now_ms())
+                .map(|(start, end)| end.saturating_sub(start)),
+        };
+        if let Some(f) = self.dispatch_observer.borrow().as_ref() {
+            f(event);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::nut::iac::subscription::Subscription;
+use crate::nut::Nut;
+use crate::*;
+use std::panic::AssertUnwindSafe;
+
+/// Receives panics of handlers, see `nuts::set_panic_handler`
+pub(crate) type PanicHandler = Box<dyn Fn(&HandlerPanic)>;
+
+/// Describes a panic that occurred inside a handler, passed to the callback set with [`nuts::set_panic_handler`](fn.set_panic_handler.html).
+#[derive(Debug)]
+pub struct HandlerPanic {
+    activity: UncheckedActivityId,
+    type_name: &'static str,
+    message: Option<String>,
+}
+
+impl HandlerPanic {
+    /// The activity whose handler panicked
+    pub fn activity(&self) -> UncheckedActivityId {
+        self.activity
+    }
+    /// Type name of the message that was handled
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+    /// The panic message, if the panic payload is a string, which is the case for `panic!` and `expect`
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl Nut {
+    pub(crate) fn set_panic_handler(&self, f: PanicHandler) {
+        *self
+            .panic_handler
+            .try_borrow_mut()
+            .expect("The panic handler cannot be replaced from inside itself.") = Some(f);
+    }
+    /// Calls `dispatch` and reports a panic to the panic handler instead of unwinding further, if a panic handler is set.
+    /// Returns false after a caught panic.
+    pub(crate) fn catch_handler_panic(
+        &self,
+        sub: &Subscription,
+        dispatch: impl FnOnce() -> bool,
+    ) -> bool {
+        if self.panic_handler.borrow().is_none() {
+            return dispatch();
+        }
+        // All borrows taken by the handler are guards that are released while unwinding
+        let payload = match std::panic::catch_unwind(AssertUnwindSafe(dispatch)) {
+            Ok(called) => return called,
+            Err(payload) => payload,
+        };
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|msg| (*msg).to_owned())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+        let panic = HandlerPanic {
+            activity: sub.activity,
+            type_name: sub.type_name,
+            message,
+        };
+        if let Some(f) = self.panic_handler.borrow().as_ref() {
+            f(&panic);
+        }
+        false
+    }
+}
@@ -0,0 +1,66 @@
+use crate::nut::iac::subscription::Subscription;
+use crate::nut::Nut;
+use crate::*;
+
+/// Returns the current time in milliseconds, see `nuts::set_clock`
+pub(crate) type Clock = Box<dyn Fn() -> u64>;
+/// Called with the activity, the message type name, and the elapsed milliseconds
+pub(crate) type SlowHandlerCallback = Box<dyn Fn(UncheckedActivityId, &'static str, u64)>;
+
+/// Measures the execution time of handlers, to detect slow handlers.
+#[derive(Default)]
+pub(crate) struct HandlerTiming {
+    clock: Option<Clock>,
+    threshold_ms: Option<u64>,
+    on_slow_handler: Option<SlowHandlerCallback>,
+}
+
+impl HandlerTiming {
+    pub(crate) fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+    pub(crate) fn set_threshold_ms(&mut self, threshold_ms: u64) {
+        self.threshold_ms = Some(threshold_ms);
+        if self.clock.is_none() {
+            let epoch = std::time::Instant::now();
+            self.clock = Some(Box::new(move || epoch.elapsed().as_millis() as u64));
+        }
+    }
+    pub(crate) fn set_on_slow_handler(&mut self, f: SlowHandlerCallback) {
+        self.on_slow_handler = Some(f);
+    }
+    /// The current time, if a clock is available
+    fn now(&self) -> Option<u64> {
+        self.clock.as_ref().map(|clock| clock())
+    }
+    /// The current time, if handlers are measured at all
+    fn start(&self) -> Option<u64> {
+        match (&self.clock, self.threshold_ms, &self.on_slow_handler) {
+            (Some(clock), Some(_), Some(_)) => Some(clock()),
+            _ => None,
+        }
+    }
+}
+
+impl Nut {
+    /// The current time in milliseconds, `None` if no clock has been set.
+    pub(crate) fn now_ms(&self) -> Option<u64> {
+        self.timing.borrow().now()
+    }
+    /// Returns the start time if the handler should be measured.
+    pub(crate) fn start_timing(&self) -> Option<u64> {
+        self.timing.borrow().start()
+    }
+    /// Reports the handler if it took longer than the threshold since `start`.
+    pub(crate) fn check_slow_handler(&self, sub: &Subscription, start: u64) {
+        let timing = self.timing.borrow();
+        if let (Some(clock), Some(threshold_ms), Some(f)) =
+            (&timing.clock, timing.threshold_ms, &timing.on_slow_handler)
+        {
+            let elapsed = clock().saturating_sub(start);
+            if elapsed > threshold_ms {
+                f(sub.activity, sub.type_name, elapsed);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::nut::iac::subscription::Subscription;
+use crate::nut::Nut;
+use crate::*;
+use core::fmt::Debug;
+
+/// The ordered list of deliveries caused by a single publish, including all messages published in cascade.
+///
+/// Created by [`nuts::trace_publish`](fn.trace_publish.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryTrace {
+    deliveries: Vec<Delivery>,
+    messages: Vec<String>,
+}
+
+/// A single call of a subscription handler, as recorded in a [`DeliveryTrace`](struct.DeliveryTrace.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Delivery {
+    /// The activity whose handler has been called
+    pub activity: UncheckedActivityId,
+    /// Name of the delivered message type
+    pub type_name: &'static str,
+}
+
+impl DeliveryTrace {
+    /// All deliveries, in the order they happened
+    pub fn deliveries(&self) -> &[Delivery] {
+        &self.deliveries
+    }
+    /// Debug representations of the messages published with [`nuts::publish_dbg`](fn.publish_dbg.html), in the order they have been published
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+}
+
+impl Nut {
+    pub(crate) fn trace_publish<MSG: Any>(&self, msg: MSG) -> DeliveryTrace {
+        assert!(
+            !self.is_executing(),
+            "`trace_publish` can only be called outside of activities."
+        );
+        *self.trace.borrow_mut() = Some(DeliveryTrace::default());
+        self.publish(msg);
+        self.trace.borrow_mut().take().unwrap_or_default()
+    }
+    pub(crate) fn publish_dbg<MSG: Any + Debug>(&self, msg: MSG) {
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.messages.push(format!("{:?}", msg));
+        }
+        self.publish(msg);
+    }
+    pub(crate) fn record_delivery(&self, sub: &Subscription) {
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.deliveries.push(Delivery {
+                activity: sub.activity,
+                type_name: sub.type_name,
+            });
+        }
+    }
+}
@@ -6,7 +6,11 @@
 //!
 //! TODO: model for shared memory is planned for higher bandwidth communication.
 
+pub(crate) mod capture;
 pub(crate) mod filter;
 pub(crate) mod managed_state;
 pub(crate) mod publish;
+pub(crate) mod registry;
+pub(crate) mod subscription;
 pub(crate) mod topic;
+pub(crate) mod variants;
@@ -0,0 +1,141 @@
+use crate::nut::Nut;
+use crate::*;
+use core::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Messages intercepted by [`nuts::capture`](fn.capture.html), in the order they have been published.
+#[derive(Clone, Default)]
+pub struct Capture {
+    messages: Rc<RefCell<Vec<CapturedMessage>>>,
+}
+
+/// A message intercepted by [`nuts::capture`](fn.capture.html).
+pub struct CapturedMessage {
+    type_name: &'static str,
+    msg: Box<dyn Any>,
+}
+
+/// The response that `nuts::publish_await_response` is waiting for
+pub(crate) struct AwaitedResponse {
+    topic: Topic,
+    response: Option<Box<dyn Any>>,
+}
+
+/// Stops awaiting a response when dropped, even if the command panics
+struct ClearOnDrop<'a>(&'a RefCell<Option<AwaitedResponse>>);
+
+impl Drop for ClearOnDrop<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut awaited) = self.0.try_borrow_mut() {
+            *awaited = None;
+        }
+    }
+}
+
+impl Capture {
+    /// Number of messages captured so far
+    pub fn len(&self) -> usize {
+        self.messages.borrow().len()
+    }
+    /// True if no messages have been captured so far
+    pub fn is_empty(&self) -> bool {
+        self.messages.borrow().is_empty()
+    }
+    /// Type names of all messages captured so far
+    pub fn type_names(&self) -> Vec<&'static str> {
+        self.messages
+            .borrow()
+            .iter()
+            .map(|captured| captured.type_name)
+            .collect()
+    }
+    /// Removes all captured messages and returns them
+    pub fn take(&self) -> Vec<CapturedMessage> {
+        std::mem::take(&mut self.messages.borrow_mut())
+    }
+    fn push<MSG: Any>(&self, msg: MSG) {
+        self.messages.borrow_mut().push(CapturedMessage {
+            type_name: std::any::type_name::<MSG>(),
+            msg: Box::new(msg),
+        });
+    }
+}
+
+impl CapturedMessage {
+    /// Name of the message type
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+    /// Access to the captured message, if it is of type `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.msg.downcast_ref()
+    }
+}
+
+impl Nut {
+    pub(crate) fn capture<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Capture) -> R,
+    {
+        let capture = Capture::default();
+        let previous = self.capture.replace(Some(capture.clone()));
+        let result = f(&capture);
+        *self.capture.borrow_mut() = previous;
+        result
+    }
+    pub(crate) fn publish_await_response<CMD: Any, RESP: Any>(&self, cmd: CMD) -> Option<RESP> {
+        assert!(
+            !self.is_executing(),
+            "`publish_await_response` can only be called outside of activities."
+        );
+        assert_ne!(
+            TypeId::of::<CMD>(),
+            TypeId::of::<RESP>(),
+            "`publish_await_response` cannot await a response of the same type as the command `{}`.",
+            std::any::type_name::<CMD>()
+        );
+        *self.awaited_response.borrow_mut() = Some(AwaitedResponse {
+            topic: Topic::message::<RESP>(),
+            response: None,
+        });
+        let awaited = ClearOnDrop(&self.awaited_response);
+        self.publish(cmd);
+        let response = awaited.0.take()?.response?;
+        Some(*response.downcast().expect("Bug: wrong response captured"))
+    }
+    /// Returns the message back if it is not the awaited response, or if the response has been captured already
+    pub(crate) fn try_take_response<MSG: Any>(&self, msg: MSG) -> Option<MSG> {
+        match self.awaited_response.borrow_mut().as_mut() {
+            Some(awaited)
+                if awaited.response.is_none() && awaited.topic == Topic::message::<MSG>() =>
+            {
+                awaited.response = Some(Box::new(msg));
+                None
+            }
+            _ => Some(msg),
+        }
+    }
+    /// True if a message of the topic published now would be taken by a capture or as an awaited response
+    pub(crate) fn would_intercept(&self, topic: &Topic) -> bool {
+        (!self.is_executing() && self.capture.borrow().is_some())
+            || self
+                .awaited_response
+                .borrow()
+                .as_ref()
+                .is_some_and(|awaited| awaited.response.is_none() && awaited.topic == *topic)
+    }
+    /// Returns the message back if it has not been captured
+    pub(crate) fn try_capture<MSG: Any>(&self, msg: MSG) -> Option<MSG> {
+        if self.is_executing() {
+            return Some(msg);
+        }
+        match self.capture.borrow().as_ref() {
+            Some(capture) => {
+                capture.push(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+}
@@ -1,35 +1,114 @@
 use crate::*;
+use core::any::TypeId;
+use std::rc::Rc;
+
+/// Checks a message, which is only called with messages of the type identified by the `TypeId`
+type MessagePredicate = (TypeId, Rc<dyn Fn(&dyn Any) -> bool>);
 
 /// Defines under which circumstances a subscribing activity should be called.
-/// At the moment, the only filter option is to check the activity lifecycle state.
+/// The filter can check the activity lifecycle state and the content of the message.
 /// The default filter will ignore events when the activity is inactive.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 #[non_exhaustive]
 pub struct SubscriptionFilter {
     /// Only call the subscribed closure when the activity is active.
     pub active_only: bool,
+    /// Only call the subscribed closure for messages published by this activity.
+    pub(crate) sender: Option<UncheckedActivityId>,
+    /// Only call the subscribed closure for messages that satisfy the predicate.
+    pub(crate) predicate: Option<MessagePredicate>,
 }
 
 impl Default for SubscriptionFilter {
     fn default() -> Self {
-        Self { active_only: true }
+        Self {
+            active_only: true,
+            sender: None,
+            predicate: None,
+        }
+    }
+}
+
+impl core::fmt::Debug for SubscriptionFilter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SubscriptionFilter")
+            .field("active_only", &self.active_only)
+            .field("sender", &self.sender)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
     }
 }
 
 impl SubscriptionFilter {
     /// Create a new subscription filter that will ensure the activity always receives a message, even when inactive.
     pub fn no_filter() -> Self {
-        Self { active_only: false }
+        Self {
+            active_only: false,
+            sender: None,
+            predicate: None,
+        }
+    }
+    /// Create a new subscription filter that only lets messages through which satisfy the predicate.
+    /// Like the default filter, it also ignores messages while the activity is inactive.
+    ///
+    /// The filter can only be used for subscriptions to messages of type `M`, subscribing with another type panics.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use nuts::SubscriptionFilter;
+    /// struct NetworkEvent {
+    ///     channel: u8,
+    /// }
+    /// let activity = nuts::new_activity(0u32);
+    /// let filter = SubscriptionFilter::with_predicate(|event: &NetworkEvent| event.channel == 3);
+    /// activity.subscribe_masked(filter, |received, _: &NetworkEvent| *received += 1);
+    ///
+    /// nuts::publish(NetworkEvent { channel: 3 });
+    /// nuts::publish(NetworkEvent { channel: 4 });
+    /// assert_eq!(Some(1), activity.read(|received| *received));
+    /// ```
+    pub fn with_predicate<M: Any>(predicate: impl Fn(&M) -> bool + 'static) -> Self {
+        let predicate = move |msg: &dyn Any| msg.downcast_ref().is_some_and(&predicate);
+        Self {
+            predicate: Some((TypeId::of::<M>(), Rc::new(predicate))),
+            ..Default::default()
+        }
+    }
+    /// Returns true if a message published by `sender` should go through.
+    /// (`None` for messages published from outside of activities)
+    pub(crate) fn accepts_sender(&self, sender: Option<UncheckedActivityId>) -> bool {
+        self.sender.is_none() || self.sender == sender
+    }
+    /// Returns true if the message should go through.
+    /// (`None` if the message has already been taken by a previous subscriber, which only passes without a predicate)
+    pub(crate) fn accepts_message(&self, msg: Option<&dyn Any>) -> bool {
+        match &self.predicate {
+            None => true,
+            Some((_, predicate)) => msg.is_some_and(|msg| predicate(msg)),
+        }
+    }
+    /// The message type the predicate has been defined for, if any
+    pub(crate) fn predicate_type(&self) -> Option<TypeId> {
+        self.predicate.as_ref().map(|(type_id, _)| *type_id)
     }
 }
 
 impl ActivityContainer {
     /// Returns true if the call should go through (false if it should be filtered out)
-    pub(crate) fn filter<A: Activity>(
+    pub(crate) fn filter(
+        &self,
+        id: UncheckedActivityId,
+        filter: &SubscriptionFilter,
+        msg: Option<&dyn Any>,
+    ) -> bool {
+        self.is_reachable(id, filter) && filter.accepts_message(msg)
+    }
+    /// Returns true if the activity currently passes the filter for some messages, ignoring predicates on the message
+    pub(crate) fn is_reachable(
         &self,
-        id: ActivityId<A>,
+        id: UncheckedActivityId,
         filter: &SubscriptionFilter,
     ) -> bool {
-        !filter.active_only || self.status(id.id).is_active()
+        self.contains(id) && (!filter.active_only || self.status(id).is_active())
     }
 }
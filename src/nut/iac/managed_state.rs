@@ -2,22 +2,53 @@
 //!
 //! Objects to which multiple activities have access
 
+mod access_report;
+mod domain_diff;
 mod domain_id;
+mod domain_snapshot;
 mod domain_state;
+mod domain_transaction;
+mod domain_view;
 
 use crate::nut::activity::Activity;
 use crate::nut::activity::ActivityContainer;
 use crate::nut::activity::ActivityId;
-use crate::nut::iac::filter::SubscriptionFilter;
-use crate::nut::Handler;
-use core::any::Any;
+use crate::nut::activity::UncheckedActivityId;
+use crate::nut::iac::registry::BoxedHandler;
+use crate::nut::{Handler, RefHandler};
+pub use access_report::*;
+use core::any::{Any, TypeId};
+pub(crate) use domain_diff::DiffableType;
+pub use domain_diff::{Changed, DomainDiffSnapshot};
 pub use domain_id::*;
+pub use domain_snapshot::*;
 pub use domain_state::*;
+pub use domain_transaction::DomainTransaction;
+pub use domain_view::DomainView;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+pub(crate) type DomainMirror = Box<dyn Fn(DomainId, TypeId)>;
 
 #[derive(Default)]
 pub(crate) struct ManagedState {
     domains: Vec<DomainState>,
     broadcast: Option<Box<dyn Any>>,
+    /// Long-lived services injected by the user, see `nuts::set_services`
+    services: Option<Box<dyn Any>>,
+    /// Types that are included in domain snapshots
+    snapshot_types: HashMap<TypeId, CloneFn>,
+    /// Types that are included in diff snapshots, see `nuts::register_diffable`
+    diffable_types: HashMap<TypeId, DiffableType>,
+    /// Domains that must contain data before activities are added to them, see `nuts::ensure_domain_before_activity`
+    initialized_first: HashSet<DomainId>,
+    /// Called for every write to a domain, see `nuts::set_domain_mirror`
+    mirror: Option<DomainMirror>,
+    /// Number of domain borrows handed out during the current dispatch, only tracked in debug builds
+    #[cfg(debug_assertions)]
+    domain_borrows: usize,
+    /// Domain accesses of each activity, only recorded after `nuts::enable_domain_profiling`
+    access_report: Option<AccessReport>,
 }
 
 impl ManagedState {
@@ -28,15 +59,149 @@ impl ManagedState {
     pub(crate) fn prepare(&mut self, id: DomainId) {
         if let Some(n) = id.index() {
             while self.domains.len() <= n {
-                self.domains.push(Default::default());
+                let mut domain = DomainState::default();
+                if self.access_report.is_some() {
+                    domain.enable_profiling();
+                }
+                if self.mirror.is_some() {
+                    domain.enable_mirroring();
+                }
+                self.domains.push(domain);
             }
         }
     }
+    pub(crate) fn require_data_before_activity(&mut self, id: DomainId) {
+        self.initialized_first.insert(id);
+    }
+    /// Checks that the domain is ready for a new activity, in debug builds only.
+    pub(crate) fn debug_assert_ready_for_activity(&self, id: DomainId) {
+        debug_assert!(
+            !(self.initialized_first.contains(&id) && self.is_domain_empty(id)),
+            "Activity added to {:?} before any data has been stored to it, use `store_to_domain` first.",
+            id
+        );
+    }
+    /// True if no data is stored in the domain
+    pub(crate) fn is_domain_empty(&self, id: DomainId) -> bool {
+        id.index()
+            .and_then(|i| self.domains.get(i))
+            .is_none_or(|domain| domain.is_empty())
+    }
+    /// Removes all data stored in the domain.
+    pub(crate) fn clear_domain(&mut self, id: DomainId) {
+        if let Some(domain) = self.get_mut(id) {
+            domain.clear();
+        }
+        self.notify_mirror();
+    }
+    pub(crate) fn clear_all_domains(&mut self) {
+        for domain in &mut self.domains {
+            domain.clear();
+        }
+        self.notify_mirror();
+    }
+    pub(crate) fn enable_profiling(&mut self) {
+        self.access_report.get_or_insert_with(Default::default);
+        for domain in &mut self.domains {
+            domain.enable_profiling();
+        }
+    }
+    pub(crate) fn access_report(&self) -> AccessReport {
+        self.access_report.clone().unwrap_or_default()
+    }
+    pub(crate) fn set_domain_mirror(&mut self, mirror: DomainMirror) {
+        self.mirror = Some(mirror);
+        for domain in &mut self.domains {
+            domain.enable_mirroring();
+        }
+    }
+    /// Reports all domain writes since the last call to the mirror, if one is set.
+    ///
+    /// Without mirror and profiling, nothing is recorded and this returns immediately.
+    pub(crate) fn notify_mirror(&mut self) {
+        if self.mirror.is_none() && self.access_report.is_none() {
+            return;
+        }
+        for (i, domain) in self.domains.iter_mut().enumerate() {
+            // Accesses outside of handlers are not attributed to any activity
+            domain.take_accesses();
+            let written = domain.take_written();
+            if let Some(mirror) = &self.mirror {
+                for type_id in written {
+                    mirror(DomainId(Some(i)), type_id);
+                }
+            }
+        }
+    }
+    /// Checks that at most one domain borrow is handed out per dispatch.
+    /// This is a no-op in release builds.
+    fn count_domain_borrow(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            debug_assert_eq!(
+                0, self.domain_borrows,
+                "Bug: domain borrowed twice in the same dispatch"
+            );
+            self.domain_borrows += 1;
+        }
+    }
+    /// Marks the end of a dispatch to the activity, after which no domain borrow may be alive anymore.
+    /// The borrow check is a no-op in release builds.
+    pub(crate) fn end_dispatch(&mut self, activity: UncheckedActivityId) {
+        if let Some(report) = &mut self.access_report {
+            for (i, domain) in self.domains.iter_mut().enumerate() {
+                if let Some(access) = domain.take_accesses() {
+                    report.record(activity, DomainId(Some(i)), access);
+                }
+            }
+        }
+        self.notify_mirror();
+        #[cfg(debug_assertions)]
+        {
+            self.domain_borrows = 0;
+        }
+    }
+    /// Number of domain borrows handed out since the last dispatch ended.
+    #[cfg(all(test, debug_assertions))]
+    pub(crate) fn domain_borrows(&self) -> usize {
+        self.domain_borrows
+    }
     pub(crate) fn set_broadcast(&mut self, msg: Box<dyn Any>) {
         self.broadcast = Some(msg);
     }
-    pub(crate) fn clear_broadcast(&mut self) {
-        self.broadcast = None;
+    /// The message currently being delivered, `None` if it has been taken by a subscriber
+    pub(crate) fn current_message(&self) -> Option<&dyn Any> {
+        self.broadcast.as_deref()
+    }
+    pub(crate) fn take_broadcast(&mut self) -> Option<Box<dyn Any>> {
+        self.broadcast.take()
+    }
+    pub(crate) fn register_snapshot_type<T: Any + Clone + Send + Sync>(&mut self) {
+        self.snapshot_types
+            .insert(TypeId::of::<T>(), clone_any::<T> as CloneFn);
+    }
+    pub(crate) fn snapshot(&self, id: DomainId) -> DomainSnapshot {
+        let objects = id
+            .index()
+            .and_then(|i| self.domains.get(i))
+            .map(|domain| domain.clone_objects(&self.snapshot_types))
+            .unwrap_or_default();
+        DomainSnapshot::new(objects)
+    }
+    pub(crate) fn register_diffable<T: Any + Clone + PartialEq>(&mut self) {
+        self.diffable_types
+            .insert(TypeId::of::<T>(), DiffableType::of::<T>());
+    }
+    pub(crate) fn snapshot_for_diff(&self, id: DomainId) -> DomainDiffSnapshot {
+        let objects = id
+            .index()
+            .and_then(|i| self.domains.get(i))
+            .map(|domain| domain.clone_diffable(&self.diffable_types))
+            .unwrap_or_default();
+        DomainDiffSnapshot::new(objects)
+    }
+    pub(crate) fn set_services(&mut self, services: Box<dyn Any>) {
+        self.services = Some(services);
     }
     /// panics if runtime broadcast is not of static type A
     fn current_broadcast<A: Any>(&mut self) -> &mut A {
@@ -49,6 +214,7 @@ impl ManagedState {
         msg
     }
     fn current_broadcast_and_domain<A: Any>(&mut self, id: DomainId) -> (&mut A, &mut DomainState) {
+        self.count_domain_borrow();
         let msg: &mut A = self
             .broadcast
             .as_mut()
@@ -59,19 +225,55 @@ impl ManagedState {
         let domain = &mut self.domains[i];
         (msg, domain)
     }
-    fn take_current_broadcast<A: Any>(&mut self) -> Box<A> {
-        let msg = self
+    /// The message with the activity's own domain and read-only access to another, distinct domain
+    fn current_broadcast_and_domains<A: Any>(
+        &mut self,
+        id: DomainId,
+        extra: DomainId,
+    ) -> (&A, &mut DomainState, &DomainState) {
+        self.count_domain_borrow();
+        let msg: &A = self
             .broadcast
+            .as_ref()
+            .expect("Bug: nothing broadcasted")
+            .downcast_ref()
+            .expect("Bug: wrong message broadcasted");
+        let i = id.index().expect("Activity has no domain");
+        let j = extra.index().expect("Bug: extra domain without index");
+        let (domain, extra) = if i < j {
+            let (left, right) = self.domains.split_at_mut(j);
+            (&mut left[i], &right[0])
+        } else {
+            let (left, right) = self.domains.split_at_mut(i);
+            (&mut right[0], &left[j])
+        };
+        (msg, domain, extra)
+    }
+    fn current_broadcast_and_services<A: Any>(&mut self) -> (&mut A, &dyn Any) {
+        let msg: &mut A = self
+            .broadcast
+            .as_mut()
+            .expect("Bug: nothing broadcasted")
+            .downcast_mut()
+            .expect("Bug: wrong message broadcasted");
+        let services = self
+            .services
+            .as_deref()
+            .expect("No services registered, use `nuts::set_services` before publishing.");
+        (msg, services)
+    }
+    fn take_current_broadcast<A: Any>(&mut self) -> Box<A> {
+        self.broadcast
             .take()
             .expect("Bug: nothing broadcasted")
             .downcast()
-            .expect("Bug: wrong message broadcasted");
-        msg
+            .expect("Bug: wrong message broadcasted")
     }
     fn take_current_broadcast_and_borrow_domain<A: Any>(
         &mut self,
         id: DomainId,
     ) -> (Box<A>, &mut DomainState) {
+        self.count_domain_borrow();
         let msg = self
             .broadcast
             .take()
@@ -83,139 +285,248 @@ impl ManagedState {
         (msg, domain)
     }
 
-    pub(crate) fn pack_closure<A, F, MSG>(
-        f: F,
-        index: ActivityId<A>,
-        filter: SubscriptionFilter,
-    ) -> Handler
+    pub(crate) fn pack_closure<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
     where
         A: Activity,
         F: Fn(&mut A, &MSG) + 'static,
         MSG: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let msg = managed_state.current_broadcast();
-                    f(a, msg)
-                }
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let msg = managed_state.current_broadcast();
+                        f(a, msg)
+                    })
+                    .is_some()
             },
         )
     }
-    pub(crate) fn pack_closure_mut<A, F, MSG>(
-        f: F,
+    pub(crate) fn pack_closure_by_ref<A, F, MSG>(f: Rc<F>, index: ActivityId<A>) -> RefHandler
+    where
+        A: Activity,
+        F: Fn(&mut A, &MSG) + 'static,
+        MSG: Any,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, _: &mut ManagedState, msg: &dyn Any| {
+                activities
+                    .handle(index, |a| {
+                        let msg = msg.downcast_ref().expect("Bug: wrong message broadcasted");
+                        f(a, msg)
+                    })
+                    .is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_domained_closure_by_ref<A, F, MSG>(
+        f: Rc<F>,
         index: ActivityId<A>,
-        filter: SubscriptionFilter,
-    ) -> Handler
+    ) -> RefHandler
+    where
+        A: Activity,
+        F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
+        MSG: Any,
+    {
+        Box::new(
+            move |activities: &ActivityContainer,
+                  managed_state: &mut ManagedState,
+                  msg: &dyn Any| {
+                activities
+                    .handle(index, |a| {
+                        let msg = msg.downcast_ref().expect("Bug: wrong message broadcasted");
+                        managed_state.count_domain_borrow();
+                        let i = index.domain_index.index().expect("Activity has no domain");
+                        f(a, &mut managed_state.domains[i], msg)
+                    })
+                    .is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_closure_by_name(f: BoxedHandler, index: UncheckedActivityId) -> Handler {
+        Box::new(
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                let msg = managed_state
+                    .broadcast
+                    .as_deref()
+                    .expect("Bug: nothing broadcasted");
+                activities.handle_untyped(index, |a| f(a, msg))
+            },
+        )
+    }
+    /// The handler skips messages that have already been taken by a subscriber with ownership.
+    pub(crate) fn pack_closure_any<A, F>(f: Rc<F>, index: ActivityId<A>) -> Handler
+    where
+        A: Activity,
+        F: Fn(&mut A, &dyn Any) + 'static,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                let msg = match managed_state.broadcast.as_deref() {
+                    Some(msg) => msg,
+                    None => return false,
+                };
+                activities.handle(index, |a| f(a, msg)).is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_closure_any_by_ref<A, F>(f: Rc<F>, index: ActivityId<A>) -> RefHandler
+    where
+        A: Activity,
+        F: Fn(&mut A, &dyn Any) + 'static,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, _: &mut ManagedState, msg: &dyn Any| {
+                activities.handle(index, |a| f(a, msg)).is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_closure_mut<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
     where
         A: Activity,
         F: Fn(&mut A, &mut MSG) + 'static,
         MSG: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let msg = managed_state.current_broadcast();
-                    f(a, msg)
-                }
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let msg = managed_state.current_broadcast();
+                        f(a, msg)
+                    })
+                    .is_some()
             },
         )
     }
-    pub(crate) fn pack_closure_owned<A, F, MSG>(
-        f: F,
-        index: ActivityId<A>,
-        filter: SubscriptionFilter,
-    ) -> Handler
+    pub(crate) fn pack_closure_owned<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
     where
         A: Activity,
         F: Fn(&mut A, MSG) + 'static,
         MSG: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let msg = managed_state.take_current_broadcast();
-                    f(a, *msg)
-                }
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let msg = managed_state.take_current_broadcast();
+                        f(a, *msg)
+                    })
+                    .is_some()
             },
         )
     }
-    pub(crate) fn pack_domained_closure<A, F, MSG>(
-        f: F,
-        index: ActivityId<A>,
-        filter: SubscriptionFilter,
-    ) -> Handler
+    pub(crate) fn pack_closure_with_services<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
+    where
+        A: Activity,
+        F: Fn(&mut A, &dyn Any, &MSG) + 'static,
+        MSG: Any,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let (msg, services) = managed_state.current_broadcast_and_services();
+                        f(a, services, msg)
+                    })
+                    .is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_domained_closure<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
     where
         A: Activity,
         F: Fn(&mut A, &mut DomainState, &MSG) + 'static,
         MSG: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let (msg, domain) =
-                        managed_state.current_broadcast_and_domain(index.domain_index);
-                    f(a, domain, msg)
-                }
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let (msg, domain) =
+                            managed_state.current_broadcast_and_domain(index.domain_index);
+                        f(a, domain, msg)
+                    })
+                    .is_some()
             },
         )
     }
-    pub(crate) fn pack_domained_closure_mut<A, F, MSG>(
+    pub(crate) fn pack_domained_closure_with<A, F, MSG>(
         f: F,
         index: ActivityId<A>,
-        filter: SubscriptionFilter,
+        extra: DomainId,
     ) -> Handler
     where
         A: Activity,
-        F: Fn(&mut A, &mut DomainState, &mut MSG) + 'static,
+        F: Fn(&mut A, &mut DomainState, &DomainState, &MSG) + 'static,
         MSG: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let (msg, domain) =
-                        managed_state.current_broadcast_and_domain(index.domain_index);
-                    f(a, domain, msg)
-                }
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let (msg, domain, extra) =
+                            managed_state.current_broadcast_and_domains(index.domain_index, extra);
+                        f(a, domain, extra, msg)
+                    })
+                    .is_some()
             },
         )
     }
-    pub(crate) fn pack_domained_closure_owned<A, F, MSG>(
+    pub(crate) fn pack_domained_closure_requires<A, F, MSG, REQ>(
         f: F,
         index: ActivityId<A>,
-        filter: SubscriptionFilter,
     ) -> Handler
     where
         A: Activity,
-        F: Fn(&mut A, &mut DomainState, MSG) + 'static,
+        F: Fn(&mut A, &REQ, &MSG) + 'static,
         MSG: Any,
+        REQ: Any,
     {
         Box::new(
-            move |activities: &mut ActivityContainer, managed_state: &mut ManagedState| {
-                if activities.filter(index, &filter) {
-                    let a = activities[index]
-                        .downcast_mut::<A>()
-                        .expect("Wrong activity"); // deleted and replaced?
-                    let (msg, domain) =
-                        managed_state.take_current_broadcast_and_borrow_domain(index.domain_index);
-                    f(a, domain, *msg)
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                let (msg, domain) = managed_state.current_broadcast_and_domain(index.domain_index);
+                if let Some(required) = domain.try_get::<REQ>() {
+                    activities.handle(index, |a| f(a, required, msg)).is_some()
+                } else {
+                    false
                 }
             },
         )
     }
+    pub(crate) fn pack_domained_closure_mut<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
+    where
+        A: Activity,
+        F: Fn(&mut A, &mut DomainState, &mut MSG) + 'static,
+        MSG: Any,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let (msg, domain) =
+                            managed_state.current_broadcast_and_domain(index.domain_index);
+                        f(a, domain, msg)
+                    })
+                    .is_some()
+            },
+        )
+    }
+    pub(crate) fn pack_domained_closure_owned<A, F, MSG>(f: F, index: ActivityId<A>) -> Handler
+    where
+        A: Activity,
+        F: Fn(&mut A, &mut DomainState, MSG) + 'static,
+        MSG: Any,
+    {
+        Box::new(
+            move |activities: &ActivityContainer, managed_state: &mut ManagedState| {
+                activities
+                    .handle(index, |a| {
+                        let (msg, domain) = managed_state
+                            .take_current_broadcast_and_borrow_domain(index.domain_index);
+                        f(a, domain, *msg)
+                    })
+                    .is_some()
+            },
+        )
+    }
 }
@@ -0,0 +1,86 @@
+use super::DomainId;
+use crate::UncheckedActivityId;
+use core::any::TypeId;
+use std::collections::BTreeMap;
+
+/// Records which domain types each activity accessed, created by [`nuts::domain_access_report`](fn.domain_access_report.html).
+///
+/// Only accesses inside handlers are recorded, and only after [`nuts::enable_domain_profiling`](fn.enable_domain_profiling.html) has been called.
+#[derive(Debug, Clone, Default)]
+pub struct AccessReport {
+    activities: BTreeMap<UncheckedActivityId, DomainAccess>,
+}
+
+/// The domain types accessed by a single activity, part of an [`AccessReport`](struct.AccessReport.html).
+#[derive(Debug, Clone, Default)]
+pub struct DomainAccess {
+    domain: DomainId,
+    reads: BTreeMap<TypeId, &'static str>,
+    writes: BTreeMap<TypeId, &'static str>,
+}
+
+impl AccessReport {
+    /// The accesses of an activity, `None` if it has not accessed its domain in any handler
+    pub fn activity(&self, id: impl Into<UncheckedActivityId>) -> Option<&DomainAccess> {
+        self.activities.get(&id.into())
+    }
+    /// All activities that have accessed their domain, ordered by id
+    pub fn activities(&self) -> impl Iterator<Item = (UncheckedActivityId, &DomainAccess)> {
+        self.activities.iter().map(|(id, access)| (*id, access))
+    }
+    /// True if both activities access the same type in the same domain and at least one of them writes to it.
+    ///
+    /// Activities without conflicts could run in parallel.
+    pub fn conflicts(
+        &self,
+        a: impl Into<UncheckedActivityId>,
+        b: impl Into<UncheckedActivityId>,
+    ) -> bool {
+        match (self.activity(a), self.activity(b)) {
+            (Some(a), Some(b)) => {
+                a.domain == b.domain && (a.writes_any_of(b) || b.writes_any_of(a))
+            }
+            _ => false,
+        }
+    }
+    pub(crate) fn record(
+        &mut self,
+        id: UncheckedActivityId,
+        domain: DomainId,
+        access: DomainAccess,
+    ) {
+        let entry = self.activities.entry(id).or_default();
+        entry.domain = domain;
+        entry.reads.extend(access.reads);
+        entry.writes.extend(access.writes);
+    }
+}
+
+impl DomainAccess {
+    /// The domain the activity accessed
+    pub fn domain(&self) -> DomainId {
+        self.domain
+    }
+    /// Type names of all values read with shared access, sorted by type id
+    pub fn reads(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.reads.values().copied()
+    }
+    /// Type names of all values stored or borrowed mutably, sorted by type id
+    pub fn writes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.writes.values().copied()
+    }
+    pub(crate) fn read(&mut self, id: TypeId, name: &'static str) {
+        self.reads.insert(id, name);
+    }
+    pub(crate) fn write(&mut self, id: TypeId, name: &'static str) {
+        self.writes.insert(id, name);
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+    fn writes_any_of(&self, other: &DomainAccess) -> bool {
+        self.writes
+            .keys()
+            .any(|id| other.reads.contains_key(id) || other.writes.contains_key(id))
+    }
+}
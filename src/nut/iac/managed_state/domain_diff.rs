@@ -0,0 +1,85 @@
+use core::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// Type-erased operations of a type registered with `nuts::register_diffable`
+#[derive(Clone, Copy)]
+pub(crate) struct DiffableType {
+    name: &'static str,
+    clone: fn(&dyn Any) -> Box<dyn Any>,
+    eq: fn(&dyn Any, &dyn Any) -> bool,
+}
+
+impl DiffableType {
+    pub(crate) fn of<T: Any + Clone + PartialEq>() -> Self {
+        Self {
+            name: std::any::type_name::<T>(),
+            clone: |obj| Box::new(downcast::<T>(obj).clone()),
+            eq: |a, b| downcast::<T>(a) == downcast::<T>(b),
+        }
+    }
+    pub(crate) fn clone_object(&self, obj: &dyn Any) -> Box<dyn Any> {
+        (self.clone)(obj)
+    }
+}
+
+fn downcast<T: Any>(obj: &dyn Any) -> &T {
+    obj.downcast_ref().expect("Bug: wrong diff function")
+}
+
+/// A copy of the diffable values in a domain, created by [`nuts::snapshot_domain_for_diff`](fn.snapshot_domain_for_diff.html).
+///
+/// Only values of types registered with [`nuts::register_diffable`](fn.register_diffable.html) are included.
+/// Compare two snapshots with [`diff`](#method.diff) to find out what changed in between.
+#[derive(Default)]
+pub struct DomainDiffSnapshot {
+    objects: HashMap<TypeId, (DiffableType, Box<dyn Any>)>,
+}
+
+/// A difference between two [`DomainDiffSnapshot`s](struct.DomainDiffSnapshot.html), each variant holds the name of the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changed {
+    /// The type is only in the later snapshot
+    Added(&'static str),
+    /// The type is only in the earlier snapshot
+    Removed(&'static str),
+    /// The type is in both snapshots, with values that are not equal
+    Modified(&'static str),
+}
+
+impl DomainDiffSnapshot {
+    pub(crate) fn new(objects: HashMap<TypeId, (DiffableType, Box<dyn Any>)>) -> Self {
+        Self { objects }
+    }
+    /// Lists all changes from this snapshot to a later one, sorted by type name.
+    pub fn diff(&self, later: &DomainDiffSnapshot) -> Vec<Changed> {
+        let mut changes: Vec<Changed> = self
+            .objects
+            .iter()
+            .filter_map(|(key, (ty, before))| match later.objects.get(key) {
+                None => Some(Changed::Removed(ty.name)),
+                Some((_, after)) if !(ty.eq)(before.as_ref(), after.as_ref()) => {
+                    Some(Changed::Modified(ty.name))
+                }
+                Some(_) => None,
+            })
+            .chain(
+                later
+                    .objects
+                    .iter()
+                    .filter(|(key, _)| !self.objects.contains_key(key))
+                    .map(|(_, (ty, _))| Changed::Added(ty.name)),
+            )
+            .collect();
+        changes.sort_by_key(|change| change.type_name());
+        changes
+    }
+}
+
+impl Changed {
+    /// Name of the changed type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Added(name) | Self::Removed(name) | Self::Modified(name) => name,
+        }
+    }
+}
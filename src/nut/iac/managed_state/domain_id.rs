@@ -1,5 +1,8 @@
+/// Identifies a domain at runtime, independent of the domain type.
+///
+/// Obtain it with [`DomainId::new`](#method.new) to compare it with ids passed to callbacks, e.g. by [`set_domain_mirror`](fn.set_domain_mirror.html).
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
-pub(crate) struct DomainId(Option<usize>);
+pub struct DomainId(pub(super) Option<usize>);
 
 /// Used for mapping domain identifiers to unique integers.
 /// Can be derived with `domain_enum!(TYPE)`;
@@ -20,7 +23,8 @@ impl DomainEnumeration for DefaultDomain {
 }
 
 impl DomainId {
-    pub(crate) fn new(d: &impl DomainEnumeration) -> DomainId {
+    /// The id of a specific domain
+    pub fn new(d: &impl DomainEnumeration) -> DomainId {
         DomainId(Some(d.id()))
     }
     pub(crate) fn index(&self) -> Option<usize> {
@@ -29,7 +33,7 @@ impl DomainId {
 }
 
 #[macro_export]
-/// Implements DomainEnumeration for an enum.
+/// Implements `DomainEnumeration` for an enum.
 ///
 /// This macro can only be used on primitive enums that implement Copy.
 /// The current implementation of the macro unfortunately also requires
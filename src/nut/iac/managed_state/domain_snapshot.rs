@@ -0,0 +1,46 @@
+use core::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Function that clones a type-erased object, used to snapshot domain state.
+pub(crate) type CloneFn = fn(&dyn Any) -> Box<dyn Any + Send + Sync>;
+
+pub(crate) fn clone_any<T: Any + Clone + Send + Sync>(obj: &dyn Any) -> Box<dyn Any + Send + Sync> {
+    Box::new(
+        obj.downcast_ref::<T>()
+            .expect("Bug: wrong clone function")
+            .clone(),
+    )
+}
+
+/// An immutable point-in-time copy of the data stored in a domain.
+///
+/// Create one with [`nuts::snapshot_domain`](fn.snapshot_domain.html).
+/// Only values of types registered with [`nuts::register_snapshot_type`](fn.register_snapshot_type.html) are included.
+///
+/// The snapshot does not change when the domain is modified afterwards.
+/// Cloning a snapshot is cheap, the data is shared between all clones.
+/// Snapshots can also be sent to other threads.
+#[derive(Clone, Default)]
+pub struct DomainSnapshot {
+    objects: Arc<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+}
+
+impl DomainSnapshot {
+    pub(crate) fn new(objects: HashMap<TypeId, Box<dyn Any + Send + Sync>>) -> Self {
+        Self {
+            objects: Arc::new(objects),
+        }
+    }
+    /// Returns a reference to a value of the specified type, if it has been in the domain when the snapshot was taken.
+    #[allow(clippy::unwrap_used)]
+    pub fn try_get<T: Any>(&self) -> Option<&T> {
+        self.objects
+            .get(&TypeId::of::<T>())
+            .map(|obj| (obj.as_ref() as &dyn Any).downcast_ref().unwrap())
+    }
+    /// Returns true if the snapshot contains a value of the specified type.
+    pub fn contains<T: Any>(&self) -> bool {
+        self.objects.contains_key(&TypeId::of::<T>())
+    }
+}
@@ -1,4 +1,9 @@
-use core::any::{Any, TypeId};
+use super::{CloneFn, DiffableType, DomainAccess, DomainTransaction, DomainView};
+use crate::nut::hash::FxHashMap;
+use core::any::{type_name, Any, TypeId};
+use core::cell::RefCell;
+use core::convert::Infallible;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
 /// Stores passive data that can be accessed in event handlers of multiple activities.
@@ -17,7 +22,11 @@ use std::collections::HashMap;
 // @ END-DOC DOMAIN
 #[derive(Default)]
 pub struct DomainState {
-    objects: HashMap<TypeId, Box<dyn Any>>,
+    objects: FxHashMap<TypeId, Box<dyn Any>>,
+    /// Types written or borrowed mutably since the last call to `take_written`, only recorded while mirrored
+    written: Option<Vec<TypeId>>,
+    /// Accesses since the last call to `take_accesses`, only recorded while profiling
+    accesses: Option<RefCell<DomainAccess>>,
 }
 
 impl DomainState {
@@ -27,18 +36,108 @@ impl DomainState {
     /// If an old value of the same type already exists in the domain, it will be overwritten.
     // @ END-DOC DOMAIN_STORE
     pub fn store<T: Any>(&mut self, obj: T) {
+        self.record_write::<T>();
         self.objects.insert(TypeId::of::<T>(), Box::new(obj));
     }
+    /// Replaces the value of type `T` in a single operation and returns the previous value, if any.
+    ///
+    /// Useful for double-buffering, where the old value is reused for the next frame.
+    #[allow(clippy::unwrap_used)]
+    pub fn swap<T: Any>(&mut self, new: T) -> Option<T> {
+        self.record_write::<T>();
+        self.objects
+            .insert(TypeId::of::<T>(), Box::new(new))
+            .map(|old| *old.downcast().unwrap())
+    }
+    /// Removes the value of type `T` from the domain and returns it, if there was one.
+    ///
+    /// ### Example
+    /// ```rust
+    /// let mut domain = nuts::DomainState::default();
+    /// domain.store(7u32);
+    /// assert_eq!(Some(7), domain.remove::<u32>());
+    /// assert_eq!(None, domain.remove::<u32>());
+    /// ```
+    #[allow(clippy::unwrap_used)]
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.record_write::<T>();
+        self.objects
+            .remove(&TypeId::of::<T>())
+            .map(|old| *old.downcast().unwrap())
+    }
     /// Returns a reference to a value of the specified type, if such a value has previously been stored to the domain.
     #[allow(clippy::unwrap_used)]
     pub fn try_get<T: Any>(&self) -> Option<&T> {
+        self.record_read::<T>();
         self.objects
             .get(&TypeId::of::<T>())
             .map(|obj| obj.as_ref().downcast_ref().unwrap())
     }
+    /// Creates a view of the domain that only grants access to the given types.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use std::any::TypeId;
+    /// struct Score(u32);
+    /// struct Secret;
+    /// let mut domain = nuts::DomainState::default();
+    /// domain.store(Secret);
+    ///
+    /// let allowed = [TypeId::of::<Score>()];
+    /// let mut view = domain.view(&allowed);
+    /// view.store(Score(7)).unwrap();
+    /// assert!(view.store(Secret).is_err());
+    /// assert!(view.try_get::<Secret>().is_none());
+    /// assert_eq!(7, view.try_get::<Score>().unwrap().0);
+    /// ```
+    pub fn view<'a>(&'a mut self, allowed: &'a [TypeId]) -> DomainView<'a> {
+        DomainView::new(self, allowed)
+    }
+    /// Applies several changes to the domain, either all of them or none.
+    ///
+    /// If the closure returns an error, all values changed through the transaction are restored, including removed values.
+    /// Values can only be borrowed mutably inside a transaction if they implement `Clone`, since a copy is needed for the rollback.
+    ///
+    /// # Errors
+    /// Returns the error of the closure, after the rollback.
+    ///
+    /// ### Example
+    /// ```rust
+    /// #[derive(Clone)]
+    /// struct Gold(u32);
+    /// struct Sword;
+    /// let mut domain = nuts::DomainState::default();
+    /// domain.store(Gold(10));
+    ///
+    /// let result = domain.transaction(|tx| {
+    ///     tx.store(Sword);
+    ///     let gold = tx.try_get_mut::<Gold>().unwrap();
+    ///     gold.0 = gold.0.checked_sub(50).ok_or("not enough gold")?;
+    ///     Ok(())
+    /// });
+    /// assert_eq!(Err("not enough gold"), result);
+    /// assert_eq!(10, domain.get::<Gold>().0);
+    /// assert!(domain.try_get::<Sword>().is_none());
+    /// ```
+    pub fn transaction<R, E>(
+        &mut self,
+        f: impl FnOnce(&mut DomainTransaction) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let mut tx = DomainTransaction::new(self);
+        let result = f(&mut tx);
+        if result.is_err() {
+            tx.rollback();
+        }
+        result
+    }
+    /// True if a value of the specified type has been stored to the domain and it satisfies the predicate.
+    pub fn any<T: Any>(&self, pred: impl Fn(&T) -> bool) -> bool {
+        self.try_get::<T>().is_some_and(pred)
+    }
     /// Same as [`try_get`](#try_get) but grants mutable access to the object.
     #[allow(clippy::unwrap_used)]
     pub fn try_get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.record_write::<T>();
         self.objects
             .get_mut(&TypeId::of::<T>())
             .map(|obj| obj.as_mut().downcast_mut().unwrap())
@@ -49,6 +148,7 @@ impl DomainState {
     /// [`try_get()`](#try_get) is usually recommended instead.
     #[allow(clippy::unwrap_used)]
     pub fn get<T: Any>(&self) -> &T {
+        self.record_read::<T>();
         self.objects
             .get(&TypeId::of::<T>())
             .map(|obj| obj.as_ref().downcast_ref().unwrap())
@@ -60,9 +160,149 @@ impl DomainState {
     /// [`try_get_mut()`](#try_get_mut) is usually recommended instead.
     #[allow(clippy::unwrap_used)]
     pub fn get_mut<T: Any>(&mut self) -> &mut T {
+        self.record_write::<T>();
         self.objects
             .get_mut(&TypeId::of::<T>())
             .map(|obj| obj.as_mut().downcast_mut().unwrap())
             .expect("Not in domain")
     }
+    /// Returns a mutable reference to a value of the specified type.
+    /// If no such value is stored in the domain, the default value is stored first.
+    #[allow(clippy::unwrap_used)]
+    pub fn get_or_default<T: Any + Default>(&mut self) -> &mut T {
+        self.record_write::<T>();
+        self.objects
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .as_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+    /// Returns a mutable reference to a value of the specified type.
+    /// If no such value is stored in the domain, it is created with `f` and stored first.
+    ///
+    /// ### Example
+    /// ```rust
+    /// let mut domain = nuts::DomainState::default();
+    /// domain.get_or_insert_with(Vec::new).push(1u32);
+    /// domain.get_or_insert_with(Vec::new).push(2u32);
+    /// assert_eq!(&vec![1, 2], domain.get::<Vec<u32>>());
+    /// ```
+    pub fn get_or_insert_with<T: Any>(&mut self, f: impl FnOnce() -> T) -> &mut T {
+        match self.get_or_try_insert_with(|| Ok::<T, Infallible>(f())) {
+            Ok(obj) => obj,
+            Err(never) => match never {},
+        }
+    }
+    /// Returns a mutable reference to a value of the specified type.
+    /// If no such value is stored in the domain, it is created with `f` and stored first.
+    ///
+    /// # Errors
+    /// If `f` fails, its error is returned and nothing is stored.
+    #[allow(clippy::unwrap_used)]
+    pub fn get_or_try_insert_with<T, E, F>(&mut self, f: F) -> Result<&mut T, E>
+    where
+        T: Any,
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Entry::Vacant(entry) = self.objects.entry(TypeId::of::<T>()) {
+            entry.insert(Box::new(f()?));
+        }
+        self.record_write::<T>();
+        let obj = self.objects.get_mut(&TypeId::of::<T>()).unwrap();
+        Ok(obj.as_mut().downcast_mut().unwrap())
+    }
+    /// Replaces or removes the boxed value of type `T`, returning the previous one.
+    pub(crate) fn swap_boxed<T: Any>(&mut self, new: Option<Box<dyn Any>>) -> Option<Box<dyn Any>> {
+        self.record_write::<T>();
+        match new {
+            Some(new) => self.objects.insert(TypeId::of::<T>(), new),
+            None => self.objects.remove(&TypeId::of::<T>()),
+        }
+    }
+    /// Puts back a value previously returned by `swap_boxed`.
+    pub(crate) fn restore(&mut self, id: TypeId, old: Option<Box<dyn Any>>) {
+        if let Some(written) = &mut self.written {
+            written.push(id);
+        }
+        match old {
+            Some(old) => self.objects.insert(id, old),
+            None => self.objects.remove(&id),
+        };
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    /// Removes all objects, which counts as a write to each of them.
+    pub(crate) fn clear(&mut self) {
+        if let Some(written) = &mut self.written {
+            written.extend(self.objects.keys());
+        }
+        self.objects.clear();
+    }
+    fn record_read<T: Any>(&self) {
+        if let Some(accesses) = &self.accesses {
+            accesses
+                .borrow_mut()
+                .read(TypeId::of::<T>(), type_name::<T>());
+        }
+    }
+    fn record_write<T: Any>(&mut self) {
+        if let Some(written) = &mut self.written {
+            written.push(TypeId::of::<T>());
+        }
+        if let Some(accesses) = &mut self.accesses {
+            accesses
+                .get_mut()
+                .write(TypeId::of::<T>(), type_name::<T>());
+        }
+    }
+    /// Starts recording which types are read and written, see `nuts::enable_domain_profiling`
+    pub(crate) fn enable_profiling(&mut self) {
+        self.accesses.get_or_insert_with(Default::default);
+    }
+    /// Accesses since the last call, `None` if there were none or profiling is disabled.
+    pub(crate) fn take_accesses(&mut self) -> Option<DomainAccess> {
+        let accesses = std::mem::take(self.accesses.as_mut()?.get_mut());
+        (!accesses.is_empty()).then_some(accesses)
+    }
+    /// Starts recording which types are written, see `nuts::set_domain_mirror`
+    pub(crate) fn enable_mirroring(&mut self) {
+        self.written.get_or_insert_with(Default::default);
+    }
+    /// Types written or borrowed mutably since the last call, without duplicates.
+    pub(crate) fn take_written(&mut self) -> Vec<TypeId> {
+        let mut written = self
+            .written
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        written.sort_unstable();
+        written.dedup();
+        written
+    }
+    /// Clones all objects for which a clone function is provided.
+    pub(crate) fn clone_objects(
+        &self,
+        cloners: &HashMap<TypeId, CloneFn>,
+    ) -> HashMap<TypeId, Box<dyn Any + Send + Sync>> {
+        self.objects
+            .iter()
+            .filter_map(|(key, obj)| cloners.get(key).map(|clone| (*key, clone(obj.as_ref()))))
+            .collect()
+    }
+    /// Clones all objects of registered diffable types, together with their type.
+    pub(crate) fn clone_diffable(
+        &self,
+        types: &HashMap<TypeId, DiffableType>,
+    ) -> HashMap<TypeId, (DiffableType, Box<dyn Any>)> {
+        self.objects
+            .iter()
+            .filter_map(|(key, obj)| {
+                types
+                    .get(key)
+                    .map(|ty| (*key, (*ty, ty.clone_object(obj.as_ref()))))
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,61 @@
+use super::DomainState;
+use core::any::{Any, TypeId};
+
+/// Changes to a domain that are undone if the transaction fails.
+///
+/// Created by [`DomainState::transaction`](struct.DomainState.html#method.transaction).
+/// The first time a type is changed, its previous value is kept aside, such that it can be restored on failure.
+pub struct DomainTransaction<'a> {
+    domain: &'a mut DomainState,
+    /// Previous value of each changed type, `None` if there was none
+    undo: Vec<(TypeId, Option<Box<dyn Any>>)>,
+}
+
+impl<'a> DomainTransaction<'a> {
+    pub(crate) fn new(domain: &'a mut DomainState) -> Self {
+        Self {
+            domain,
+            undo: Vec::new(),
+        }
+    }
+    /// Stores a value in the domain, like [`DomainState::store`](struct.DomainState.html#method.store).
+    pub fn store<T: Any>(&mut self, obj: T) {
+        let old = self.domain.swap_boxed::<T>(Some(Box::new(obj)));
+        if !self.is_changed::<T>() {
+            self.undo.push((TypeId::of::<T>(), old));
+        }
+    }
+    /// Removes the value of type `T` from the domain.
+    /// Returns true if there was a value to remove.
+    pub fn remove<T: Any>(&mut self) -> bool {
+        let old = self.domain.swap_boxed::<T>(None);
+        let removed = old.is_some();
+        if !self.is_changed::<T>() {
+            self.undo.push((TypeId::of::<T>(), old));
+        }
+        removed
+    }
+    /// Returns a reference to a value of the specified type, including changes of the transaction.
+    pub fn try_get<T: Any>(&self) -> Option<&T> {
+        self.domain.try_get()
+    }
+    /// Same as [`try_get`](#method.try_get) but grants mutable access to the object.
+    ///
+    /// The value must be `Clone`, because a copy is kept for the rollback.
+    pub fn try_get_mut<T: Any + Clone>(&mut self) -> Option<&mut T> {
+        if !self.is_changed::<T>() {
+            let old = self.domain.try_get::<T>()?.clone();
+            self.undo.push((TypeId::of::<T>(), Some(Box::new(old))));
+        }
+        self.domain.try_get_mut()
+    }
+    fn is_changed<T: Any>(&self) -> bool {
+        self.undo.iter().any(|(id, _)| *id == TypeId::of::<T>())
+    }
+    /// Restores all values changed by the transaction.
+    pub(crate) fn rollback(self) {
+        for (id, old) in self.undo.into_iter().rev() {
+            self.domain.restore(id, old);
+        }
+    }
+}
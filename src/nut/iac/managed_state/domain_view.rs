@@ -0,0 +1,47 @@
+use super::DomainState;
+use crate::NutsError;
+use core::any::{Any, TypeId};
+
+/// Access to a domain that is restricted to a fixed set of types.
+///
+/// Create one with [`DomainState::view`](struct.DomainState.html#method.view), for example to hand limited access to plugin code.
+/// Types that are not allowed behave as if they were not stored in the domain.
+pub struct DomainView<'a> {
+    domain: &'a mut DomainState,
+    allowed: &'a [TypeId],
+}
+
+impl<'a> DomainView<'a> {
+    pub(crate) fn new(domain: &'a mut DomainState, allowed: &'a [TypeId]) -> Self {
+        Self { domain, allowed }
+    }
+    /// True if the view grants access to values of type `T`
+    pub fn allows<T: Any>(&self) -> bool {
+        self.allowed.contains(&TypeId::of::<T>())
+    }
+    /// Stores a value in the domain, like [`DomainState::store`](struct.DomainState.html#method.store).
+    ///
+    /// # Errors
+    /// Returns [`NutsError::TypeNotAllowed`](enum.NutsError.html#variant.TypeNotAllowed) and drops the value if the view does not allow the type.
+    pub fn store<T: Any>(&mut self, obj: T) -> Result<(), NutsError> {
+        if !self.allows::<T>() {
+            return Err(NutsError::TypeNotAllowed);
+        }
+        self.domain.store(obj);
+        Ok(())
+    }
+    /// Returns a reference to a value of the specified type, if it is allowed and stored in the domain.
+    pub fn try_get<T: Any>(&self) -> Option<&T> {
+        if !self.allows::<T>() {
+            return None;
+        }
+        self.domain.try_get()
+    }
+    /// Same as [`try_get`](#method.try_get) but grants mutable access to the object.
+    pub fn try_get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        if !self.allows::<T>() {
+            return None;
+        }
+        self.domain.try_get_mut()
+    }
+}
@@ -1,20 +1,280 @@
 pub(crate) use broadcast::BroadcastInfo;
+pub(crate) use deferred::DeferredMessage;
+
+/// Receives published messages without subscribers, see `nuts::set_dead_letter`
+pub(crate) type DeadLetterHandler = Box<dyn Fn(&'static str, &dyn Any)>;
+
+/// Converts a message of one type to a broadcast of another type.
+pub(crate) type MessageAlias = Box<dyn Fn(&dyn Any) -> BroadcastInfo>;
 
 mod broadcast;
+mod checked;
+mod counted;
+mod deferred;
+mod dispatch_set;
+mod domain;
+mod fold;
+mod history;
+mod idle;
+mod pipe;
+mod republish;
+mod required;
+mod sequence;
+mod settle;
+
+pub use dispatch_set::DispatchSet;
+pub use domain::DomainReach;
+pub(crate) use fold::Fold;
+pub(crate) use history::History;
+pub(crate) use idle::Idle;
+pub use pipe::PipeId;
+pub use republish::SelfRepublishPolicy;
+pub use required::RequiredMessage;
+pub use sequence::Sequence;
+pub(crate) use settle::Settled;
 
 use crate::nut::Nut;
 use crate::*;
 use core::any::Any;
 
 impl Nut {
-    pub(crate) fn publish_local<MSG: Any>(&self, id: UncheckedActivityId, topic: Topic, msg: MSG) {
-        let broadcast = BroadcastInfo::local(msg, id, topic);
-        self.deferred_events.push(broadcast.into());
+    /// Delivers a builtin event to a single activity right away, without going through the queue.
+    ///
+    /// only access after locking with executing flag
+    pub(crate) fn deliver_lifecycle_event(&self, id: UncheckedActivityId, topic: Topic) {
+        self.unchecked_broadcast(BroadcastInfo::local((), id, topic));
+    }
+    pub(crate) fn publish_to<MSG: Any>(&self, id: UncheckedActivityId, msg: MSG) {
+        let broadcast = BroadcastInfo::local(msg, id, Topic::message::<MSG>());
+        self.enqueue(broadcast);
+        self.catch_up_deferred_to_quiescence();
+    }
+    pub(crate) fn publish_to_including_inactive<MSG: Any>(
+        &self,
+        id: UncheckedActivityId,
+        msg: MSG,
+    ) {
+        let broadcast = BroadcastInfo::local_including_inactive(msg, id, Topic::message::<MSG>());
+        self.enqueue(broadcast);
+        self.catch_up_deferred_to_quiescence();
+    }
+    /// Publishes on a named topic, which bypasses captures, aliases, and the history of the message type.
+    pub(crate) fn publish_on_topic<MSG: Any>(&self, name: &'static str, msg: MSG) {
+        let topic = Topic::named::<MSG>(name);
+        let type_name = std::any::type_name::<MSG>();
+        if self.check_drop_publish(type_name) && self.check_self_republish(&topic, type_name) {
+            self.enqueue(BroadcastInfo::global(msg, topic));
+        }
         self.catch_up_deferred_to_quiescence();
     }
     pub(crate) fn publish<MSG: Any>(&self, msg: MSG) {
-        let broadcast = BroadcastInfo::global(msg, Topic::message::<MSG>());
-        self.deferred_events.push(broadcast.into());
+        if let Some((broadcast, aliased)) = self.prepare_publish(msg) {
+            // Fast path for the common case of a top-level publish, which can skip the queue
+            let broadcast = if aliased.is_empty() && !self.is_paused(broadcast.topic()) {
+                match self.try_broadcast_directly(broadcast) {
+                    Ok(()) => return,
+                    Err(broadcast) => broadcast,
+                }
+            } else {
+                broadcast
+            };
+            self.enqueue(broadcast);
+            for broadcast in aliased {
+                self.enqueue(broadcast);
+            }
+        }
         self.catch_up_deferred_to_quiescence();
     }
+    /// Only constructs and publishes the message if someone could receive it.
+    pub(crate) fn publish_with<MSG: Any>(&self, f: impl FnOnce() -> MSG) {
+        if self.has_receivers(&Topic::message::<MSG>()) {
+            self.publish(f());
+        } else if self.strict_unhandled.get() {
+            self.assert_handled::<MSG>();
+        }
+    }
+    /// Same as calling `publish` for each message, but the executing flag is only taken once for all of them.
+    ///
+    /// Subscriptions are still looked up for each message, since a cascade may change them, e.g. by consuming a `subscribe_once`.
+    pub(crate) fn publish_batch<MSG: Any>(&self, msgs: Vec<MSG>) {
+        let topic = Topic::message::<MSG>();
+        let batchable = !self.is_paused(&topic)
+            && !self.aliases.borrow().contains_key(&topic)
+            && self.capture.borrow().is_none()
+            && self.can_broadcast_directly();
+        if batchable {
+            let broadcasts = msgs
+                .into_iter()
+                .map(|msg| self.prepare_publish(msg).map(|(broadcast, _)| broadcast));
+            self.broadcast_batch(broadcasts);
+        } else {
+            for msg in msgs {
+                self.publish(msg);
+            }
+        }
+    }
+    /// Queues the message without delivering the queue.
+    pub(crate) fn enqueue_message<MSG: Any>(&self, msg: MSG) {
+        if let Some((broadcast, aliased)) = self.prepare_publish(msg) {
+            self.enqueue(broadcast);
+            for broadcast in aliased {
+                self.enqueue(broadcast);
+            }
+        }
+    }
+    /// Creates the broadcast for a published message and for its aliases.
+    /// Returns `None` if the message has been consumed, e.g. by a capture.
+    fn prepare_publish<MSG: Any>(&self, msg: MSG) -> Option<(BroadcastInfo, Vec<BroadcastInfo>)> {
+        if !self.check_drop_publish(std::any::type_name::<MSG>()) {
+            return None;
+        }
+        if !self.check_self_republish(&Topic::message::<MSG>(), std::any::type_name::<MSG>()) {
+            return None;
+        }
+        let msg = self.try_capture(msg)?;
+        let msg = self.try_take_response(msg)?;
+        if self.strict_unhandled.get() {
+            self.assert_handled::<MSG>();
+        }
+        let topic = Topic::message::<MSG>();
+        self.record_history(&topic, &msg);
+        let aliased: Vec<BroadcastInfo> = self
+            .aliases
+            .borrow()
+            .get(&topic)
+            .into_iter()
+            .flatten()
+            .map(|alias| alias(&msg))
+            .collect();
+        let broadcast =
+            BroadcastInfo::global(msg, topic).with_dead_letter(std::any::type_name::<MSG>());
+        Some((broadcast, aliased))
+    }
+    fn is_paused(&self, topic: &Topic) -> bool {
+        self.paused_topics.borrow().contains_key(topic)
+    }
+    /// Queues a global broadcast for delivery, or holds it back if its topic is paused.
+    fn enqueue(&self, broadcast: BroadcastInfo) {
+        if let Some(held) = self.paused_topics.borrow_mut().get_mut(broadcast.topic()) {
+            held.push(broadcast);
+            return;
+        }
+        let generation = if self.is_executing() {
+            self.current_generation.get() + 1
+        } else {
+            0
+        };
+        let broadcast = broadcast
+            .with_sender(self.current_activity.get())
+            .with_generation(generation);
+        self.deferred_events.push(broadcast.into());
+    }
+    pub(crate) fn pause_topic(&self, topic: Topic) {
+        self.paused_topics.borrow_mut().entry(topic).or_default();
+    }
+    /// Delivers all messages held back while the topic was paused, or only the last one if `coalesce` is set.
+    pub(crate) fn resume_topic(&self, topic: Topic, coalesce: bool) {
+        let held = self.paused_topics.borrow_mut().remove(&topic);
+        if let Some(mut held) = held {
+            if coalesce {
+                held.drain(..held.len().saturating_sub(1));
+            }
+            for broadcast in held {
+                self.deferred_events.push(broadcast.into());
+            }
+            self.catch_up_deferred_to_quiescence();
+        }
+    }
+    pub(crate) fn add_alias<OLD, NEW, F>(&self, f: F)
+    where
+        OLD: Any,
+        NEW: Any,
+        F: Fn(&OLD) -> NEW + 'static,
+    {
+        let alias: MessageAlias = Box::new(move |msg: &dyn Any| {
+            let old = msg.downcast_ref().expect("Bug: wrong message aliased");
+            BroadcastInfo::global(f(old), Topic::message::<NEW>())
+                .with_dead_letter(std::any::type_name::<NEW>())
+        });
+        self.aliases
+            .try_borrow_mut()
+            .expect(
+                "Tried to add a message alias while converting a message, which is not allowed.",
+            )
+            .entry(Topic::message::<OLD>())
+            .or_default()
+            .push(alias);
+    }
+    /// Rejects borrowed messages that cannot be delivered right away, see `nuts::publish_ref`.
+    pub(crate) fn check_publish_ref<MSG: Any>(&self) {
+        assert!(
+            !self.is_paused(&Topic::message::<MSG>()),
+            "`publish_ref` cannot hold back messages of the paused type `{}`.",
+            std::any::type_name::<MSG>()
+        );
+        if self.strict_unhandled.get() {
+            self.assert_handled::<MSG>();
+        }
+    }
+    /// Panics if no activity has subscribed to messages of type `MSG`.
+    ///
+    /// Checked before the message is queued, such that the panic occurs at the call site of `publish`.
+    fn assert_handled<MSG: Any>(&self) {
+        if !self.is_handled(&Topic::message::<MSG>()) {
+            panic!(
+                "Published message of type `{}` but nobody subscribed to it. (Strict mode is enabled)",
+                std::any::type_name::<MSG>()
+            );
+        }
+    }
+    /// True if any activity subscribed to the topic, or if messages of the topic are converted by an alias.
+    ///
+    /// Subscriptions of inactive activities count, removed subscriptions and deleted activities do not.
+    pub(crate) fn is_handled(&self, topic: &Topic) -> bool {
+        let activities = self.activities.borrow();
+        let subscribed = self
+            .subscriptions
+            .borrow()
+            .get(topic)
+            .is_some_and(|handlers| handlers.iter().any(|sub| activities.contains(sub.activity)));
+        subscribed || self.aliases.borrow().contains_key(topic)
+    }
+    /// Number of subscriptions to the topic, including those of inactive activities
+    pub(crate) fn subscriber_count(&self, topic: &Topic) -> usize {
+        self.subscriptions
+            .borrow()
+            .get(topic)
+            .map_or(0, |handlers| handlers.iter().count())
+    }
+    pub(crate) fn is_subscribed(&self, topic: &Topic, id: UncheckedActivityId) -> bool {
+        self.subscriptions
+            .borrow()
+            .get(topic)
+            .is_some_and(|handlers| handlers.iter_for(id).next().is_some())
+    }
+    /// True if a published message of the topic could currently be received by anyone.
+    ///
+    /// Subscriptions of activities that filter out the message because of their status are ignored.
+    /// Aliases, captures, and awaited responses count as receivers.
+    fn has_receivers(&self, topic: &Topic) -> bool {
+        let activities = self.activities.borrow();
+        let subscriptions = self.subscriptions.borrow();
+        let reachable = [*topic, Topic::Wildcard]
+            .iter()
+            .filter_map(|topic| subscriptions.get(topic))
+            .flat_map(|handlers| handlers.iter())
+            .any(|sub| sub.is_reachable(&activities));
+        reachable || self.aliases.borrow().contains_key(topic) || self.would_intercept(topic)
+    }
+    pub(crate) fn set_dead_letter(&self, f: DeadLetterHandler) {
+        *self
+            .dead_letter
+            .try_borrow_mut()
+            .expect("The dead letter handler cannot be replaced from inside itself.") = Some(f);
+    }
+    pub(crate) fn deliver_dead_letter(&self, type_name: &'static str, msg: &dyn Any) {
+        if let Some(f) = self.dead_letter.borrow().as_ref() {
+            f(type_name, msg);
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use crate::nut::iac::managed_state::ManagedState;
+use crate::nut::iac::subscription::Subscription;
 use crate::nut::Nut;
 use crate::*;
 use core::any::Any;
@@ -6,49 +8,205 @@ pub(crate) struct BroadcastInfo {
     address: BroadcastAddress,
     msg: Box<dyn Any>,
     topic: Topic,
+    /// Name of the message type, for diagnostics
+    type_name: &'static str,
+    /// The activity that published the message, if published from inside a handler
+    sender: Option<UncheckedActivityId>,
+    /// Number of messages in the chain of publishes that led to this message
+    generation: usize,
+    /// Type name passed to the dead letter handler if nobody subscribed to the message, only set for published messages
+    dead_letter: Option<&'static str>,
 }
 
 enum BroadcastAddress {
     Local(UncheckedActivityId),
+    /// Like `Local` but ignores the `active_only` filter of subscriptions
+    LocalIncludingInactive(UncheckedActivityId),
     Global,
+    /// Only the subscriptions with the given indices
+    Selected(Vec<usize>),
+    /// The receiving end of a pipe, bypassing the subscriptions of the topic
+    Pipe(usize),
+    /// Subscriptions of activities in the given domains
+    Domains(Vec<DomainId>),
 }
 
 impl BroadcastInfo {
-    pub(super) fn global<MSG: Any>(msg: MSG, topic: Topic) -> Self {
+    fn new<MSG: Any>(address: BroadcastAddress, msg: MSG, topic: Topic) -> Self {
         BroadcastInfo {
-            address: BroadcastAddress::Global,
+            address,
             msg: Box::new(msg),
             topic,
+            type_name: std::any::type_name::<MSG>(),
+            sender: None,
+            generation: 0,
+            dead_letter: None,
         }
     }
-    pub(super) fn local<MSG: Any>(msg: MSG, id: UncheckedActivityId, topic: Topic) -> Self {
-        BroadcastInfo {
-            address: BroadcastAddress::Local(id),
-            msg: Box::new(msg),
-            topic,
-        }
+    pub(super) fn global<MSG: Any>(msg: MSG, topic: Topic) -> Self {
+        Self::new(BroadcastAddress::Global, msg, topic)
+    }
+    pub(crate) fn local<MSG: Any>(msg: MSG, id: UncheckedActivityId, topic: Topic) -> Self {
+        Self::new(BroadcastAddress::Local(id), msg, topic)
+    }
+    pub(super) fn local_including_inactive<MSG: Any>(
+        msg: MSG,
+        id: UncheckedActivityId,
+        topic: Topic,
+    ) -> Self {
+        Self::new(BroadcastAddress::LocalIncludingInactive(id), msg, topic)
+    }
+    pub(crate) fn selected<MSG: Any>(msg: MSG, indices: Vec<usize>, topic: Topic) -> Self {
+        Self::new(BroadcastAddress::Selected(indices), msg, topic)
+    }
+    pub(super) fn pipe<MSG: Any>(msg: MSG, pipe: usize, topic: Topic) -> Self {
+        Self::new(BroadcastAddress::Pipe(pipe), msg, topic)
+    }
+    pub(super) fn domains<MSG: Any>(msg: MSG, domains: Vec<DomainId>, topic: Topic) -> Self {
+        Self::new(BroadcastAddress::Domains(domains), msg, topic)
+    }
+    pub(crate) fn with_sender(mut self, sender: Option<UncheckedActivityId>) -> Self {
+        self.sender = sender;
+        self
+    }
+    pub(crate) fn with_dead_letter(mut self, type_name: &'static str) -> Self {
+        self.dead_letter = Some(type_name);
+        self
+    }
+    pub(crate) fn with_generation(mut self, generation: usize) -> Self {
+        self.generation = generation;
+        self
+    }
+    pub(crate) fn generation(&self) -> usize {
+        self.generation
+    }
+    pub(crate) fn topic(&self) -> &Topic {
+        &self.topic
+    }
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.type_name
     }
 }
 
 impl Nut {
     /// only access after locking with executing flag
-    pub(crate) fn unchecked_broadcast(&self, broadcast: BroadcastInfo) {
+    ///
+    /// Returns the message after delivery, unless a subscriber took ownership of it.
+    pub(crate) fn unchecked_broadcast(&self, broadcast: BroadcastInfo) -> Option<Box<dyn Any>> {
+        if let Some(type_name) = broadcast.dead_letter {
+            if !self.is_handled(&broadcast.topic) {
+                // Wildcard subscriptions still observe the message
+                self.deliver_dead_letter(type_name, broadcast.msg.as_ref());
+            }
+        }
+        let to_wildcards = matches!(broadcast.address, BroadcastAddress::Global)
+            && broadcast.topic.message_type().is_some();
         let mut managed_state = self.managed_state.borrow_mut();
         managed_state.set_broadcast(broadcast.msg);
-        if let Some(handlers) = self.subscriptions.borrow().get(&broadcast.topic) {
+        self.current_generation.set(broadcast.generation);
+        self.current_topic.set(Some(broadcast.topic));
+        if let BroadcastAddress::Pipe(index) = broadcast.address {
+            self.deliver(
+                &self.pipes.borrow()[index],
+                &mut managed_state,
+                broadcast.sender,
+                false,
+            );
+        } else if let Some(handlers) = self.subscriptions.borrow().get(&broadcast.topic) {
             match broadcast.address {
                 BroadcastAddress::Global => {
-                    for f in handlers.iter() {
-                        f(&mut self.activities.borrow_mut(), &mut managed_state);
+                    for sub in handlers.iter() {
+                        self.deliver(sub, &mut managed_state, broadcast.sender, false);
                     }
                 }
                 BroadcastAddress::Local(id) => {
-                    for f in handlers.iter_for(id) {
-                        f(&mut self.activities.borrow_mut(), &mut managed_state);
+                    for sub in handlers.iter_for(id) {
+                        self.deliver(sub, &mut managed_state, broadcast.sender, false);
                     }
                 }
+                BroadcastAddress::LocalIncludingInactive(id) => {
+                    for sub in handlers.iter_for(id) {
+                        self.deliver(sub, &mut managed_state, broadcast.sender, true);
+                    }
+                }
+                BroadcastAddress::Selected(indices) => {
+                    for sub in handlers.iter().filter(|sub| indices.contains(&sub.index())) {
+                        self.deliver(sub, &mut managed_state, broadcast.sender, false);
+                    }
+                }
+                BroadcastAddress::Domains(domains) => {
+                    let activities = self.activities.borrow();
+                    for sub in handlers
+                        .iter()
+                        .filter(|sub| domains.contains(&activities.domain(sub.activity)))
+                    {
+                        self.deliver(sub, &mut managed_state, broadcast.sender, false);
+                    }
+                }
+                BroadcastAddress::Pipe(_) => unreachable!(),
             }
         }
-        managed_state.clear_broadcast();
+        if to_wildcards {
+            if let Some(handlers) = self.subscriptions.borrow().get(&Topic::Wildcard) {
+                for sub in handlers.iter() {
+                    self.deliver(sub, &mut managed_state, broadcast.sender, false);
+                }
+            }
+        }
+        self.current_generation.set(0);
+        self.current_topic.set(None);
+        managed_state.take_broadcast()
+    }
+    /// only access after locking with executing flag
+    ///
+    /// Delivers a borrowed message to all subscriptions that accept messages by reference.
+    pub(crate) fn unchecked_broadcast_ref<MSG: Any>(&self, msg: &MSG) {
+        if !self.is_handled(&Topic::message::<MSG>()) {
+            self.deliver_dead_letter(std::any::type_name::<MSG>(), msg);
+        }
+        let mut managed_state = self.managed_state.borrow_mut();
+        let subscriptions = self.subscriptions.borrow();
+        let topics = [Topic::message::<MSG>(), Topic::Wildcard];
+        self.current_topic.set(Some(topics[0]));
+        for handlers in topics.iter().filter_map(|topic| subscriptions.get(topic)) {
+            for sub in handlers.iter() {
+                self.deliver_with(sub, &mut managed_state, |activities, managed_state| {
+                    sub.dispatch_by_ref(activities, managed_state, msg)
+                });
+            }
+        }
+        self.current_topic.set(None);
+    }
+    fn deliver(
+        &self,
+        sub: &Subscription,
+        managed_state: &mut ManagedState,
+        sender: Option<UncheckedActivityId>,
+        include_inactive: bool,
+    ) {
+        self.deliver_with(sub, managed_state, |activities, managed_state| {
+            sub.dispatch(activities, managed_state, sender, include_inactive)
+        })
+    }
+    fn deliver_with(
+        &self,
+        sub: &Subscription,
+        managed_state: &mut ManagedState,
+        dispatch: impl FnOnce(&ActivityContainer, &mut ManagedState) -> bool,
+    ) {
+        let outer = self.current_activity.replace(Some(sub.activity));
+        let start = self.start_timing();
+        let called = self.catch_handler_panic(sub, || {
+            dispatch(&self.activities.borrow(), &mut *managed_state)
+        });
+        if called {
+            self.handler_calls.set(self.handler_calls.get() + 1);
+            self.record_delivery(sub);
+        }
+        if let Some(start) = start {
+            self.check_slow_handler(sub, start);
+        }
+        managed_state.end_dispatch(sub.activity);
+        self.current_activity.set(outer);
     }
 }
@@ -0,0 +1,25 @@
+use crate::nut::Nut;
+use crate::*;
+
+impl Nut {
+    pub(crate) fn publish_checked<MSG: Any, E: Any>(&self, msg: MSG) -> Vec<E> {
+        assert!(
+            !self.is_executing(),
+            "`publish_checked` can only be called outside of activities."
+        );
+        *self.checked_errors.borrow_mut() = Some(Vec::new());
+        self.publish(msg);
+        self.checked_errors
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|err| err.downcast().ok().map(|err| *err))
+            .collect()
+    }
+    /// Keeps the error of a subscription created with `subscribe_checked`, if `publish_checked` is collecting errors.
+    pub(crate) fn report_checked_error(&self, err: Box<dyn Any>) {
+        if let Some(errors) = self.checked_errors.borrow_mut().as_mut() {
+            errors.push(err);
+        }
+    }
+}
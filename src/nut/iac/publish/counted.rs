@@ -0,0 +1,27 @@
+use crate::nut::Nut;
+use crate::*;
+
+impl Nut {
+    pub(crate) fn publish_counted<MSG: Any>(&self, msg: MSG) -> usize {
+        assert!(
+            !self.is_executing(),
+            "`publish_counted` can only be called outside of activities."
+        );
+        // Queued events also have generation 0, deliver them before counting
+        self.catch_up_deferred_to_quiescence();
+        self.counted_calls.set(Some(0));
+        self.publish(msg);
+        self.counted_calls.take().unwrap_or_default()
+    }
+    /// Adds the handler calls since `calls_before` if the broadcast is the top-level message of `publish_counted`.
+    ///
+    /// Messages published by the handlers have a higher generation and are not counted.
+    pub(crate) fn count_dispatch(&self, broadcast_generation: usize, calls_before: usize) {
+        if broadcast_generation == 0 {
+            if let Some(count) = self.counted_calls.get() {
+                let calls = self.handler_calls.get() - calls_before;
+                self.counted_calls.set(Some(count + calls));
+            }
+        }
+    }
+}
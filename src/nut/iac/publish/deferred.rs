@@ -0,0 +1,23 @@
+use crate::nut::Nut;
+use crate::*;
+
+/// Publishes a message that was held back until the next `nuts::run_deferred`, see `nuts::publish_deferred`
+pub(crate) type DeferredMessage = Box<dyn FnOnce()>;
+
+impl Nut {
+    pub(crate) fn publish_deferred<MSG: Any>(&self, msg: MSG) {
+        self.deferred_messages
+            .borrow_mut()
+            .push(Box::new(move || crate::nut::publish_custom(msg)));
+    }
+    /// Publishes all messages that have been held back so far, in order.
+    /// Messages held back while doing so wait for the next call.
+    pub(crate) fn run_deferred(&self) -> usize {
+        let pending = std::mem::take(&mut *self.deferred_messages.borrow_mut());
+        let n = pending.len();
+        for publish in pending {
+            publish();
+        }
+        n
+    }
+}
@@ -0,0 +1,60 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use crate::*;
+use core::marker::PhantomData;
+
+/// The subscriptions to messages of type `MSG` at a specific point in time, which can be invoked manually.
+///
+/// Created by [`nuts::take_dispatch`](fn.take_dispatch.html).
+/// The set only refers to the subscriptions, it does not hold any borrow of the activities.
+/// Subscriptions added later are not part of the set.
+pub struct DispatchSet<MSG> {
+    indices: Vec<usize>,
+    phantom: PhantomData<MSG>,
+}
+
+impl<MSG: Any> DispatchSet<MSG> {
+    /// Number of subscriptions in the set
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+    /// True if the set contains no subscriptions
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+    /// Delivers the message to all subscriptions in the set, in their usual order and with their usual filters applied.
+    ///
+    /// The delivery happens immediately, messages published by the handlers are delivered before this function returns.
+    /// Returns the message afterwards, unless a subscriber took ownership of it.
+    ///
+    /// # Panics
+    /// Panics if called from inside an activity.
+    pub fn dispatch(&self, msg: MSG) -> Option<MSG> {
+        crate::nut::dispatch_selected(Topic::message::<MSG>(), self.indices.clone(), msg)
+    }
+}
+
+impl Nut {
+    pub(crate) fn take_dispatch<MSG: Any>(&self) -> DispatchSet<MSG> {
+        let indices = self
+            .subscriptions
+            .borrow()
+            .get(&Topic::message::<MSG>())
+            .map(|handlers| handlers.iter().map(|sub| sub.index()).collect())
+            .unwrap_or_default();
+        DispatchSet {
+            indices,
+            phantom: PhantomData,
+        }
+    }
+    pub(crate) fn dispatch_selected<MSG: Any>(
+        &self,
+        topic: Topic,
+        indices: Vec<usize>,
+        msg: MSG,
+    ) -> Option<MSG> {
+        let broadcast = BroadcastInfo::selected(msg, indices, topic);
+        self.broadcast_now(broadcast, "DispatchSet::dispatch")
+            .map(|msg| *msg.downcast().expect("Bug: wrong message dispatched"))
+    }
+}
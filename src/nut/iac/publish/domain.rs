@@ -0,0 +1,48 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use crate::*;
+
+/// Defines which activities [`nuts::publish_to_domain`](fn.publish_to_domain.html) reaches.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum DomainReach {
+    /// Only activities in the target domain
+    SelfOnly,
+    /// Activities in the target domain and in all its ancestors, see [`nuts::set_domain_parent`](fn.set_domain_parent.html)
+    IncludeAncestors,
+}
+
+impl Nut {
+    pub(crate) fn set_domain_parent(&self, child: DomainId, parent: DomainId) {
+        assert!(
+            !self.domain_chain(parent).contains(&child),
+            "Setting the parent of {:?} to {:?} creates a cycle.",
+            child,
+            parent
+        );
+        self.domain_parents.borrow_mut().insert(child, parent);
+    }
+    /// The domain followed by its ancestors
+    fn domain_chain(&self, domain: DomainId) -> Vec<DomainId> {
+        let parents = self.domain_parents.borrow();
+        let mut chain = vec![domain];
+        while let Some(parent) = parents.get(chain.last().expect("chain is never empty")) {
+            chain.push(*parent);
+        }
+        chain
+    }
+    pub(crate) fn publish_to_domain<MSG: Any>(
+        &self,
+        domain: DomainId,
+        msg: MSG,
+        reach: DomainReach,
+    ) {
+        let domains = match reach {
+            DomainReach::SelfOnly => vec![domain],
+            DomainReach::IncludeAncestors => self.domain_chain(domain),
+        };
+        let broadcast = BroadcastInfo::domains(msg, domains, Topic::message::<MSG>());
+        self.enqueue(broadcast);
+        self.catch_up_deferred_to_quiescence();
+    }
+}
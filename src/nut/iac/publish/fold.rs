@@ -0,0 +1,22 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use crate::*;
+
+/// Internal message type for `nuts::publish_fold`, carries the accumulator along the message.
+pub(crate) struct Fold<MSG, ACC> {
+    pub(crate) msg: MSG,
+    pub(crate) acc: ACC,
+}
+
+impl Nut {
+    pub(crate) fn publish_fold<MSG: Any, ACC: Any>(&self, msg: MSG, acc: ACC) -> ACC {
+        let topic = Topic::message::<Fold<MSG, ACC>>();
+        let broadcast = BroadcastInfo::global(Fold { msg, acc }, topic);
+        let fold = self
+            .broadcast_now(broadcast, "publish_fold")
+            .expect("Bug: fold message taken")
+            .downcast::<Fold<MSG, ACC>>()
+            .expect("Bug: wrong fold message");
+        fold.acc
+    }
+}
@@ -0,0 +1,70 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use crate::*;
+use std::collections::VecDeque;
+
+/// The most recent messages of a type, see `nuts::set_history`
+pub(crate) struct History {
+    depth: usize,
+    messages: VecDeque<Box<dyn Any>>,
+    clone: fn(&dyn Any) -> Box<dyn Any>,
+}
+
+fn clone_boxed<MSG: Any + Clone>(msg: &dyn Any) -> Box<dyn Any> {
+    Box::new(
+        msg.downcast_ref::<MSG>()
+            .expect("Bug: wrong message in history")
+            .clone(),
+    )
+}
+
+impl Nut {
+    pub(crate) fn set_history<MSG: Any + Clone>(&self, depth: usize) {
+        let topic = Topic::message::<MSG>();
+        let mut histories = self.histories.borrow_mut();
+        if depth == 0 {
+            histories.remove(&topic);
+            return;
+        }
+        let history = histories.entry(topic).or_insert_with(|| History {
+            depth,
+            messages: VecDeque::with_capacity(depth),
+            clone: clone_boxed::<MSG>,
+        });
+        history.depth = depth;
+        while history.messages.len() > depth {
+            history.messages.pop_front();
+        }
+    }
+    /// Keeps a clone of the published message if a history is kept for its type
+    pub(super) fn record_history(&self, topic: &Topic, msg: &dyn Any) {
+        if let Some(history) = self.histories.borrow_mut().get_mut(topic) {
+            if history.messages.len() == history.depth {
+                history.messages.pop_front();
+            }
+            let copy = (history.clone)(msg);
+            history.messages.push_back(copy);
+        }
+    }
+    /// Delivers clones of the recorded messages to a single subscription, oldest first
+    pub(crate) fn replay_history<MSG: Any + Clone>(&self, subscription: SubscriptionId) {
+        let messages: Vec<MSG> = self
+            .histories
+            .borrow()
+            .get(&subscription.topic)
+            .into_iter()
+            .flat_map(|history| history.messages.iter())
+            .map(|msg| {
+                msg.downcast_ref::<MSG>()
+                    .expect("Bug: wrong message in history")
+                    .clone()
+            })
+            .collect();
+        for msg in messages {
+            let broadcast =
+                BroadcastInfo::selected(msg, vec![subscription.index], subscription.topic);
+            self.deferred_events.push(broadcast.into());
+        }
+        self.catch_up_deferred_to_quiescence();
+    }
+}
@@ -0,0 +1,15 @@
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::Nut;
+use crate::*;
+
+/// Internal message type for subscriptions created with `subscribe_deferred`, carries a clone of the original message.
+pub(crate) struct Idle<MSG>(pub(crate) MSG);
+
+impl Nut {
+    /// Queues the delivery of a clone of `msg` to a single subscription, once all other events have been processed.
+    pub(crate) fn push_idle<MSG: Any>(&self, msg: MSG, subscription: usize) {
+        let broadcast =
+            BroadcastInfo::selected(Idle(msg), vec![subscription], Topic::message::<Idle<MSG>>());
+        self.idle_events.push(broadcast);
+    }
+}
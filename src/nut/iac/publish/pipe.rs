@@ -0,0 +1,64 @@
+use crate::nut::iac::filter::SubscriptionFilter;
+use crate::nut::iac::managed_state::ManagedState;
+use crate::nut::iac::publish::BroadcastInfo;
+use crate::nut::iac::subscription::Subscription;
+use crate::nut::Nut;
+use crate::*;
+use core::marker::PhantomData;
+
+/// A direct link between two activities for messages of type `MSG`, created by [`nuts::pipe`](fn.pipe.html).
+pub struct PipeId<MSG> {
+    index: usize,
+    from: UncheckedActivityId,
+    phantom: PhantomData<MSG>,
+}
+
+impl<MSG> Clone for PipeId<MSG> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<MSG> Copy for PipeId<MSG> {}
+
+impl Nut {
+    pub(crate) fn pipe<B, MSG, F>(
+        &self,
+        from: UncheckedActivityId,
+        to: ActivityId<B>,
+        f: F,
+    ) -> PipeId<MSG>
+    where
+        B: Activity,
+        MSG: Any,
+        F: Fn(&mut B, &MSG) + 'static,
+    {
+        let closure = ManagedState::pack_closure::<_, _, MSG>(f, to);
+        let sub = Subscription::new(
+            to.into(),
+            SubscriptionFilter::default(),
+            closure,
+            std::any::type_name::<MSG>(),
+        );
+        let mut pipes = self
+            .pipes
+            .try_borrow_mut()
+            .expect("Tried to add a new pipe from inside a piped handler, which is not allowed.");
+        pipes.push(sub);
+        PipeId {
+            index: pipes.len() - 1,
+            from,
+            phantom: PhantomData,
+        }
+    }
+    pub(crate) fn send_pipe<MSG: Any>(&self, pipe: PipeId<MSG>, msg: MSG) {
+        if let Some(sender) = self.current_activity.get() {
+            assert!(
+                sender == pipe.from,
+                "Only the sending activity of a pipe can send to it."
+            );
+        }
+        let broadcast = BroadcastInfo::pipe(msg, pipe.index, Topic::message::<MSG>());
+        self.enqueue(broadcast);
+        self.catch_up_deferred_to_quiescence();
+    }
+}
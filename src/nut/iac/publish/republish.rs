@@ -0,0 +1,42 @@
+use crate::nut::Nut;
+use crate::*;
+
+/// Defines what happens when a handler publishes a message of the same type it is currently handling.
+///
+/// Such a handler can keep the event queue busy forever. Set the policy with [`nuts::set_self_republish_policy`](fn.set_self_republish_policy.html).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum SelfRepublishPolicy {
+    /// The message is published like any other message. This is the default.
+    #[default]
+    Allow,
+    /// The message is dropped. In debug builds, a warning is printed to stderr.
+    DropWithWarning,
+    /// Up to the given number of such messages are published until the queue is empty again, further ones are dropped.
+    Limit(usize),
+}
+
+impl Nut {
+    /// Returns false if the message must be dropped, because a handler publishes the type it is handling.
+    pub(crate) fn check_self_republish(&self, topic: &Topic, type_name: &'static str) -> bool {
+        if self.current_topic.get().as_ref() != Some(topic) {
+            return true;
+        }
+        let allowed = match self.self_republish_policy.get() {
+            SelfRepublishPolicy::Allow => true,
+            SelfRepublishPolicy::DropWithWarning => false,
+            SelfRepublishPolicy::Limit(max) => {
+                let republished = self.self_republished.get();
+                self.self_republished.set(republished + 1);
+                republished < max
+            }
+        };
+        if !allowed && cfg!(debug_assertions) {
+            eprintln!(
+                "nuts: dropped a message of type `{}` published by a handler of the same type",
+                type_name
+            );
+        }
+        allowed
+    }
+}
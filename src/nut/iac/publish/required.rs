@@ -0,0 +1,28 @@
+use crate::nut::Nut;
+use crate::*;
+
+/// Marks message types that must have at least one subscriber when they are published.
+///
+/// Messages of such types can be published with [`nuts::publish_required`](fn.publish_required.html),
+/// which reports a missing subscriber in its return type.
+///
+/// ### Example
+/// ```rust
+/// struct SaveGame;
+/// impl nuts::RequiredMessage for SaveGame {}
+/// ```
+pub trait RequiredMessage: Any {}
+
+impl Nut {
+    pub(crate) fn publish_required<MSG: RequiredMessage>(
+        &self,
+        msg: MSG,
+    ) -> Result<usize, NutsError> {
+        let subscriptions = self.subscriber_count(&Topic::message::<MSG>());
+        if subscriptions == 0 {
+            return Err(NutsError::Unhandled);
+        }
+        self.publish(msg);
+        Ok(subscriptions)
+    }
+}
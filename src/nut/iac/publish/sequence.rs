@@ -0,0 +1,27 @@
+use crate::*;
+
+/// A list of messages of different types, published one after another.
+///
+/// Created by [`nuts::sequence`](fn.sequence.html).
+/// Each message is published with [`nuts::publish`](fn.publish.html) when the sequence is run.
+/// At the top level, the cascade of each message is processed completely before the next message is published.
+/// Inside an activity, the messages are queued in the order of the sequence.
+#[derive(Default)]
+#[must_use = "a sequence does nothing unless it is run"]
+pub struct Sequence {
+    steps: Vec<Box<dyn FnOnce()>>,
+}
+
+impl Sequence {
+    /// Appends a message to the sequence.
+    pub fn then<MSG: Any>(mut self, msg: MSG) -> Self {
+        self.steps.push(Box::new(move || crate::publish(msg)));
+        self
+    }
+    /// Publishes all messages of the sequence, in order.
+    pub fn run(self) {
+        for step in self.steps {
+            step();
+        }
+    }
+}
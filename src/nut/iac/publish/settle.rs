@@ -0,0 +1,28 @@
+use crate::nut::Nut;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Resolves once no events are queued anymore, returned by `nuts::publish_async`
+pub(crate) struct Settled;
+
+impl Future for Settled {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        crate::nut::poll_settled(cx)
+    }
+}
+
+impl Nut {
+    /// Processes all queued events, unless a broadcast is ongoing.
+    /// In that case, the waker is woken once the ongoing broadcast and all queued events have been processed.
+    pub(crate) fn poll_settled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_executing() {
+            self.settle_wakers.borrow_mut().push(cx.waker().clone());
+            Poll::Pending
+        } else {
+            self.catch_up_deferred_to_quiescence();
+            Poll::Ready(())
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::nut::iac::filter::SubscriptionFilter;
+use crate::nut::iac::managed_state::ManagedState;
+use crate::nut::iac::subscription::Subscription;
+use crate::nut::Nut;
+use crate::*;
+use core::any::Any;
+
+/// A handler subscribed by name with [`nuts::subscribe_by_name`](fn.subscribe_by_name.html).
+///
+/// It receives the activity and the message without static types, use `downcast_mut` and `downcast_ref` to access them.
+pub type BoxedHandler = Box<dyn Fn(&mut dyn Any, &dyn Any)>;
+
+/// A message type registered by name
+#[derive(Clone, Copy)]
+pub(crate) struct RegisteredType {
+    topic: Topic,
+    type_name: &'static str,
+}
+
+impl Nut {
+    pub(crate) fn register_message_type<MSG: Any>(&self, name: &str) {
+        let registered = RegisteredType {
+            topic: Topic::message::<MSG>(),
+            type_name: std::any::type_name::<MSG>(),
+        };
+        let mut registry = self.message_registry.borrow_mut();
+        if let Some(previous) = registry.get(name) {
+            assert!(
+                previous.topic == registered.topic,
+                "Message name `{}` is already registered for {}",
+                name,
+                previous.type_name
+            );
+        }
+        registry.insert(name.to_owned(), registered);
+    }
+    pub(crate) fn subscribe_by_name(
+        &self,
+        activity: UncheckedActivityId,
+        name: &str,
+        handler: BoxedHandler,
+    ) -> SubscriptionId {
+        let registered = *self
+            .message_registry
+            .borrow()
+            .get(name)
+            .unwrap_or_else(|| panic!("No message type registered with name `{}`", name));
+        let closure = ManagedState::pack_closure_by_name(handler, activity);
+        let sub = Subscription::new(
+            activity,
+            SubscriptionFilter::default(),
+            closure,
+            registered.type_name,
+        );
+        self.push_subscription(registered.topic, sub)
+    }
+}
@@ -0,0 +1,333 @@
+//! Subscriptions connect the handlers of activities to topics.
+
+mod inline_vec;
+
+use crate::nut::activity::Once;
+use crate::nut::iac::managed_state::ManagedState;
+use crate::nut::{Handler, RefHandler};
+use crate::*;
+use core::cell::Cell;
+use inline_vec::InlineVec;
+use std::rc::Rc;
+
+/// Handle to a subscription, returned when subscribing to a message type.
+///
+/// Can be used to define the order in which subscriptions to the same message type are called,
+/// or to remove the subscription with [`nuts::unsubscribe`](fn.unsubscribe.html).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SubscriptionId {
+    pub(crate) topic: Topic,
+    pub(crate) index: usize,
+}
+
+/// A handler registered on a specific activity, together with the metadata needed to dispatch it.
+pub(crate) struct Subscription {
+    pub(crate) activity: UncheckedActivityId,
+    filter: SubscriptionFilter,
+    handler: Handler,
+    /// Variant of the handler for borrowed messages, see `nuts::publish_ref`
+    by_ref: Option<RefHandler>,
+    /// Name of the subscribed message type, for diagnostics
+    pub(crate) type_name: &'static str,
+    /// Number of times the handler has been invoked, filtered out calls are not counted
+    calls: Cell<usize>,
+    /// Position in the sequence of registrations to the same topic
+    index: usize,
+    /// Subscriptions with lower priority are called first
+    priority: i32,
+    /// Set for subscriptions created with `subscribe_once`, such that a replaced handler is called at most once, too
+    once: Option<Rc<Once>>,
+    /// A hidden subscription that is removed together with this one, see `subscribe_deferred`
+    companion: Option<SubscriptionId>,
+}
+
+/// All subscriptions to a single topic
+#[derive(Default)]
+pub(crate) struct TopicSubscriptions {
+    /// Sorted in dispatch order
+    subscriptions: InlineVec<Subscription>,
+    /// Pairs of subscription indices, the first must be called before the second
+    constraints: Vec<(usize, usize)>,
+    registered: usize,
+}
+
+impl Subscription {
+    pub(crate) fn new(
+        activity: UncheckedActivityId,
+        filter: SubscriptionFilter,
+        handler: Handler,
+        type_name: &'static str,
+    ) -> Self {
+        Self {
+            activity,
+            filter,
+            handler,
+            by_ref: None,
+            type_name,
+            calls: Cell::new(0),
+            index: 0,
+            priority: 0,
+            once: None,
+            companion: None,
+        }
+    }
+    /// Calls the handler, unless the filter says otherwise.
+    /// With `include_inactive`, the lifecycle status of the activity is not checked, only that it still exists.
+    /// Returns true if the handler has been called and has not skipped the call.
+    pub(crate) fn dispatch(
+        &self,
+        activities: &ActivityContainer,
+        managed_state: &mut ManagedState,
+        sender: Option<UncheckedActivityId>,
+        include_inactive: bool,
+    ) -> bool {
+        let msg = managed_state.current_message();
+        let accepted = if include_inactive {
+            activities.contains(self.activity) && self.filter.accepts_message(msg)
+        } else {
+            activities.filter(self.activity, &self.filter, msg)
+        };
+        let called = self.filter.accepts_sender(sender)
+            && accepted
+            && (self.handler)(activities, managed_state);
+        if called {
+            self.calls.set(self.calls.get() + 1);
+        }
+        called
+    }
+    /// Adds a handler that is called for borrowed messages
+    pub(crate) fn with_ref_handler(mut self, by_ref: RefHandler) -> Self {
+        self.by_ref = Some(by_ref);
+        self
+    }
+    pub(crate) fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Swaps the handlers, keeping the position, filter, and call count of the subscription
+    pub(crate) fn replace_handler(&mut self, handler: Handler, by_ref: RefHandler) {
+        self.handler = handler;
+        self.by_ref = Some(by_ref);
+    }
+    pub(crate) fn with_companion(mut self, companion: SubscriptionId) -> Self {
+        self.companion = Some(companion);
+        self
+    }
+    pub(crate) fn companion(&self) -> Option<SubscriptionId> {
+        self.companion
+    }
+    pub(crate) fn once(&self) -> Option<Rc<Once>> {
+        self.once.clone()
+    }
+    pub(crate) fn set_once(&mut self, once: Rc<Once>) {
+        self.once = Some(once);
+    }
+    /// Calls the handler for borrowed messages, unless the filter says otherwise.
+    /// Returns false if the subscription does not accept borrowed messages.
+    pub(crate) fn dispatch_by_ref(
+        &self,
+        activities: &ActivityContainer,
+        managed_state: &mut ManagedState,
+        msg: &dyn Any,
+    ) -> bool {
+        let by_ref = match &self.by_ref {
+            Some(by_ref) => by_ref,
+            None => return false,
+        };
+        let called = self.filter.accepts_sender(None)
+            && activities.filter(self.activity, &self.filter, Some(msg))
+            && by_ref(activities, managed_state, msg);
+        if called {
+            self.calls.set(self.calls.get() + 1);
+        }
+        called
+    }
+    /// True if the activity could currently receive a message, regardless of its content and sender
+    pub(crate) fn is_reachable(&self, activities: &ActivityContainer) -> bool {
+        activities.is_reachable(self.activity, &self.filter)
+    }
+    /// The message type the filter predicate has been defined for, if any
+    pub(crate) fn predicate_type(&self) -> Option<core::any::TypeId> {
+        self.filter.predicate_type()
+    }
+    /// Position in the sequence of registrations to the same topic
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+    pub(crate) fn calls(&self) -> usize {
+        self.calls.get()
+    }
+}
+
+impl TopicSubscriptions {
+    /// Adds a subscription and returns its index within the topic.
+    pub(crate) fn push(&mut self, mut sub: Subscription) -> usize {
+        let index = self.registered;
+        self.registered += 1;
+        sub.index = index;
+        self.subscriptions.push(sub);
+        self.sort();
+        index
+    }
+    /// True if the subscriptions are stored without a heap allocation
+    #[cfg(test)]
+    pub(crate) fn is_inline(&self) -> bool {
+        self.subscriptions.is_inline()
+    }
+    #[cfg(test)]
+    pub(crate) fn constraint_count(&self) -> usize {
+        self.constraints.len()
+    }
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Subscription> {
+        self.subscriptions.iter()
+    }
+    pub(crate) fn iter_for(&self, id: UncheckedActivityId) -> impl Iterator<Item = &Subscription> {
+        self.subscriptions
+            .iter()
+            .filter(move |sub| sub.activity == id)
+    }
+    /// The subscription with the given index, if it still exists.
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut Subscription> {
+        self.subscriptions.iter_mut().find(|sub| sub.index == index)
+    }
+    /// Removes all subscriptions of the given activities, together with their ordering constraints.
+    pub(crate) fn remove_activities(&mut self, ids: &[UncheckedActivityId]) {
+        let removed: Vec<usize> = self
+            .subscriptions
+            .iter()
+            .filter(|sub| ids.contains(&sub.activity))
+            .map(|sub| sub.index)
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+        self.subscriptions
+            .retain(|sub| !ids.contains(&sub.activity));
+        self.constraints
+            .retain(|(first, second)| !removed.contains(first) && !removed.contains(second));
+    }
+    /// Removes the subscription with the given index, if it still exists.
+    pub(crate) fn remove(&mut self, index: usize) {
+        self.subscriptions.retain(|sub| sub.index != index);
+        self.constraints
+            .retain(|(first, second)| *first != index && *second != index);
+    }
+    /// Requires subscription `first` to be called before `second`.
+    ///
+    /// The constraint is rejected if it contradicts previously added constraints.
+    pub(crate) fn add_constraint(&mut self, first: usize, second: usize) -> Result<(), NutsError> {
+        self.constraints.push((first, second));
+        if self.sort() {
+            Ok(())
+        } else {
+            self.constraints.pop();
+            Err(NutsError::OrderingCycle)
+        }
+    }
+    /// Sorts subscriptions topologically by their constraints.
+    /// Unconstrained subscriptions are kept in order of priority, then registration.
+    ///
+    /// Returns false and leaves the order untouched if the constraints contain a cycle.
+    fn sort(&mut self) -> bool {
+        if self.constraints.is_empty() {
+            self.subscriptions
+                .sort_by_key(|sub| (sub.priority, sub.index));
+            return true;
+        }
+        let mut by_priority: Vec<(i32, usize)> = self
+            .subscriptions
+            .iter()
+            .map(|sub| (sub.priority, sub.index))
+            .collect();
+        by_priority.sort_unstable();
+        let mut remaining: Vec<usize> = by_priority.into_iter().map(|(_, index)| index).collect();
+        let mut order = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let next = remaining.iter().position(|candidate| {
+                !self
+                    .constraints
+                    .iter()
+                    .any(|(first, second)| second == candidate && remaining.contains(first))
+            });
+            match next {
+                Some(i) => order.push(remaining.remove(i)),
+                None => return false,
+            }
+        }
+        self.subscriptions.sort_by_key(|sub| {
+            order
+                .iter()
+                .position(|index| *index == sub.index)
+                .expect("Bug: subscription lost while sorting")
+        });
+        true
+    }
+}
+
+impl SubscriptionId {
+    /// Makes sure this subscription is always called after `other` when a message is published.
+    ///
+    /// Without constraints, subscriptions are ordered by priority, then registration order.
+    /// Constraints can be combined to define the order of any number of subscriptions.
+    ///
+    /// # Errors
+    /// Returns [`NutsError::OrderingCycle`](enum.NutsError.html#variant.OrderingCycle) if the constraint
+    /// contradicts previously defined constraints. The contradicting constraint has no effect in that case.
+    ///
+    /// # Panics
+    /// Panics if the two subscriptions are for different message types or if called from inside an activity.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Render;
+    /// let activity = nuts::new_activity(());
+    /// let draw = activity.subscribe(|_, _: &Render| println!("second"));
+    /// let clear = activity.subscribe(|_, _: &Render| println!("first"));
+    /// draw.after(clear).unwrap();
+    /// nuts::publish(Render);
+    /// ```
+    pub fn after(&self, other: SubscriptionId) -> Result<(), NutsError> {
+        crate::nut::order_subscriptions(other, *self)
+    }
+    /// Makes sure this subscription is always called before `other` when a message is published.
+    ///
+    /// See [`after`](#method.after) for details.
+    ///
+    /// # Errors
+    /// Returns [`NutsError::OrderingCycle`](enum.NutsError.html#variant.OrderingCycle) if the constraint
+    /// contradicts previously defined constraints.
+    ///
+    /// # Panics
+    /// Panics if the two subscriptions are for different message types or if called from inside an activity.
+    pub fn before(&self, other: SubscriptionId) -> Result<(), NutsError> {
+        crate::nut::order_subscriptions(*self, other)
+    }
+    /// Replaces the closure of this subscription, keeping its position among the subscriptions to the same message type.
+    ///
+    /// Priority, ordering constraints, and the filter stay the same.
+    /// A subscription created with [`subscribe_once`](struct.ActivityId.html#method.subscribe_once) still removes itself when the new closure is called.
+    /// Returns false if the subscription has been removed already.
+    ///
+    /// # Panics
+    /// Panics if `A` or `MSG` do not match the activity and message type of the subscription, or if called from inside an activity.
+    ///
+    /// ### Example
+    /// ```rust
+    /// struct Render;
+    /// let activity = nuts::new_activity(Vec::new());
+    /// let background = activity.subscribe(|log, _: &Render| log.push("background"));
+    /// activity.subscribe(|log, _: &Render| log.push("foreground"));
+    /// background.replace(|log: &mut Vec<&str>, _: &Render| log.push("sky"));
+    ///
+    /// nuts::publish(Render);
+    /// assert_eq!(Some(vec!["sky", "foreground"]), activity.read(|log| log.clone()));
+    /// ```
+    pub fn replace<A, MSG, F>(&self, f: F) -> bool
+    where
+        A: Activity,
+        MSG: Any,
+        F: Fn(&mut A, &MSG) + 'static,
+    {
+        crate::nut::replace_subscription(*self, f)
+    }
+}
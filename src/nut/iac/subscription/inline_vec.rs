@@ -0,0 +1,61 @@
+use core::ops::{Deref, DerefMut};
+
+/// A list that stores a single element inline and only allocates on the heap for more elements.
+///
+/// Most topics have exactly one subscriber, which makes the allocation of a `Vec` unnecessary in the common case.
+#[derive(Default)]
+pub(crate) enum InlineVec<T> {
+    #[default]
+    Empty,
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> InlineVec<T> {
+    pub(crate) fn push(&mut self, item: T) {
+        *self = match std::mem::take(self) {
+            Self::Empty => Self::One(item),
+            Self::One(first) => Self::Many(vec![first, item]),
+            Self::Many(mut items) => {
+                items.push(item);
+                Self::Many(items)
+            }
+        };
+    }
+    pub(crate) fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        *self = match std::mem::take(self) {
+            Self::One(item) if f(&item) => Self::One(item),
+            Self::Empty | Self::One(_) => Self::Empty,
+            Self::Many(mut items) => {
+                items.retain(f);
+                Self::Many(items)
+            }
+        };
+    }
+    /// True if the elements are stored without a heap allocation
+    #[cfg(test)]
+    pub(crate) fn is_inline(&self) -> bool {
+        !matches!(self, Self::Many(_))
+    }
+}
+
+impl<T> Deref for InlineVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        match self {
+            Self::Empty => &[],
+            Self::One(item) => std::slice::from_ref(item),
+            Self::Many(items) => items,
+        }
+    }
+}
+
+impl<T> DerefMut for InlineVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Self::Empty => &mut [],
+            Self::One(item) => std::slice::from_mut(item),
+            Self::Many(items) => items,
+        }
+    }
+}
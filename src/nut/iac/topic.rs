@@ -1,12 +1,16 @@
 use core::any::{Any, TypeId};
 
 /// A topic for messages that can be published and subscribed to
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum Topic {
     /// Topic for a builtin event
     BuiltinEvent(BuiltinEvent),
-    /// Topic for a message type, whee type is a Rust type (core::any::TypeId)
+    /// Topic for a message type, where type is a Rust type (`core::any::TypeId`)
     Message(TypeId),
+    /// Topic for a message type that is only delivered to subscriptions under the same name
+    Named(TypeId, &'static str),
+    /// Topic for subscriptions to all published messages, regardless of their type
+    Wildcard,
 }
 
 /// Builtin events are messages without payload that are used internally.
@@ -16,6 +20,10 @@ pub(crate) enum BuiltinEvent {
     Enter,
     /// On status change to inactive
     Leave,
+    /// On status change to suspended
+    Suspend,
+    /// On status change from suspended to any other status, except deleted
+    Resume,
 }
 
 impl Topic {
@@ -25,7 +33,36 @@ impl Topic {
     pub(crate) fn leave() -> Self {
         Self::BuiltinEvent(BuiltinEvent::Leave)
     }
+    pub(crate) fn suspend() -> Self {
+        Self::BuiltinEvent(BuiltinEvent::Suspend)
+    }
+    pub(crate) fn resume() -> Self {
+        Self::BuiltinEvent(BuiltinEvent::Resume)
+    }
     pub(crate) fn message<T: Any>() -> Self {
         Self::Message(TypeId::of::<T>())
     }
+    pub(crate) fn named<T: Any>(name: &'static str) -> Self {
+        Self::Named(TypeId::of::<T>(), name)
+    }
+    /// The message type of the topic, `None` for builtin events and wildcards
+    pub(crate) fn message_type(&self) -> Option<TypeId> {
+        match self {
+            Self::Message(type_id) | Self::Named(type_id, _) => Some(*type_id),
+            Self::BuiltinEvent(_) | Self::Wildcard => None,
+        }
+    }
+    /// Name of the topic for diagnostics.
+    /// Message topics only know the `TypeId`, use `std::any::type_name` where the message type is known instead.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::BuiltinEvent(BuiltinEvent::Enter) => "on_enter",
+            Self::BuiltinEvent(BuiltinEvent::Leave) => "on_leave",
+            Self::BuiltinEvent(BuiltinEvent::Suspend) => "on_suspend",
+            Self::BuiltinEvent(BuiltinEvent::Resume) => "on_resume",
+            Self::Message(_) => "message",
+            Self::Named(_, name) => name,
+            Self::Wildcard => "any",
+        }
+    }
 }
@@ -0,0 +1,107 @@
+//! Subscriptions to several message types that share one closure, see `ActivityId::subscribe_variants`.
+//!
+//! The topic registry has no notion of a multi-type subscription.
+//! Instead, each variant type gets its own ordinary subscription under its own `TypeId`.
+//! All of them hold an `Rc` to the same closure and wrap the message into the enum before calling it.
+
+use crate::nut::activity::{Activity, ActivityId};
+use crate::{DomainState, SubscriptionId};
+use core::any::Any;
+use std::rc::Rc;
+
+/// An enum whose variants each wrap a different message type, such that all of these types can be handled by a single closure.
+///
+/// Implement it with the [`message_variants!`](macro.message_variants.html) macro,
+/// then subscribe with [`subscribe_variants`](struct.ActivityId.html#method.subscribe_variants)
+/// or [`subscribe_domained_variants`](struct.ActivityId.html#method.subscribe_domained_variants).
+/// The message types are published individually, as usual.
+pub trait MessageVariants: Sized + 'static {
+    /// Calls [`VariantSubscriber::variant`](struct.VariantSubscriber.html#method.variant) once for each variant.
+    fn subscribe_variants<A: Activity>(subscriber: &mut VariantSubscriber<A, Self>);
+}
+
+/// Registers the closure of a variant subscription for each message type of a [`MessageVariants`](trait.MessageVariants.html) enum.
+pub struct VariantSubscriber<A: Activity, E> {
+    activity: ActivityId<A>,
+    handler: VariantHandler<A, E>,
+    subscriptions: Vec<SubscriptionId>,
+}
+
+type PlainVariantFn<A, E> = Rc<dyn Fn(&mut A, E)>;
+type DomainedVariantFn<A, E> = Rc<dyn Fn(&mut A, &mut DomainState, E)>;
+
+pub(crate) enum VariantHandler<A, E> {
+    Plain(PlainVariantFn<A, E>),
+    Domained(DomainedVariantFn<A, E>),
+}
+
+impl<A: Activity, E: 'static> VariantSubscriber<A, E> {
+    pub(crate) fn new(activity: ActivityId<A>, handler: VariantHandler<A, E>) -> Self {
+        Self {
+            activity,
+            handler,
+            subscriptions: Vec::new(),
+        }
+    }
+    /// Subscribes the shared closure to messages of type `T`, which are wrapped into the enum with `wrap`.
+    ///
+    /// The message is cloned, since other subscribers may still need it.
+    pub fn variant<T: Any + Clone>(&mut self, wrap: fn(T) -> E) {
+        let id = match &self.handler {
+            VariantHandler::Plain(f) => {
+                let f = f.clone();
+                self.activity
+                    .subscribe(move |a: &mut A, msg: &T| f(a, wrap(msg.clone())))
+            }
+            VariantHandler::Domained(f) => {
+                let f = f.clone();
+                self.activity
+                    .subscribe_domained(move |a: &mut A, domain, msg: &T| {
+                        f(a, domain, wrap(msg.clone()))
+                    })
+            }
+        };
+        self.subscriptions.push(id);
+    }
+    pub(crate) fn into_subscriptions(self) -> Vec<SubscriptionId> {
+        self.subscriptions
+    }
+}
+
+#[macro_export]
+/// Implements [`MessageVariants`](trait.MessageVariants.html) for an enum, given the message type wrapped by each variant.
+///
+/// Each variant must be a tuple variant with a single field, of a type that implements `Clone`.
+///
+/// # Example:
+/// ```
+/// #[derive(Clone)]
+/// struct Pressed(char);
+/// #[derive(Clone)]
+/// struct Released(char);
+/// enum KeyEvent {
+///     Down(Pressed),
+///     Up(Released),
+/// }
+/// nuts::message_variants!(KeyEvent { Down(Pressed), Up(Released) });
+///
+/// let keyboard = nuts::new_activity(Vec::new());
+/// keyboard.subscribe_variants(|log, event: KeyEvent| match event {
+///     KeyEvent::Down(Pressed(c)) => log.push(c.to_ascii_uppercase()),
+///     KeyEvent::Up(Released(c)) => log.push(c),
+/// });
+/// nuts::publish(Pressed('a'));
+/// nuts::publish(Released('a'));
+/// assert_eq!(Some(vec!['A', 'a']), keyboard.read(|log| log.clone()));
+/// ```
+macro_rules! message_variants {
+    ( $e:ident { $( $variant:ident ( $msg:ty ) ),* $(,)? } ) => {
+        impl $crate::MessageVariants for $e {
+            fn subscribe_variants<A: $crate::Activity>(
+                subscriber: &mut $crate::VariantSubscriber<A, Self>,
+            ) {
+                $( subscriber.variant::<$msg>($e::$variant); )*
+            }
+        }
+    };
+}
@@ -0,0 +1,73 @@
+use crate::nut::Nut;
+use crate::*;
+
+/// A snapshot of all activities, created by [`nuts::export_metadata`](fn.export_metadata.html).
+///
+/// The snapshot owns all its data and can be sent to other threads, e.g. to display it in an inspector.
+/// It does not change when the activities change afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct ActivityMetadata {
+    activities: Vec<ActivityInfo>,
+}
+
+/// Metadata of a single activity, part of [`ActivityMetadata`](struct.ActivityMetadata.html).
+#[derive(Debug, Clone)]
+pub struct ActivityInfo {
+    id: UncheckedActivityId,
+    type_name: &'static str,
+    status: LifecycleStatus,
+    subscriptions: Vec<&'static str>,
+}
+
+impl ActivityMetadata {
+    /// All activities that have not been deleted, in order of creation
+    pub fn activities(&self) -> &[ActivityInfo] {
+        &self.activities
+    }
+}
+
+impl ActivityInfo {
+    /// Id of the activity
+    pub fn id(&self) -> UncheckedActivityId {
+        self.id
+    }
+    /// Type name of the activity, as returned by `std::any::type_name`
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+    /// Lifecycle status at the time of the snapshot
+    pub fn status(&self) -> LifecycleStatus {
+        self.status
+    }
+    /// Type names of the messages the activity subscribed to, sorted alphabetically.
+    /// A type appears once per subscription.
+    pub fn subscriptions(&self) -> &[&'static str] {
+        &self.subscriptions
+    }
+}
+
+impl Nut {
+    pub(crate) fn export_metadata(&self) -> ActivityMetadata {
+        let activities = self.activities.borrow();
+        let subscriptions = self.subscriptions.borrow();
+        let activities = activities
+            .domains()
+            .map(|(id, _)| {
+                let mut subscribed: Vec<_> = subscriptions
+                    .values()
+                    .flat_map(|handlers| handlers.iter())
+                    .filter(|sub| sub.activity == id)
+                    .map(|sub| sub.type_name)
+                    .collect();
+                subscribed.sort_unstable();
+                ActivityInfo {
+                    id,
+                    type_name: activities.type_name(id),
+                    status: activities.status(id),
+                    subscriptions: subscribed,
+                }
+            })
+            .collect();
+        ActivityMetadata { activities }
+    }
+}
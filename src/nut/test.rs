@@ -1,5 +1,6 @@
 use crate::*;
-use std::cell::Cell;
+use core::any::Any;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 struct TestActivity {
@@ -25,7 +26,7 @@ impl TestActivity {
 #[derive(Clone, Copy)]
 enum TestDomains {
     DomainA,
-    _DomainB,
+    DomainB,
 }
 domain_enum!(TestDomains);
 
@@ -105,6 +106,7 @@ fn domained_activity() {
     crate::publish(TestUpdateMsg);
 }
 
+#[derive(Clone, Debug)]
 struct TestMessage(u32);
 #[test]
 fn message_passing() {
@@ -219,3 +221,2843 @@ fn on_delete_domained() {
 
     assert_eq!(1, counter.get()); // Make sure subscription has been called
 }
+
+struct UnhandledMsg;
+#[test]
+#[should_panic(expected = "UnhandledMsg")]
+fn strict_unhandled_panics() {
+    crate::set_strict_unhandled(true);
+    crate::publish(UnhandledMsg);
+}
+
+#[test]
+fn strict_unhandled_subscribed() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+    crate::set_strict_unhandled(true);
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, counter.get());
+}
+
+#[test]
+fn read_other_activity_inside_publish() {
+    let target = TestActivity::new();
+    let target_counter = target.shared_counter_ref();
+    target_counter.set(5);
+    let target_id = crate::new_activity(target);
+
+    let monitor = TestActivity::new();
+    let counter = monitor.shared_counter_ref();
+    let monitor_id = crate::new_activity(monitor);
+    monitor_id.subscribe(move |monitor, _msg: &TestUpdateMsg| {
+        let observed = target_id.read(|target| target.counter.get());
+        assert_eq!(Some(5), observed);
+        monitor.inc(1);
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, counter.get());
+}
+
+#[test]
+fn read_dispatching_activity_is_rejected() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(move |activity, _msg: &TestUpdateMsg| {
+        assert!(id.read(|_| ()).is_none());
+        activity.inc(1);
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, counter.get());
+    assert_eq!(Some(1), id.read(|activity| activity.counter.get()));
+}
+
+struct OldMsg(u32);
+struct NewMsg(u32);
+#[test]
+fn message_alias() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &OldMsg| activity.inc(msg.0));
+    id.subscribe(|activity, msg: &NewMsg| activity.inc(msg.0 * 10));
+    crate::alias_message_with(|old: &OldMsg| NewMsg(old.0));
+
+    crate::publish(OldMsg(1));
+    assert_eq!(11, counter.get());
+    crate::publish(NewMsg(1));
+    assert_eq!(21, counter.get());
+}
+
+#[test]
+fn unfired_subscriptions() {
+    let id = crate::new_activity(TestActivity::new());
+    id.subscribe(|_activity, _msg: &TestUpdateMsg| {});
+    id.subscribe(|_activity, _msg: &TestMessage| {});
+    crate::publish(TestUpdateMsg);
+
+    let unfired = crate::unfired_subscriptions();
+    assert_eq!(
+        vec![(id.into(), std::any::type_name::<TestMessage>())],
+        unfired
+    );
+}
+
+#[test]
+fn default_domain() {
+    let d = TestDomains::DomainA;
+    let before = crate::new_activity(TestActivity::new());
+    crate::set_default_domain(&d);
+    crate::store_to_domain(&d, 7usize);
+
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe_domained(|activity, domain, _msg: &TestUpdateMsg| {
+        let x: usize = *domain.get();
+        activity.inc(x as u32);
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(7, counter.get());
+    assert_eq!(None, before.domain_index.index());
+}
+
+struct TestServices {
+    factor: u32,
+}
+#[test]
+fn subscribe_with_services() {
+    crate::set_services(Box::new(TestServices { factor: 3 }));
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe_with_services(|activity, services, msg: &TestMessage| {
+        let services: &TestServices = services.downcast_ref().expect("wrong services");
+        activity.inc(services.factor * msg.0);
+    });
+    crate::publish(TestMessage(2));
+    assert_eq!(6, counter.get());
+}
+
+#[test]
+fn domain_snapshot() {
+    let d = TestDomains::DomainA;
+    crate::register_snapshot_type::<usize>();
+    crate::store_to_domain(&d, 7usize);
+    crate::store_to_domain(&d, TestMessage(1));
+    let snapshot = crate::snapshot_domain(&d);
+
+    let id = crate::new_domained_activity(TestActivity::new(), &d);
+    id.subscribe_domained(|_activity, domain, _msg: &TestUpdateMsg| {
+        *domain.get_mut::<usize>() += 1;
+    });
+    crate::publish(TestUpdateMsg);
+
+    assert_eq!(Some(&7), snapshot.try_get::<usize>());
+    assert_eq!(Some(&8), crate::snapshot_domain(&d).try_get::<usize>());
+    assert!(
+        !snapshot.contains::<TestMessage>(),
+        "unregistered type in snapshot"
+    );
+}
+
+#[test]
+fn pause_type() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(100));
+
+    crate::pause_type::<TestMessage>();
+    for i in 1..=5 {
+        crate::publish(TestMessage(i));
+    }
+    crate::publish(TestUpdateMsg);
+    assert_eq!(100, counter.get(), "paused message delivered");
+
+    crate::resume_type::<TestMessage>(true);
+    assert_eq!(105, counter.get());
+
+    crate::pause_type::<TestMessage>();
+    crate::publish(TestMessage(1));
+    crate::publish(TestMessage(2));
+    crate::resume_type::<TestMessage>(false);
+    assert_eq!(108, counter.get());
+}
+
+#[derive(Default)]
+struct TestDomainCounter(u32);
+#[test]
+fn domain_get_or_default() {
+    let d = TestDomains::DomainA;
+    let id = crate::new_domained_activity(TestActivity::new(), &d);
+    id.subscribe_domained(|activity, domain, _msg: &TestUpdateMsg| {
+        let counter = domain.get_or_default::<TestDomainCounter>();
+        counter.0 += 1;
+        activity.inc(counter.0);
+    });
+    let counter = id
+        .read(|a| a.shared_counter_ref())
+        .expect("activity missing");
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestUpdateMsg);
+    assert_eq!(3, counter.get());
+}
+
+struct TestForwardMsg;
+#[test]
+fn trace_publish() {
+    let first = crate::new_activity(TestActivity::new());
+    let second = crate::new_activity(TestActivity::new());
+    first.subscribe(|_activity, _msg: &TestUpdateMsg| crate::publish(TestForwardMsg));
+    second.subscribe(|_activity, _msg: &TestForwardMsg| crate::publish(TestMessage(1)));
+    first.subscribe(|_activity, _msg: &TestMessage| {});
+
+    let trace = crate::trace_publish(TestUpdateMsg);
+    let deliveries: Vec<_> = trace
+        .deliveries()
+        .iter()
+        .map(|d| (d.activity, d.type_name))
+        .collect();
+    assert_eq!(
+        vec![
+            (first.into(), std::any::type_name::<TestUpdateMsg>()),
+            (second.into(), std::any::type_name::<TestForwardMsg>()),
+            (first.into(), std::any::type_name::<TestMessage>()),
+        ],
+        deliveries
+    );
+    assert_eq!(3, crate::trace_publish(TestUpdateMsg).deliveries().len());
+}
+
+#[test]
+fn subscribe_requires() {
+    let d = TestDomains::DomainA;
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &d);
+    id.subscribe_requires(
+        |activity, required: &TestDomainCounter, msg: &TestMessage| {
+            activity.inc(required.0 * msg.0);
+        },
+    );
+
+    crate::publish(TestMessage(1));
+    assert_eq!(0, counter.get(), "called without required value");
+    assert_eq!(1, crate::unfired_subscriptions().len());
+    crate::store_to_domain(&d, TestDomainCounter(3));
+    crate::publish(TestMessage(2));
+    assert_eq!(6, counter.get());
+    assert!(crate::unfired_subscriptions().is_empty());
+}
+
+struct PublishOnDrop(std::sync::mpsc::Sender<Result<(), NutsError>>);
+impl Drop for PublishOnDrop {
+    fn drop(&mut self) {
+        let result = crate::try_publish(TestUpdateMsg);
+        self.0.send(result).expect("receiver dropped");
+    }
+}
+#[test]
+fn storage_unavailable_during_teardown() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        crate::new_activity(PublishOnDrop(sender));
+    })
+    .join()
+    .expect("thread panicked");
+    // The activity is dropped together with the thread-local storage
+    assert_eq!(
+        Err(NutsError::StorageUnavailable),
+        receiver.recv().expect("activity not dropped")
+    );
+}
+#[test]
+fn capture_publishes() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    crate::capture(|capture| {
+        crate::publish(TestUpdateMsg);
+        crate::publish(TestMessage(7));
+        crate::publish(TestUpdateMsg);
+        assert_eq!(
+            vec![
+                std::any::type_name::<TestUpdateMsg>(),
+                std::any::type_name::<TestMessage>(),
+                std::any::type_name::<TestUpdateMsg>(),
+            ],
+            capture.type_names()
+        );
+        let captured = capture.take();
+        assert_eq!(
+            7,
+            captured[1]
+                .downcast_ref::<TestMessage>()
+                .expect("wrong type")
+                .0
+        );
+    });
+    assert_eq!(0, counter.get(), "subscriber called while capturing");
+
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, counter.get());
+}
+
+#[test]
+fn subscription_order() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    // Each handler checks that it runs at the expected position
+    let b = id.subscribe(|activity, _msg: &TestUpdateMsg| {
+        assert_eq!(1, activity.counter.get());
+        activity.inc(1);
+    });
+    let a = id.subscribe(|activity, _msg: &TestUpdateMsg| {
+        assert_eq!(0, activity.counter.get());
+        activity.inc(1);
+    });
+    b.after(a).expect("no cycle");
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, counter.get());
+
+    assert_eq!(Err(NutsError::OrderingCycle), a.after(b));
+    counter.set(0);
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, counter.get());
+}
+
+#[test]
+fn subscription_order_chain() {
+    let id = crate::new_activity(TestActivity::new());
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let subs: Vec<_> = (0..3)
+        .map(|i| {
+            let log = log.clone();
+            id.subscribe(move |_activity, _msg: &TestUpdateMsg| log.borrow_mut().push(i))
+        })
+        .collect();
+    subs[0].after(subs[2]).expect("no cycle");
+    subs[2].after(subs[1]).expect("no cycle");
+    crate::publish(TestUpdateMsg);
+    assert_eq!(vec![1, 2, 0], *log.borrow());
+    assert!(subs[1].after(subs[0]).is_err());
+}
+
+#[test]
+fn subscription_order_after_delete() {
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let middle = crate::new_activity(log.clone());
+    let id = crate::new_activity(log.clone());
+    let x = middle.subscribe(|log, _msg: &TestUpdateMsg| log.borrow_mut().push(0));
+    let first = id.subscribe(|log, _msg: &TestUpdateMsg| log.borrow_mut().push(1));
+    let second = id.subscribe(|log, _msg: &TestUpdateMsg| log.borrow_mut().push(2));
+    x.after(first).expect("no cycle");
+    second.after(x).expect("no cycle");
+    middle.delete();
+    // The constraints through the deleted subscription are gone
+    crate::nut::with_nut(|nut| {
+        let constraints =
+            nut.subscriptions.borrow()[&Topic::message::<TestUpdateMsg>()].constraint_count();
+        assert_eq!(0, constraints);
+    });
+    assert_eq!(Ok(()), first.after(second));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(vec![2, 1], *log.borrow());
+}
+
+#[test]
+fn publish_fold() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    for factor in 1..=3 {
+        id.subscribe_fold(move |activity, msg: &TestMessage, acc: &mut u32| {
+            activity.inc(1);
+            *acc = *acc * 10 + msg.0 * factor;
+        });
+    }
+    // Only fold subscriptions with an accumulator of the same type are called
+    id.subscribe_fold(|_activity, _msg: &TestMessage, _acc: &mut u64| panic!("wrong accumulator"));
+    id.subscribe(|_activity, _msg: &TestMessage| panic!("not a fold subscription"));
+
+    assert_eq!(123, crate::publish_fold(TestMessage(1), 0u32));
+    assert_eq!(3, counter.get());
+}
+
+#[test]
+#[should_panic(expected = "publish_fold")]
+fn publish_fold_reentrant() {
+    let id = crate::new_activity(TestActivity::new());
+    id.subscribe(|_activity, _msg: &TestUpdateMsg| {
+        crate::publish_fold(TestMessage(1), 0u32);
+    });
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+fn clear_domain() {
+    let a = TestActivity::new();
+    let counter_a = a.shared_counter_ref();
+    let id_a = crate::new_domained_activity(a, &TestDomains::DomainA);
+    let b = TestActivity::new();
+    let counter_b = b.shared_counter_ref();
+    let id_b = crate::new_domained_activity(b, &TestDomains::DomainB);
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    crate::store_to_domain(&TestDomains::DomainB, 2u32);
+
+    for id in [id_a, id_b] {
+        id.on_leave(|activity| activity.inc(100));
+        id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+        id.subscribe_masked(
+            SubscriptionFilter::no_filter(),
+            |activity, _msg: &TestMessage| activity.inc(10),
+        );
+    }
+
+    crate::clear_domain(&TestDomains::DomainA);
+    assert_eq!(100, counter_a.get());
+    assert!(!id_a.is_subscribed::<TestUpdateMsg>());
+    assert!(!id_a.is_subscribed::<TestMessage>());
+    assert!(id_b.is_subscribed::<TestMessage>());
+
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestMessage(0));
+    assert_eq!(100, counter_a.get());
+    assert_eq!(11, counter_b.get());
+
+    let checker = crate::new_domained_activity((), &TestDomains::DomainA);
+    checker.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        assert!(domain.try_get::<u32>().is_none())
+    });
+    let checker = crate::new_domained_activity((), &TestDomains::DomainB);
+    checker.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        assert_eq!(Some(&2), domain.try_get::<u32>())
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(12, counter_b.get());
+}
+
+#[test]
+fn clear_domain_inside_publish() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &TestDomains::DomainA);
+    id.subscribe(|activity, _msg: &TestUpdateMsg| {
+        crate::clear_domain(&TestDomains::DomainA);
+        activity.inc(1);
+    });
+    // Still delivered, the teardown takes place after the current message
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, counter.get());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, counter.get());
+}
+
+#[test]
+fn publish_bounded() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    crate::publish_bounded::<4, _>(TestMessage(2));
+    assert_eq!(2, counter.get());
+}
+
+#[test]
+fn defer_domain_write() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &TestDomains::DomainA);
+    crate::store_to_domain(&TestDomains::DomainA, TestMessage(1));
+    id.subscribe_domained(|activity, domain, _msg: &TestUpdateMsg| {
+        let value = domain.get::<TestMessage>().0;
+        activity.inc(value);
+        crate::defer_domain_write(&TestDomains::DomainA, TestMessage(value * 10));
+    });
+    // Subscribers of the same message still see the old value
+    id.subscribe_domained(|activity, domain, _msg: &TestUpdateMsg| {
+        activity.inc(domain.get::<TestMessage>().0);
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, counter.get());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(22, counter.get());
+}
+
+#[test]
+fn subscribe_from() {
+    struct Announce;
+    let a = crate::new_activity(());
+    let c = crate::new_activity(());
+    a.subscribe(|_, msg: &TestMessage| {
+        if msg.0 == 1 {
+            crate::publish(TestUpdateMsg)
+        }
+    });
+    c.subscribe(|_, msg: &TestMessage| {
+        if msg.0 == 2 {
+            crate::publish(TestUpdateMsg)
+        }
+    });
+    let b = TestActivity::new();
+    let counter = b.shared_counter_ref();
+    let b = crate::new_activity(b);
+    b.subscribe_from(a.into(), |activity, _msg: &TestUpdateMsg| activity.inc(1));
+    // Messages published by `b` itself are not from `a` either
+    b.subscribe(|_, _msg: &Announce| crate::publish(TestUpdateMsg));
+
+    crate::publish(TestMessage(1));
+    assert_eq!(1, counter.get());
+    crate::publish(TestMessage(2));
+    crate::publish(TestUpdateMsg);
+    crate::publish(Announce);
+    assert_eq!(1, counter.get());
+}
+
+#[test]
+fn deterministic_activity_ids() {
+    fn setup() -> Vec<UncheckedActivityId> {
+        let mut ids: Vec<UncheckedActivityId> = vec![
+            crate::new_activity(()).into(),
+            crate::new_domained_activity(0u32, &TestDomains::DomainA).into(),
+        ];
+        ids[0].set_status(LifecycleStatus::Deleted);
+        ids.push(crate::new_activity(TestActivity::new()).into());
+        ids
+    }
+    let first = std::thread::spawn(setup).join().expect("setup failed");
+    let second = std::thread::spawn(setup).join().expect("setup failed");
+    assert_eq!(first, second);
+    // Strictly increasing, deleted ids are not reused
+    assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+impl Handler<TestUpdateMsg> for TestActivity {
+    fn handle(&mut self, _msg: &TestUpdateMsg) {
+        self.inc(1);
+    }
+}
+
+impl Handler<TestMessage> for TestActivity {
+    fn handle(&mut self, msg: &TestMessage) {
+        self.inc(msg.0);
+    }
+}
+
+#[test]
+fn subscribe_handler() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe_handler::<TestUpdateMsg>();
+    id.subscribe_handler::<TestMessage>();
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestMessage(10));
+    assert_eq!(11, counter.get());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn domain_borrow_released_after_dispatch() {
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| domain.store(1u32));
+    id.subscribe_domained_owned(|_, domain, msg: TestMessage| domain.store(msg.0));
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestMessage(2));
+    crate::nut::with_nut(|nut| assert_eq!(0, nut.managed_state.borrow().domain_borrows()));
+}
+
+#[test]
+fn subscribed_types() {
+    struct NotSubscribed;
+    let id = crate::new_domained_activity(TestActivity::new(), &TestDomains::DomainA);
+    id.subscribe(|_, _msg: &TestUpdateMsg| {});
+    id.subscribe_mut(|_, _msg: &mut TestMessage| {});
+    id.subscribe_domained_owned(|_, _, _msg: UnhandledMsg| {});
+    id.subscribe(|_, _msg: &TestMessage| {});
+    id.on_enter(|_| {});
+
+    let types = crate::subscribed_types();
+    assert_eq!(3, types.len());
+    for name in [
+        std::any::type_name::<TestUpdateMsg>(),
+        std::any::type_name::<TestMessage>(),
+        std::any::type_name::<UnhandledMsg>(),
+    ] {
+        assert!(types.contains(&name));
+    }
+    assert!(!types.contains(&std::any::type_name::<NotSubscribed>()));
+}
+
+#[test]
+fn domain_mirror() {
+    use core::any::TypeId;
+    let writes = Rc::new(std::cell::RefCell::new(vec![]));
+    let mirror = writes.clone();
+    crate::set_domain_mirror(move |domain, type_id| mirror.borrow_mut().push((domain, type_id)));
+
+    let a = DomainId::new(&TestDomains::DomainA);
+    let b = DomainId::new(&TestDomains::DomainB);
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    crate::store_to_domain(&TestDomains::DomainB, TestMessage(0));
+    assert_eq!(
+        vec![(a, TypeId::of::<u32>()), (b, TypeId::of::<TestMessage>())],
+        writes.take()
+    );
+
+    let id = crate::new_domained_activity((), &TestDomains::DomainB);
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        domain.get_mut::<TestMessage>().0 += 1;
+        domain.store(TestMessage(5));
+    });
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        domain.get::<TestMessage>();
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(vec![(b, TypeId::of::<TestMessage>())], writes.take());
+}
+
+#[test]
+fn publish_await_response() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|_, _cmd: &TestUpdateMsg| crate::publish(TestForwardMsg));
+    id.subscribe(|_, _msg: &TestForwardMsg| {
+        crate::publish(TestMessage(1));
+        crate::publish(TestMessage(2));
+    });
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    let response = crate::publish_await_response::<_, TestMessage>(TestUpdateMsg);
+    assert_eq!(Some(1), response.map(|msg| msg.0));
+    // Only the first response is intercepted
+    assert_eq!(2, counter.get());
+
+    let response = crate::publish_await_response::<_, UnhandledMsg>(TestUpdateMsg);
+    assert!(response.is_none());
+    assert_eq!(5, counter.get());
+}
+
+#[test]
+#[should_panic(expected = "cannot await a response of the same type as the command")]
+fn publish_await_response_same_type() {
+    let id = crate::new_activity(());
+    id.subscribe(|_, _msg: &TestMessage| {});
+    let _ = crate::publish_await_response::<_, TestMessage>(TestMessage(1));
+}
+
+#[test]
+fn publish_await_response_after_panic() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    crate::set_strict_unhandled(true);
+    let result =
+        std::panic::catch_unwind(|| crate::publish_await_response::<_, TestMessage>(UnhandledMsg));
+    assert!(result.is_err());
+    crate::set_strict_unhandled(false);
+    // The response is no longer awaited after the panic
+    crate::publish(TestMessage(3));
+    assert_eq!(3, counter.get());
+}
+
+#[test]
+fn slow_handler_detection() {
+    let now = Rc::new(Cell::new(100u64));
+    let clock = now.clone();
+    crate::set_clock(move || clock.get());
+    let reports = Rc::new(std::cell::RefCell::new(vec![]));
+    let reported = reports.clone();
+    crate::on_slow_handler(move |activity, type_name, ms| {
+        reported.borrow_mut().push((activity, type_name, ms))
+    });
+    crate::set_slow_handler_threshold_ms(10);
+
+    let fast = crate::new_activity(());
+    fast.subscribe(|_, _msg: &TestUpdateMsg| {});
+    let slow = crate::new_activity(());
+    let advance = now.clone();
+    slow.subscribe(move |_, _msg: &TestUpdateMsg| advance.set(advance.get() + 25));
+    let borderline = crate::new_activity(());
+    let advance = now.clone();
+    borderline.subscribe(move |_, _msg: &TestUpdateMsg| advance.set(advance.get() + 10));
+
+    crate::publish(TestUpdateMsg);
+    assert_eq!(
+        vec![(slow.into(), std::any::type_name::<TestUpdateMsg>(), 25)],
+        *reports.borrow()
+    );
+}
+
+#[test]
+fn domain_swap() {
+    struct FrameBuffer(Vec<u32>);
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    crate::store_to_domain(&TestDomains::DomainA, FrameBuffer(Vec::with_capacity(16)));
+    let back = std::cell::RefCell::new(Some(FrameBuffer(Vec::with_capacity(16))));
+    id.subscribe_domained(move |_, domain, msg: &TestMessage| {
+        let mut next = back.borrow_mut().take().expect("back buffer missing");
+        next.0.clear();
+        next.0.push(msg.0);
+        let old = domain.swap(next).expect("front buffer missing");
+        assert!(old.0.capacity() >= 16);
+        *back.borrow_mut() = Some(old);
+    });
+    for frame in 1..=3 {
+        crate::publish(TestMessage(frame));
+    }
+    let checker = crate::new_domained_activity((), &TestDomains::DomainA);
+    checker.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        assert_eq!(vec![3], domain.get::<FrameBuffer>().0);
+    });
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+fn call_activity() {
+    let b = crate::new_activity(TestActivity::new());
+    let a = crate::new_activity(TestActivity::new());
+    a.subscribe(move |activity, msg: &TestMessage| {
+        let doubled = crate::call(
+            b,
+            |b: &mut TestActivity, n: u32| {
+                b.inc(n);
+                2 * n
+            },
+            msg.0,
+        );
+        activity.inc(doubled.expect("call failed"));
+    });
+    // Calling into the activity whose handler is executing is refused
+    b.subscribe(move |_, _msg: &TestUpdateMsg| {
+        assert!(crate::call(b, |_, ()| (), ()).is_none());
+    });
+
+    crate::publish(TestMessage(3));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(Some(6), a.read(|a| a.counter.get()));
+    assert_eq!(Some(3), crate::call(b, |b, ()| b.counter.get(), ()));
+
+    b.set_status(LifecycleStatus::Deleted);
+    assert!(crate::call(b, |_, ()| (), ()).is_none());
+}
+
+#[test]
+fn publish_dbg() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|_, _msg: &TestUpdateMsg| {
+        crate::publish_dbg(TestMessage(4));
+        crate::publish(TestMessage(5));
+    });
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    let trace = crate::trace_publish(TestUpdateMsg);
+    assert_eq!(["TestMessage(4)"], trace.messages());
+    assert_eq!(9, counter.get());
+    // Without an active trace, it is a normal publish
+    crate::publish_dbg(TestMessage(1));
+    assert_eq!(10, counter.get());
+}
+
+#[test]
+fn validate_wiring() {
+    crate::new_activity(());
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    crate::new_domained_activity((), &TestDomains::DomainA);
+    assert_eq!(Ok(()), crate::validate_wiring());
+
+    let forgotten = crate::new_domained_activity((), &TestDomains::DomainB);
+    assert_eq!(
+        Err(vec![WiringIssue::EmptyDomain {
+            activity: forgotten.into(),
+            domain: DomainId::new(&TestDomains::DomainB),
+        }]),
+        crate::validate_wiring()
+    );
+}
+
+#[test]
+fn process_deferred_limited() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| {
+        activity.inc(msg.0);
+        if msg.0 == 5 {
+            crate::publish(TestUpdateMsg);
+        }
+    });
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(100));
+
+    for i in 1..=5 {
+        crate::enqueue(TestMessage(i));
+    }
+    assert_eq!(0, counter.get());
+    assert_eq!(2, crate::process_deferred_limited(2));
+    assert_eq!(3, counter.get());
+    assert_eq!(2, crate::process_deferred_limited(2));
+    assert_eq!(10, counter.get());
+    // The message published by the last handler counts on its own
+    assert_eq!(1, crate::process_deferred_limited(1));
+    assert_eq!(15, counter.get());
+    assert_eq!(1, crate::process_deferred_limited(2));
+    assert_eq!(115, counter.get());
+    assert_eq!(0, crate::process_deferred_limited(2));
+}
+
+#[test]
+fn single_subscriber_stored_inline() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+    for _ in 0..3 {
+        id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    }
+    crate::nut::with_nut(|nut| {
+        let subscriptions = nut.subscriptions.borrow();
+        assert!(subscriptions[&Topic::message::<TestUpdateMsg>()].is_inline());
+        assert!(!subscriptions[&Topic::message::<TestMessage>()].is_inline());
+    });
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestMessage(10));
+    assert_eq!(31, counter.get());
+}
+
+#[test]
+fn suspend_resume() {
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let id = crate::new_activity(TestActivity::new());
+    let logger = |name: &'static str| {
+        let log = log.clone();
+        move |_: &mut TestActivity| log.borrow_mut().push(name)
+    };
+    id.on_enter(logger("enter"));
+    id.on_leave(logger("leave"));
+    id.on_suspend(logger("suspend"));
+    id.on_resume(logger("resume"));
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(1));
+
+    id.set_status(LifecycleStatus::Suspended);
+    assert_eq!(vec!["leave", "suspend"], log.take());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(Some(0), id.read(|a| a.counter.get()));
+
+    id.set_status(LifecycleStatus::Inactive);
+    assert_eq!(vec!["resume"], log.take());
+    id.set_status(LifecycleStatus::Suspended);
+    assert_eq!(vec!["suspend"], log.take());
+    id.set_status(LifecycleStatus::Active);
+    assert_eq!(vec!["resume", "enter"], log.take());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(Some(1), id.read(|a| a.counter.get()));
+}
+
+#[test]
+fn subscribe_convertible() {
+    #[derive(Clone)]
+    struct ButtonClick(u32);
+    impl From<ButtonClick> for TestMessage {
+        fn from(click: ButtonClick) -> Self {
+            TestMessage(click.0)
+        }
+    }
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe_convertible::<ButtonClick, _, _>(|activity, msg: &TestMessage| {
+        activity.inc(msg.0)
+    });
+    id.subscribe(|activity, msg: &ButtonClick| activity.inc(10 * msg.0));
+
+    crate::publish(ButtonClick(2));
+    assert_eq!(22, counter.get());
+    // The subscription is only for `ButtonClick`
+    crate::publish(TestMessage(100));
+    assert_eq!(22, counter.get());
+}
+
+#[test]
+fn cascade_generation() {
+    let observed = Rc::new(std::cell::RefCell::new(vec![]));
+    let id = crate::new_activity(());
+    let log = observed.clone();
+    id.subscribe(move |_, msg: &TestMessage| {
+        log.borrow_mut().push(crate::cascade_generation());
+        if msg.0 < 2 {
+            crate::publish(TestMessage(msg.0 + 1));
+            // Siblings are of the same generation
+            crate::publish(TestUpdateMsg);
+        }
+    });
+    let log = observed.clone();
+    id.subscribe(move |_, _msg: &TestUpdateMsg| log.borrow_mut().push(crate::cascade_generation()));
+
+    crate::publish(TestMessage(0));
+    assert_eq!(vec![0, 1, 1, 2, 2], *observed.borrow());
+    assert_eq!(0, crate::cascade_generation());
+}
+
+#[test]
+fn ensure_domain_before_activity() {
+    crate::ensure_domain_before_activity(&TestDomains::DomainA);
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    id.on_enter_domained(|_, domain| assert_eq!(Some(&1u32), domain.try_get()));
+    id.set_status(LifecycleStatus::Inactive);
+    id.set_status(LifecycleStatus::Active);
+    // Other domains are not affected
+    crate::new_domained_activity((), &TestDomains::DomainB);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "before any data has been stored")]
+fn ensure_domain_before_activity_violated() {
+    crate::ensure_domain_before_activity(&TestDomains::DomainA);
+    crate::new_domained_activity((), &TestDomains::DomainA);
+}
+
+#[test]
+fn take_dispatch() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    id.subscribe_mut(|activity, msg: &mut TestMessage| {
+        activity.inc(msg.0);
+        msg.0 += 1;
+    });
+    id.subscribe(|_, msg: &TestMessage| {
+        if msg.0 == 2 {
+            crate::publish(TestUpdateMsg)
+        }
+    });
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(100));
+
+    let dispatch = crate::take_dispatch::<TestMessage>();
+    assert_eq!(3, dispatch.len());
+    // Not part of the set
+    id.subscribe(|activity, _msg: &TestMessage| activity.inc(1000));
+
+    let msg = dispatch.dispatch(TestMessage(1)).expect("message consumed");
+    assert_eq!(2, msg.0);
+    assert_eq!(102, counter.get());
+    crate::publish(TestMessage(1));
+    assert_eq!(1204, counter.get());
+    assert!(crate::take_dispatch::<UnhandledMsg>().is_empty());
+}
+
+#[test]
+fn domain_get_or_try_insert_with() {
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    id.subscribe_domained(|_, domain, msg: &TestMessage| {
+        let result = domain.get_or_try_insert_with(|| {
+            if msg.0 == 0 {
+                Err("cannot open cache")
+            } else {
+                Ok(TestMessage(msg.0))
+            }
+        });
+        match msg.0 {
+            0 => assert_eq!(Err("cannot open cache"), result.map(|value| value.0)),
+            _ => assert_eq!(Ok(1), result.map(|value| value.0)),
+        }
+    });
+    id.subscribe_domained(|_, domain, msg: &TestMessage| {
+        assert_eq!(msg.0 != 0, domain.try_get::<TestMessage>().is_some());
+    });
+    crate::publish(TestMessage(0));
+    crate::publish(TestMessage(1));
+    // The stored value is kept
+    crate::publish(TestMessage(2));
+}
+
+#[test]
+fn subscribe_deferred() {
+    #[derive(Clone)]
+    struct Analytics(u32);
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let id = crate::new_activity(());
+    let l = log.clone();
+    id.subscribe_deferred(move |_, msg: &Analytics| {
+        l.borrow_mut().push(format!("deferred {}", msg.0))
+    });
+    let l = log.clone();
+    id.subscribe(move |_, msg: &Analytics| {
+        l.borrow_mut().push(format!("sync {}", msg.0));
+        if msg.0 == 0 {
+            crate::publish(TestUpdateMsg);
+            crate::publish(Analytics(1));
+        }
+    });
+    let l = log.clone();
+    id.subscribe(move |_, _msg: &TestUpdateMsg| l.borrow_mut().push("cascade".to_owned()));
+
+    crate::publish(Analytics(0));
+    assert_eq!(
+        vec!["sync 0", "cascade", "sync 1", "deferred 0", "deferred 1"],
+        *log.borrow()
+    );
+}
+
+#[test]
+fn dead_letter() {
+    let received = Rc::new(std::cell::RefCell::new(vec![]));
+    let log = received.clone();
+    crate::set_dead_letter(move |type_name, msg| {
+        let value = msg.downcast_ref::<TestMessage>().map(|msg| msg.0);
+        log.borrow_mut().push((type_name, value));
+    });
+    let id = crate::new_activity(());
+    id.subscribe(|_, _msg: &TestUpdateMsg| crate::publish(TestMessage(7)));
+
+    crate::publish(TestUpdateMsg);
+    crate::publish(UnhandledMsg);
+    assert_eq!(
+        vec![
+            (std::any::type_name::<TestMessage>(), Some(7)),
+            (std::any::type_name::<UnhandledMsg>(), None),
+        ],
+        *received.borrow()
+    );
+
+    // Inactive subscribers still count
+    id.subscribe(|_, _msg: &TestMessage| {});
+    id.set_status(LifecycleStatus::Inactive);
+    crate::publish(TestMessage(1));
+    assert_eq!(2, received.borrow().len());
+}
+
+#[test]
+fn unregister_by_predicate() {
+    let core = TestActivity::new();
+    let counter = core.shared_counter_ref();
+    let core = crate::new_activity(core);
+    core.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    let mut plugin_ids: Vec<UncheckedActivityId> = vec![];
+    for _ in 0..2 {
+        let plugin = crate::new_activity(());
+        plugin.subscribe(|_, _msg: &TestMessage| panic!("plugin still subscribed"));
+        plugin.subscribe(|_, _msg: &UnhandledMsg| panic!("plugin still subscribed"));
+        plugin_ids.push(plugin.into());
+    }
+    let left = Rc::new(Cell::new(0));
+    let on_leave = left.clone();
+    crate::new_activity(()).on_leave(move |_| on_leave.set(on_leave.get() + 1));
+
+    let plugin = plugin_ids.clone();
+    crate::unregister_by_predicate(move |id| plugin.contains(&id));
+    crate::publish(TestMessage(1));
+    crate::publish(UnhandledMsg);
+    assert_eq!(1, counter.get());
+    assert_eq!(0, left.get());
+    assert!(crate::unfired_subscriptions()
+        .iter()
+        .all(|(id, _)| !plugin_ids.contains(id)));
+
+    // Also works from inside a handler
+    core.subscribe(|_, _msg: &TestUpdateMsg| crate::unregister_by_predicate(|_| true));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, left.get());
+    crate::publish(TestMessage(1));
+    assert_eq!(1, counter.get());
+}
+
+#[test]
+fn domain_writes_visible_across_cascade() {
+    struct Generation0;
+    struct Generation1;
+    struct Generation2;
+    let seen = Rc::new(Cell::new(0));
+    let seen_in_handler = seen.clone();
+
+    let first = crate::new_domained_activity((), &TestDomains::DomainA);
+    let second = crate::new_domained_activity((), &TestDomains::DomainA);
+    let third = crate::new_domained_activity((), &TestDomains::DomainA);
+    first.subscribe_domained(|_, domain, _msg: &Generation0| {
+        domain.store(10u32);
+        crate::publish(Generation1);
+        // Not delivered yet, the write below is still visible to generation 1
+        *domain.get_mut::<u32>() += 10;
+    });
+    second.subscribe_domained(|_, domain, _msg: &Generation1| {
+        assert_eq!(1, crate::cascade_generation());
+        *domain.get_mut::<u32>() += 1;
+        crate::publish(Generation2);
+    });
+    third.subscribe_domained(move |_, domain, _msg: &Generation2| {
+        assert_eq!(2, crate::cascade_generation());
+        seen_in_handler.set(*domain.get::<u32>());
+    });
+
+    crate::publish(Generation0);
+    assert_eq!(21, seen.get());
+    crate::nut::with_nut(|nut| {
+        let mut managed_state = nut.managed_state.borrow_mut();
+        let domain = managed_state
+            .get_mut(DomainId::new(&TestDomains::DomainA))
+            .expect("domain exists");
+        assert_eq!(21, *domain.get::<u32>());
+    });
+}
+
+#[test]
+fn sequence_of_different_types() {
+    struct First;
+    struct Second;
+    struct Third;
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let a = crate::new_activity(());
+    let l = log.clone();
+    a.subscribe(move |_, _: &First| l.borrow_mut().push("first"));
+    let l = log.clone();
+    a.subscribe(move |_, _: &Second| l.borrow_mut().push("second"));
+    let l = log.clone();
+    a.subscribe(move |_, _: &Third| l.borrow_mut().push("third"));
+
+    crate::sequence().then(First).then(Second).then(Third).run();
+    assert_eq!(*log.borrow(), ["first", "second", "third"]);
+
+    // Inside an activity, the sequence is queued behind the messages published before
+    let l = log.clone();
+    a.subscribe(move |_, _: &TestUpdateMsg| {
+        crate::publish(Third);
+        crate::sequence().then(Second).then(First).run();
+        l.borrow_mut().push("update");
+    });
+    log.borrow_mut().clear();
+    crate::publish(TestUpdateMsg);
+    assert_eq!(*log.borrow(), ["update", "third", "second", "first"]);
+}
+
+#[test]
+fn domain_any() {
+    struct Config {
+        debug: bool,
+    }
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        assert!(!domain.any(|config: &Config| config.debug));
+        domain.store(Config { debug: true });
+        assert!(domain.any(|config: &Config| config.debug));
+        domain.store(Config { debug: false });
+        assert!(!domain.any(|config: &Config| config.debug));
+        assert!(domain.any(|config: &Config| !config.debug));
+    });
+    crate::publish(TestUpdateMsg);
+}
+
+struct PublishingDrop;
+impl Drop for PublishingDrop {
+    fn drop(&mut self) {
+        crate::publish(TestMessage(1));
+    }
+}
+
+struct PublishingWithDrop;
+impl Drop for PublishingWithDrop {
+    fn drop(&mut self) {
+        crate::publish_with(|| TestMessage(1));
+    }
+}
+
+#[test]
+fn drop_publish_policy() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let listener = crate::new_activity(a);
+    listener.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    // Enqueue is the default
+    crate::new_activity(PublishingDrop).set_status(LifecycleStatus::Deleted);
+    assert_eq!(1, counter.get());
+    // Checking for receivers while the activity is dropped
+    crate::new_activity(PublishingWithDrop).set_status(LifecycleStatus::Deleted);
+    assert_eq!(2, counter.get());
+    crate::set_strict_unhandled(true);
+    crate::new_activity(PublishingDrop).delete();
+    assert_eq!(3, counter.get());
+    crate::set_strict_unhandled(false);
+
+    crate::set_drop_publish_policy(DropPublishPolicy::Ignore);
+    let id = crate::new_domained_activity(PublishingDrop, &TestDomains::DomainA);
+    id.on_delete(|_| ());
+    crate::store_to_domain(&TestDomains::DomainA, PublishingDrop);
+    crate::new_activity(PublishingDrop);
+    crate::clear_domain(&TestDomains::DomainA);
+    crate::unregister_by_predicate(move |id| id != listener.into());
+    assert_eq!(3, counter.get());
+
+    // Publishing outside of teardowns is not affected
+    crate::publish(TestMessage(1));
+    assert_eq!(4, counter.get());
+}
+
+#[test]
+#[should_panic(expected = "while dropping library owned data")]
+fn drop_publish_policy_panic() {
+    crate::set_drop_publish_policy(DropPublishPolicy::Panic);
+    crate::new_activity(PublishingDrop).set_status(LifecycleStatus::Deleted);
+}
+
+#[test]
+fn subscribe_by_name() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    crate::register_message_type::<TestMessage>("test_message");
+    crate::register_message_type::<TestMessage>("test_message");
+    crate::subscribe_by_name(
+        id.into(),
+        "test_message",
+        Box::new(|activity, msg| {
+            let msg = msg.downcast_ref::<TestMessage>().expect("wrong message");
+            let activity = activity
+                .downcast_mut::<TestActivity>()
+                .expect("wrong activity");
+            activity.inc(msg.0);
+        }),
+    );
+    crate::publish(TestMessage(3));
+    assert_eq!(3, counter.get());
+    id.set_status(LifecycleStatus::Inactive);
+    crate::publish(TestMessage(3));
+    assert_eq!(3, counter.get());
+}
+
+#[test]
+#[should_panic(expected = "No message type registered with name `unknown`")]
+fn subscribe_by_unknown_name() {
+    let id = crate::new_activity(());
+    crate::subscribe_by_name(id.into(), "unknown", Box::new(|_, _| {}));
+}
+
+#[test]
+#[should_panic(expected = "already registered")]
+fn register_message_type_twice() {
+    crate::register_message_type::<TestMessage>("msg");
+    crate::register_message_type::<TestUpdateMsg>("msg");
+}
+
+#[test]
+fn export_metadata() {
+    fn assert_send<T: Send>(_: &T) {}
+    let a = crate::new_activity(TestActivity::new());
+    a.subscribe(|_, _msg: &TestMessage| {});
+    a.subscribe(|_, _msg: &TestUpdateMsg| {});
+    let b = crate::new_activity(());
+    b.subscribe(|_, _msg: &TestMessage| {});
+    b.set_status(LifecycleStatus::Inactive);
+    let deleted = crate::new_activity(());
+    deleted.set_status(LifecycleStatus::Deleted);
+
+    let metadata = crate::export_metadata();
+    assert_send(&metadata);
+    let activities = std::thread::spawn(move || metadata.activities().to_vec())
+        .join()
+        .expect("inspector thread panicked");
+    assert_eq!(2, activities.len());
+    assert_eq!(UncheckedActivityId::from(a), activities[0].id());
+    assert_eq!(
+        std::any::type_name::<TestActivity>(),
+        activities[0].type_name()
+    );
+    assert_eq!(LifecycleStatus::Active, activities[0].status());
+    assert_eq!(
+        [
+            std::any::type_name::<TestMessage>(),
+            std::any::type_name::<TestUpdateMsg>()
+        ],
+        activities[0].subscriptions()
+    );
+    assert_eq!(UncheckedActivityId::from(b), activities[1].id());
+    assert_eq!(LifecycleStatus::Inactive, activities[1].status());
+    assert_eq!(
+        [std::any::type_name::<TestMessage>()],
+        activities[1].subscriptions()
+    );
+}
+
+#[test]
+fn pipe_reaches_only_target() {
+    let producer = crate::new_activity(());
+    let target = TestActivity::new();
+    let target_counter = target.shared_counter_ref();
+    let target = crate::new_activity(target);
+    let global = TestActivity::new();
+    let global_counter = global.shared_counter_ref();
+    let global = crate::new_activity(global);
+    global.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    target.subscribe(|activity, msg: &TestMessage| activity.inc(100 * msg.0));
+
+    let pipe = crate::pipe(producer.into(), target, |activity, msg: &TestMessage| {
+        activity.inc(msg.0)
+    });
+    producer.subscribe(move |_, _msg: &TestUpdateMsg| crate::send_pipe(pipe, TestMessage(2)));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, target_counter.get());
+    assert_eq!(0, global_counter.get());
+
+    // Sending from outside of activities is allowed, publishing does not reach the pipe
+    crate::send_pipe(pipe, TestMessage(1));
+    crate::publish(TestMessage(1));
+    assert_eq!(103, target_counter.get());
+    assert_eq!(1, global_counter.get());
+}
+
+#[test]
+#[should_panic(expected = "Only the sending activity of a pipe can send to it.")]
+fn pipe_from_other_activity() {
+    let producer = crate::new_activity(());
+    let other = crate::new_activity(());
+    let pipe = crate::pipe(producer.into(), other, |_, _msg: &TestMessage| {});
+    other.subscribe(move |_, _msg: &TestUpdateMsg| crate::send_pipe(pipe, TestMessage(0)));
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+fn detect_cycles() {
+    struct A;
+    struct B;
+    struct C;
+    let id = crate::new_activity(());
+    id.subscribe_publishing::<A, B>(|_, _| {});
+    id.subscribe_publishing::<B, C>(|_, _| {});
+    id.subscribe_publishing::<A, C>(|_, _| {});
+    assert!(crate::detect_cycles().is_empty());
+
+    id.subscribe_publishing::<C, A>(|_, _| {});
+    id.subscribe_publishing::<B, B>(|_, _| {});
+    use std::any::type_name as name;
+    assert_eq!(
+        vec![
+            vec![name::<A>(), name::<B>(), name::<C>()],
+            vec![name::<A>(), name::<C>()],
+            vec![name::<B>()],
+        ],
+        crate::detect_cycles()
+    );
+}
+
+#[test]
+fn publish_to_domain_reach() {
+    #[derive(Clone, Copy)]
+    enum Scopes {
+        Root,
+        Parent,
+        Child,
+    }
+    domain_enum!(Scopes);
+
+    let mut counters = vec![];
+    let mut add = |domain: &Scopes| {
+        let a = TestActivity::new();
+        counters.push(a.shared_counter_ref());
+        let id = crate::new_domained_activity(a, domain);
+        id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    };
+    add(&Scopes::Root);
+    add(&Scopes::Parent);
+    add(&Scopes::Child);
+    add(&Scopes::Child);
+    crate::new_activity(()).subscribe(|_, _msg: &TestMessage| panic!("not in the hierarchy"));
+    crate::set_domain_parent(&Scopes::Child, &Scopes::Parent);
+    crate::set_domain_parent(&Scopes::Parent, &Scopes::Root);
+    let counts = || counters.iter().map(|c| c.get()).collect::<Vec<_>>();
+
+    crate::publish_to_domain(&Scopes::Child, TestMessage(1), DomainReach::SelfOnly);
+    assert_eq!(vec![0, 0, 1, 1], counts());
+    crate::publish_to_domain(
+        &Scopes::Child,
+        TestMessage(1),
+        DomainReach::IncludeAncestors,
+    );
+    assert_eq!(vec![1, 1, 2, 2], counts());
+    crate::publish_to_domain(
+        &Scopes::Parent,
+        TestMessage(1),
+        DomainReach::IncludeAncestors,
+    );
+    assert_eq!(vec![2, 2, 2, 2], counts());
+}
+
+#[test]
+#[should_panic(expected = "creates a cycle")]
+fn domain_parent_cycle() {
+    crate::set_domain_parent(&TestDomains::DomainB, &TestDomains::DomainA);
+    crate::set_domain_parent(&TestDomains::DomainA, &TestDomains::DomainB);
+}
+
+#[test]
+fn unsubscribe() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &TestDomains::DomainA);
+    let stream = id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    let domained =
+        id.subscribe_domained(|activity, _domain, msg: &TestMessage| activity.inc(10 * msg.0));
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(100));
+    crate::publish(TestMessage(1));
+    assert_eq!(11, counter.get());
+
+    crate::unsubscribe(stream);
+    crate::unsubscribe(stream);
+    crate::publish(TestMessage(1));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(121, counter.get());
+
+    // From inside a handler
+    id.subscribe(move |_, _msg: &UnhandledMsg| crate::unsubscribe(domained));
+    crate::publish(UnhandledMsg);
+    crate::publish(TestMessage(1));
+    assert_eq!(121, counter.get());
+}
+
+#[test]
+fn subscribe_once() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &TestDomains::DomainA);
+    id.subscribe_once(|activity, msg: &TestMessage| {
+        activity.inc(msg.0);
+        crate::publish(TestMessage(10));
+    });
+    id.subscribe_once_domained(|activity, domain, _msg: &TestUpdateMsg| {
+        domain.store(1u32);
+        activity.inc(100);
+    });
+
+    // Filtered out calls do not consume the subscription
+    id.set_status(LifecycleStatus::Inactive);
+    crate::publish(TestMessage(1));
+    crate::publish(TestUpdateMsg);
+    assert_eq!(0, counter.get());
+
+    id.set_status(LifecycleStatus::Active);
+    crate::publish(TestMessage(1));
+    crate::publish(TestMessage(1));
+    crate::publish(TestUpdateMsg);
+    crate::publish(TestUpdateMsg);
+    assert_eq!(101, counter.get());
+    assert!(crate::subscribed_types().is_empty());
+}
+
+#[test]
+fn publish_to_including_inactive() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let target = crate::new_activity(a);
+    target.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    let b = TestActivity::new();
+    let other_counter = b.shared_counter_ref();
+    let other = crate::new_activity(b);
+    other.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    target.set_status(LifecycleStatus::Inactive);
+    other.set_status(LifecycleStatus::Inactive);
+
+    crate::publish(TestMessage(1));
+    assert_eq!(0, counter.get());
+    crate::publish_to_including_inactive(target, TestMessage(1));
+    assert_eq!(1, counter.get());
+    assert_eq!(0, other_counter.get());
+    crate::nut::with_nut(|nut| {
+        assert_eq!(
+            LifecycleStatus::Inactive,
+            nut.activities.borrow().status(target.into())
+        )
+    });
+
+    target.set_status(LifecycleStatus::Deleted);
+    crate::publish_to_including_inactive(target, TestMessage(1));
+}
+
+#[test]
+fn subscribe_with_history() {
+    crate::set_history::<TestMessage>(3);
+    for i in 1..=5 {
+        crate::publish(TestMessage(i));
+    }
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let l = log.clone();
+    let early = crate::new_activity(());
+    early.subscribe(|_, _msg: &TestMessage| {});
+    let late = crate::new_activity(());
+    late.subscribe_with_history(move |_, msg: &TestMessage| l.borrow_mut().push(msg.0));
+    assert_eq!(*log.borrow(), [3, 4, 5]);
+
+    crate::publish(TestMessage(6));
+    assert_eq!(*log.borrow(), [3, 4, 5, 6]);
+
+    // Reducing the depth drops the oldest messages
+    crate::set_history::<TestMessage>(1);
+    let l = log.clone();
+    log.borrow_mut().clear();
+    late.subscribe_with_history(move |_, msg: &TestMessage| l.borrow_mut().push(msg.0));
+    assert_eq!(*log.borrow(), [6]);
+}
+
+#[test]
+fn publish_to() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let target = crate::new_activity(a);
+    target.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    target.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    let b = TestActivity::new();
+    let other_counter = b.shared_counter_ref();
+    let other = crate::new_activity(b);
+    other.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    other.subscribe(move |_, _msg: &TestUpdateMsg| crate::publish_to(target, TestMessage(10)));
+
+    crate::publish_to(target, TestMessage(1));
+    assert_eq!(2, counter.get());
+    assert_eq!(0, other_counter.get());
+
+    // Queued when called from inside an activity
+    crate::publish(TestUpdateMsg);
+    assert_eq!(22, counter.get());
+
+    // Filters apply, messages without subscription are dropped
+    target.set_status(LifecycleStatus::Inactive);
+    crate::publish_to(target, TestMessage(1));
+    crate::publish_to(target, UnhandledMsg);
+    assert_eq!(22, counter.get());
+    assert_eq!(0, other_counter.get());
+}
+
+#[test]
+fn publish_and_collect() {
+    let first = crate::new_activity(TestActivity::new());
+    let second = crate::new_activity(TestActivity::new());
+    let inactive = crate::new_activity(TestActivity::new());
+    second.subscribe_with_response(|_, msg: &TestMessage| msg.0 * 2);
+    first.subscribe_with_response(|_, msg: &TestMessage| msg.0);
+    inactive.subscribe_with_response(|_, msg: &TestMessage| msg.0 * 3);
+    first.subscribe_with_response(|_, _msg: &TestMessage| "other response type");
+    first.subscribe(|_, _msg: &TestMessage| panic!("not part of the collection"));
+    inactive.set_status(LifecycleStatus::Inactive);
+
+    assert_eq!(
+        vec![10, 5],
+        crate::publish_and_collect::<_, u32>(TestMessage(5))
+    );
+    assert_eq!(
+        vec!["other response type"],
+        crate::publish_and_collect::<_, &str>(TestMessage(5))
+    );
+    assert!(crate::publish_and_collect::<_, u32>(TestUpdateMsg).is_empty());
+}
+
+#[test]
+fn domain_view() {
+    use std::any::TypeId;
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    crate::store_to_domain(&TestDomains::DomainA, TestMessage(2));
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| {
+        let allowed = [TypeId::of::<u32>(), TypeId::of::<u64>()];
+        let mut view = domain.view(&allowed);
+        assert_eq!(Some(&1), view.try_get::<u32>());
+        *view.try_get_mut::<u32>().expect("allowed") += 1;
+        assert!(view.store(3u64).is_ok());
+        assert!(view.try_get::<TestMessage>().is_none());
+        assert!(view.try_get_mut::<TestMessage>().is_none());
+        assert_eq!(Err(NutsError::TypeNotAllowed), view.store(TestMessage(4)));
+
+        assert_eq!(2, *domain.get::<u32>());
+        assert_eq!(3, *domain.get::<u64>());
+        assert_eq!(2, domain.get::<TestMessage>().0);
+    });
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+fn shutdown_runs_teardowns_in_priority_order() {
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let l = log.clone();
+    crate::register_teardown(1, move || l.borrow_mut().push("persistence"));
+    let l = log.clone();
+    crate::register_teardown(10, move || l.borrow_mut().push("network"));
+    let l = log.clone();
+    crate::register_teardown(-5, move || l.borrow_mut().push("logging"));
+    let l = log.clone();
+    crate::register_teardown(1, move || l.borrow_mut().push("cache"));
+
+    let l = log.clone();
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    id.on_leave(move |_| l.borrow_mut().push("on_leave"));
+
+    crate::shutdown();
+    assert_eq!(
+        *log.borrow(),
+        ["network", "persistence", "cache", "logging", "on_leave"]
+    );
+    crate::nut::with_nut(|nut| {
+        assert!(!nut.activities.borrow().contains(id.into()));
+        assert!(nut
+            .managed_state
+            .borrow()
+            .is_domain_empty(DomainId::new(&TestDomains::DomainA)));
+    });
+
+    // Callbacks only run once
+    crate::shutdown();
+    assert_eq!(5, log.borrow().len());
+}
+
+#[test]
+fn publish_ref() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_domained_activity(a, &TestDomains::DomainA);
+    id.subscribe(|activity, msg: &TestMessage| {
+        activity.inc(msg.0);
+        crate::publish(TestUpdateMsg);
+    });
+    id.subscribe_domained(|activity, domain, msg: &TestMessage| {
+        domain.store(msg.0);
+        activity.inc(10 * msg.0);
+    });
+    id.subscribe_mut(|_, _msg: &mut TestMessage| panic!("mutable subscribers are skipped"));
+    id.subscribe_owned(|_, _msg: TestMessage| panic!("owning subscribers are skipped"));
+    id.subscribe(|activity, _msg: &TestUpdateMsg| activity.inc(100));
+
+    let msg = TestMessage(2);
+    crate::publish_ref(&msg);
+    assert_eq!(122, counter.get());
+    assert_eq!(2, msg.0);
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| assert_eq!(2, *domain.get::<u32>()));
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+#[should_panic(expected = "`publish_ref` can only be called outside of activities.")]
+fn publish_ref_inside_activity() {
+    let id = crate::new_activity(());
+    id.subscribe(|_, _msg: &TestUpdateMsg| crate::publish_ref(&TestMessage(1)));
+    crate::publish(TestUpdateMsg);
+}
+
+#[test]
+fn publish_ref_after_queued_events() {
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let id = crate::new_activity(log.clone());
+    id.subscribe(|log, msg: &TestMessage| log.borrow_mut().push(msg.0));
+    crate::enqueue(TestMessage(1));
+    crate::enqueue(TestMessage(2));
+    crate::publish_ref(&TestMessage(3));
+    assert_eq!(vec![1, 2, 3], *log.borrow());
+}
+
+#[test]
+fn publish_ref_dead_letter() {
+    let dead = Rc::new(Cell::new(0));
+    let count = dead.clone();
+    crate::set_dead_letter(move |_, msg| {
+        if let Some(msg) = msg.downcast_ref::<TestMessage>() {
+            count.set(count.get() + msg.0);
+        }
+    });
+    crate::publish_ref(&TestMessage(7));
+    assert_eq!(7, dead.get());
+}
+
+#[test]
+#[should_panic(expected = "`publish_ref` cannot hold back messages of the paused type")]
+fn publish_ref_paused() {
+    let id = crate::new_activity(());
+    id.subscribe(|_, _msg: &TestMessage| {});
+    crate::pause_type::<TestMessage>();
+    crate::publish_ref(&TestMessage(1));
+}
+
+#[test]
+fn publish_fast_path_keeps_nested_order() {
+    let log = Rc::new(std::cell::RefCell::new(vec![]));
+    let l = log.clone();
+    let id = crate::new_activity(());
+    id.subscribe(move |_, msg: &usize| {
+        l.borrow_mut().push(format!("Start of {}", msg));
+        if *msg < 3 {
+            crate::publish(msg + 1);
+        }
+        l.borrow_mut().push(format!("End of {}", msg));
+    });
+    crate::publish(0usize);
+    let expected: Vec<String> = (0..=3)
+        .flat_map(|i| vec![format!("Start of {}", i), format!("End of {}", i)])
+        .collect();
+    assert_eq!(*log.borrow(), expected);
+
+    // Messages queued earlier are still delivered first
+    log.borrow_mut().clear();
+    crate::enqueue(3usize);
+    crate::publish(2usize);
+    assert_eq!(
+        *log.borrow(),
+        [
+            "Start of 3",
+            "End of 3",
+            "Start of 2",
+            "End of 2",
+            "Start of 3",
+            "End of 3"
+        ]
+    );
+}
+
+#[test]
+fn publish_hot_loop() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+    for _ in 0..100_000 {
+        crate::publish(TestMessage(1));
+    }
+    assert_eq!(100_000, counter.get());
+    crate::nut::with_nut(|nut| assert!(nut.deferred_events.is_empty()));
+}
+
+struct DropCounter(Rc<Cell<u32>>);
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn delete_activity() {
+    let drops = Rc::new(Cell::new(0));
+    let id = crate::new_activity(DropCounter(drops.clone()));
+    id.subscribe(move |activity, _msg: &TestMessage| {
+        id.delete();
+        // Self-deletion is deferred until the message has been delivered
+        assert_eq!(0, activity.0.get());
+    });
+    id.subscribe(|activity, _msg: &TestMessage| assert_eq!(0, activity.0.get()));
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let other = crate::new_activity(a);
+    other.subscribe(|activity, msg: &TestMessage| activity.inc(msg.0));
+
+    crate::publish(TestMessage(10));
+    assert_eq!(1, drops.get());
+    assert!(crate::export_metadata()
+        .activities()
+        .iter()
+        .all(|info| info.id() != id.into()));
+    crate::nut::with_nut(|nut| {
+        assert!(nut
+            .subscriptions
+            .borrow()
+            .values()
+            .flat_map(|handlers| handlers.iter())
+            .all(|sub| sub.activity != id.into()))
+    });
+
+    // Stale ids are ignored
+    crate::publish_to(id, TestMessage(1));
+    id.delete();
+    crate::publish(TestMessage(1));
+    assert_eq!(1, drops.get());
+    assert_eq!(11, counter.get());
+}
+
+#[test]
+fn domain_access_report() {
+    struct Score(u32);
+    struct Level(u32);
+    crate::enable_domain_profiling();
+    let d = TestDomains::DomainA;
+    crate::store_to_domain(&d, Score(0));
+    crate::store_to_domain(&d, Level(1));
+
+    let reader = crate::new_domained_activity((), &d);
+    reader.subscribe_domained(|_, domain, _: &TestMessage| {
+        assert_eq!(1, domain.get::<Level>().0);
+    });
+    let writer = crate::new_domained_activity((), &d);
+    writer.subscribe_domained(|_, domain, msg: &TestMessage| {
+        domain.get_mut::<Score>().0 += msg.0;
+    });
+    let other = crate::new_domained_activity((), &TestDomains::DomainB);
+    other.subscribe_domained(|_, domain, _: &TestMessage| domain.store(Score(7)));
+    crate::publish(TestMessage(3));
+
+    let report = crate::domain_access_report();
+    let reads = report.activity(reader).expect("reader accessed its domain");
+    assert_eq!(DomainId::new(&d), reads.domain());
+    assert_eq!(
+        vec![std::any::type_name::<Level>()],
+        reads.reads().collect::<Vec<_>>()
+    );
+    assert_eq!(0, reads.writes().count());
+    let writes = report.activity(writer).expect("writer accessed its domain");
+    assert_eq!(0, writes.reads().count());
+    assert_eq!(
+        vec![std::any::type_name::<Score>()],
+        writes.writes().collect::<Vec<_>>()
+    );
+    // Accesses outside of handlers are not attributed
+    assert_eq!(3, report.activities().count());
+    assert!(!report.conflicts(reader, writer));
+    // Same type, but different domains
+    assert!(!report.conflicts(writer, other));
+}
+
+#[test]
+fn with_domain_outside_activities() {
+    let d = TestDomains::DomainA;
+    let writes = Rc::new(Cell::new(0));
+    let counter = writes.clone();
+    crate::set_domain_mirror(move |_, _| counter.set(counter.get() + 1));
+    crate::with_domain(&d, |domain| domain.store(1u32));
+    assert_eq!(1, writes.get());
+    let a = crate::new_domained_activity((), &d);
+    a.subscribe_domained(|_, domain, msg: &TestMessage| *domain.get_mut::<u32>() += msg.0);
+    crate::publish(TestMessage(2));
+    assert_eq!(3, crate::with_domain(&d, |domain| *domain.get::<u32>()));
+    assert_eq!(2, writes.get());
+    // Domains that have never been used are empty
+    assert!(crate::with_domain(&TestDomains::DomainB, |domain| domain
+        .try_get::<u32>()
+        .is_none()));
+}
+
+#[test]
+#[should_panic(expected = "`with_domain` cannot be used from inside an activity.")]
+fn with_domain_inside_activity() {
+    let a = crate::new_activity(());
+    a.subscribe(|_, _: &TestMessage| {
+        crate::with_domain(&DefaultDomain, |_| ());
+    });
+    crate::publish(TestMessage(0));
+}
+
+#[test]
+fn predicate_filter() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    let filter = SubscriptionFilter::with_predicate(|msg: &TestMessage| msg.0 != 1);
+    id.subscribe_masked(filter.clone(), |a, msg: &TestMessage| a.inc(msg.0));
+    id.subscribe_masked(filter, |a, msg: &TestMessage| a.inc(msg.0 * 10));
+    // Owned messages are taken before later subscribers see them
+    id.subscribe_owned(|a, msg: TestMessage| a.inc(msg.0 * 100));
+    let late = SubscriptionFilter::with_predicate(|_: &TestMessage| true);
+    id.subscribe_masked(late, |a, msg: &TestMessage| a.inc(msg.0 * 1000));
+
+    crate::publish(TestMessage(1));
+    assert_eq!(100, counter.get());
+    crate::publish(TestMessage(2));
+    assert_eq!(100 + 2 + 20 + 200, counter.get());
+    crate::publish_ref(&TestMessage(4));
+    assert_eq!(322 + 4 + 40 + 4000, counter.get());
+
+    // The predicate does not replace the lifecycle check
+    id.set_status(LifecycleStatus::Inactive);
+    crate::publish(TestMessage(2));
+    assert_eq!(4366, counter.get());
+}
+
+#[test]
+#[should_panic(expected = "The predicate of the subscription filter expects another message type")]
+fn predicate_filter_wrong_type() {
+    let id = crate::new_activity(());
+    let filter = SubscriptionFilter::with_predicate(|_: &u32| true);
+    id.subscribe_masked(filter, |_, _: &TestMessage| {});
+}
+
+#[test]
+fn status_change_callback() {
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let watched = crate::new_activity(());
+    let other = crate::new_activity(());
+    let log = changes.clone();
+    watched.on_status_change(move |old, new| log.borrow_mut().push((old, new)));
+    other.on_status_change(|_, _| panic!("Callback of another activity called"));
+    watched.set_status(LifecycleStatus::Inactive);
+    // Setting the same status again is not a change
+    watched.set_status(LifecycleStatus::Inactive);
+    watched.set_status(LifecycleStatus::Deleted);
+    assert_eq!(
+        vec![
+            (LifecycleStatus::Active, LifecycleStatus::Inactive),
+            (LifecycleStatus::Inactive, LifecycleStatus::Deleted),
+        ],
+        *changes.borrow()
+    );
+}
+
+#[test]
+fn domain_transaction_rollback() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct Health(u32);
+    #[derive(Debug, PartialEq)]
+    struct Armor(u32);
+    struct Buff;
+    let mut domain = DomainState::default();
+    domain.store(Health(10));
+    domain.store(Armor(2));
+    domain.store(Buff);
+
+    let result: Result<(), &str> = domain.transaction(|tx| {
+        tx.try_get_mut::<Health>().expect("stored").0 = 5;
+        tx.store(Armor(0));
+        tx.store(Armor(1));
+        assert!(tx.remove::<Buff>());
+        tx.store(0u8);
+        assert_eq!(Some(&Armor(1)), tx.try_get::<Armor>());
+        Err("invalid")
+    });
+    assert_eq!(Err("invalid"), result);
+    assert_eq!(&Health(10), domain.get::<Health>());
+    assert_eq!(&Armor(2), domain.get::<Armor>());
+    assert!(domain.try_get::<Buff>().is_some());
+    assert!(domain.try_get::<u8>().is_none());
+
+    let result: Result<u32, ()> = domain.transaction(|tx| {
+        tx.store(Armor(3));
+        Ok(tx.try_get::<Health>().expect("stored").0)
+    });
+    assert_eq!(Ok(10), result);
+    assert_eq!(&Armor(3), domain.get::<Armor>());
+}
+
+#[test]
+fn subscription_priority() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let id = crate::new_activity(calls.clone());
+    id.subscribe_with_priority(5, |calls, _: &TestMessage| calls.borrow_mut().push("5a"));
+    id.subscribe(|calls, _: &TestMessage| calls.borrow_mut().push("0a"));
+    id.subscribe_with_priority(-1, |calls, _: &TestMessage| calls.borrow_mut().push("-1"));
+    let late =
+        id.subscribe_with_priority(5, |calls, _: &TestMessage| calls.borrow_mut().push("5b"));
+    let first = id.subscribe(|calls, _: &TestMessage| calls.borrow_mut().push("0b"));
+    crate::publish(TestMessage(0));
+    assert_eq!(vec!["-1", "0a", "0b", "5a", "5b"], *calls.borrow());
+
+    // Explicit constraints take precedence
+    calls.borrow_mut().clear();
+    first.after(late).expect("no cycle");
+    crate::publish(TestMessage(0));
+    assert_eq!(vec!["-1", "0a", "5a", "5b", "0b"], *calls.borrow());
+}
+
+#[test]
+fn subscribe_any_after_concrete_types() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let tracer = crate::new_activity(calls.clone());
+    let wildcard = tracer.subscribe_any(|calls, msg: &dyn Any| {
+        let name = if let Some(msg) = msg.downcast_ref::<TestMessage>() {
+            format!("any {}", msg.0)
+        } else if msg.is::<TestUpdateMsg>() {
+            "any update".to_owned()
+        } else {
+            "any other".to_owned()
+        };
+        calls.borrow_mut().push(name);
+    });
+    let id = crate::new_activity(calls.clone());
+    id.subscribe(|calls, msg: &TestMessage| calls.borrow_mut().push(format!("concrete {}", msg.0)));
+
+    crate::publish(TestMessage(1));
+    // Messages without concrete subscribers are observed, too
+    crate::publish(TestUpdateMsg);
+    crate::publish_ref(&TestMessage(2));
+    // Builtin events are not published messages
+    id.set_status(LifecycleStatus::Inactive);
+    id.set_status(LifecycleStatus::Active);
+    assert_eq!(
+        vec!["concrete 1", "any 1", "any update", "concrete 2", "any 2"],
+        *calls.borrow()
+    );
+
+    calls.borrow_mut().clear();
+    crate::unsubscribe(wildcard);
+    crate::publish(TestMessage(3));
+    assert_eq!(vec!["concrete 3"], *calls.borrow());
+}
+
+#[test]
+fn shared_activity() {
+    let shared = Rc::new(RefCell::new(TestActivity::new()));
+    let counter = shared.borrow().shared_counter_ref();
+    let id = crate::new_shared_activity(shared.clone());
+    let calls = id.subscribe(|a, msg: &TestMessage| a.inc(msg.0));
+    id.subscribe_mut(|a, msg: &mut TestMessage| a.inc(msg.0 * 10));
+
+    crate::publish(TestMessage(1));
+    assert_eq!(11, counter.get());
+    shared.borrow().inc(100);
+    assert_eq!(Some(111), id.read(|a| a.counter.get()));
+
+    // Contended borrows skip the handler, later messages are delivered again
+    {
+        let _borrow = shared.borrow_mut();
+        crate::publish(TestMessage(1));
+        assert!(id.read(|_| ()).is_none());
+    }
+    assert_eq!(111, counter.get());
+    crate::publish(TestMessage(2));
+    assert_eq!(133, counter.get());
+    let subscriptions = crate::nut::with_nut(|nut| {
+        nut.subscriptions.borrow()[&Topic::message::<TestMessage>()]
+            .iter()
+            .filter(|sub| sub.index() == calls.index)
+            .map(|sub| sub.calls())
+            .sum::<usize>()
+    });
+    assert_eq!(2, subscriptions);
+
+    // Still referenced outside, so on_delete is not called
+    id.on_delete(|_| panic!("on_delete called"));
+    id.set_status(LifecycleStatus::Deleted);
+    assert_eq!(1, Rc::strong_count(&shared));
+}
+
+#[test]
+fn publish_async_settles() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    #[derive(Default)]
+    struct WakeCounter(AtomicUsize);
+    impl Wake for WakeCounter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    type Pending = Rc<RefCell<Option<Pin<Box<dyn Future<Output = ()>>>>>>;
+
+    let wakes = Arc::new(WakeCounter::default());
+    let waker = Waker::from(wakes.clone());
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|a, msg: &TestMessage| {
+        a.inc(msg.0);
+        if msg.0 > 1 {
+            crate::publish(TestMessage(msg.0 - 1));
+        }
+    });
+
+    // Outside of activities, the first poll processes the whole cascade
+    let mut settled = crate::publish_async(TestMessage(3));
+    assert_eq!(0, counter.get());
+    let mut cx = Context::from_waker(&waker);
+    assert_eq!(Poll::Ready(()), Pin::new(&mut settled).poll(&mut cx));
+    assert_eq!(6, counter.get());
+
+    // Inside activities, the future is woken once the current broadcast has settled
+    let pending: Pending = Rc::new(RefCell::new(None));
+    let inner = pending.clone();
+    let handler_waker = waker.clone();
+    id.subscribe(move |_, _: &TestUpdateMsg| {
+        let mut settled: Pin<Box<dyn Future<Output = ()>>> =
+            Box::pin(crate::publish_async(TestMessage(2)));
+        let mut cx = Context::from_waker(&handler_waker);
+        assert_eq!(Poll::Pending, settled.as_mut().poll(&mut cx));
+        *inner.borrow_mut() = Some(settled);
+    });
+    crate::publish(TestUpdateMsg);
+    assert_eq!(1, wakes.0.load(Ordering::Relaxed));
+    assert_eq!(9, counter.get());
+    let mut settled = pending.borrow_mut().take().expect("polled in handler");
+    assert_eq!(Poll::Ready(()), settled.as_mut().poll(&mut cx));
+}
+
+#[test]
+fn explicit_status_transitions() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|a, msg: &TestMessage| a.inc(msg.0));
+    assert_eq!(LifecycleStatus::Active, id.status());
+
+    id.deactivate();
+    assert_eq!(LifecycleStatus::Inactive, id.status());
+    crate::publish(TestMessage(1));
+    id.activate();
+    crate::publish(TestMessage(2));
+    assert_eq!(2, counter.get());
+
+    id.set_status(LifecycleStatus::Deleted);
+    assert_eq!(LifecycleStatus::Deleted, id.status());
+    crate::publish(TestMessage(4));
+    assert_eq!(2, counter.get());
+}
+
+#[test]
+fn panic_handler_isolates_handlers() {
+    let panics = Rc::new(RefCell::new(Vec::new()));
+    let log = panics.clone();
+    crate::set_panic_handler(move |panic| {
+        log.borrow_mut().push((
+            panic.activity(),
+            panic.type_name(),
+            panic.message().map(str::to_owned),
+        ));
+    });
+    let faulty = crate::new_domained_activity(TestActivity::new(), &DefaultDomain);
+    faulty.subscribe_domained(|a, domain, msg: &TestMessage| {
+        a.inc(1);
+        domain.store(msg.0);
+        crate::publish(TestUpdateMsg);
+        panic!("faulty handler {}", msg.0);
+    });
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let healthy = crate::new_domained_activity(a, &DefaultDomain);
+    healthy.subscribe_domained(|a, domain, msg: &TestMessage| {
+        a.inc(msg.0 + *domain.get::<u32>());
+    });
+    healthy.subscribe(|a, _: &TestUpdateMsg| a.inc(100));
+
+    crate::publish(TestMessage(1));
+    assert_eq!(102, counter.get());
+    // Borrows of the activities and the domain have been released
+    crate::publish(TestMessage(2));
+    assert_eq!(206, counter.get());
+    assert_eq!(Some(2), faulty.read(|a| a.counter.get()));
+    assert_eq!(
+        vec![
+            (
+                faulty.into(),
+                std::any::type_name::<TestMessage>(),
+                Some("faulty handler 1".to_owned())
+            ),
+            (
+                faulty.into(),
+                std::any::type_name::<TestMessage>(),
+                Some("faulty handler 2".to_owned())
+            ),
+        ],
+        *panics.borrow()
+    );
+}
+
+#[test]
+fn self_republish_limit() {
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    id.subscribe(|a, msg: &TestMessage| {
+        a.inc(1);
+        crate::publish(TestMessage(msg.0 + 1));
+    });
+    // Publishing other types is not limited
+    id.subscribe(|a, _: &TestUpdateMsg| {
+        a.inc(10);
+        crate::publish(TestMessage(0));
+    });
+    crate::set_self_republish_policy(SelfRepublishPolicy::Limit(2));
+
+    crate::publish(TestMessage(0));
+    assert_eq!(3, counter.get());
+    // The limit applies per top-level cascade
+    crate::publish(TestUpdateMsg);
+    assert_eq!(3 + 10 + 3, counter.get());
+
+    crate::set_self_republish_policy(SelfRepublishPolicy::DropWithWarning);
+    crate::publish(TestMessage(0));
+    assert_eq!(17, counter.get());
+}
+
+#[test]
+fn publish_required() {
+    struct Command(u32);
+    impl RequiredMessage for Command {}
+    assert_eq!(
+        Err(NutsError::Unhandled),
+        crate::publish_required(Command(1))
+    );
+
+    let a = TestActivity::new();
+    let counter = a.shared_counter_ref();
+    let id = crate::new_activity(a);
+    let first = id.subscribe(|a, msg: &Command| a.inc(msg.0));
+    id.subscribe(|a, msg: &Command| a.inc(msg.0 * 10));
+    assert_eq!(Ok(2), crate::publish_required(Command(2)));
+    assert_eq!(22, counter.get());
+
+    crate::unsubscribe(first);
+    assert_eq!(Ok(1), crate::publish_required(Command(1)));
+    assert_eq!(32, counter.get());
+}
+
+#[test]
+fn named_topics_do_not_collide() {
+    let a = new_activity(Vec::<String>::new());
+    a.subscribe_on_topic("metrics", |log, s: &String| {
+        log.push(format!("metrics {}", s))
+    });
+    a.subscribe_on_topic("chat", |log, s: &String| log.push(format!("chat {}", s)));
+    a.subscribe(|log, s: &String| log.push(format!("plain {}", s)));
+
+    crate::publish_on_topic("metrics", "1".to_owned());
+    crate::publish_on_topic("chat", "2".to_owned());
+    crate::publish("3".to_owned());
+    crate::publish_on_topic("unknown", "4".to_owned());
+
+    assert_eq!(
+        Some(vec![
+            "metrics 1".to_owned(),
+            "chat 2".to_owned(),
+            "plain 3".to_owned()
+        ]),
+        a.read(|log| log.clone())
+    );
+}
+
+#[test]
+fn introspection_counts_inside_handlers() {
+    struct Probe;
+    struct Unheard;
+    let activities = crate::activity_count();
+    let a = new_activity((0, 0, false));
+    let b = new_activity(());
+    a.subscribe(|counts, _: &Probe| {
+        *counts = (
+            crate::subscriber_count::<Probe>(),
+            crate::activity_count(),
+            crate::has_subscribers::<Unheard>(),
+        )
+    });
+    b.subscribe(|_, _: &Probe| {});
+    assert_eq!(activities + 2, crate::activity_count());
+
+    crate::publish(Probe);
+    assert_eq!(Some((2, activities + 2, false)), a.read(|c| *c));
+
+    b.set_status(LifecycleStatus::Deleted);
+    assert_eq!(activities + 1, crate::activity_count());
+}
+
+#[test]
+fn domain_diff_reports_changes() {
+    #[derive(Clone, PartialEq)]
+    struct Score(u32);
+    #[derive(Clone, PartialEq)]
+    struct Name(&'static str);
+    #[derive(Clone, PartialEq)]
+    struct Level(u8);
+    #[derive(Clone, PartialEq)]
+    struct Unregistered;
+    crate::register_diffable::<Score>();
+    crate::register_diffable::<Name>();
+    crate::register_diffable::<Level>();
+    crate::store_to_domain(&DefaultDomain, Score(1));
+    crate::store_to_domain(&DefaultDomain, Level(1));
+    let before = crate::snapshot_domain_for_diff(&DefaultDomain);
+
+    let a = new_domained_activity((), &DefaultDomain);
+    a.subscribe_domained(|_, domain, _: &()| {
+        domain.get_mut::<Score>().0 += 1;
+        domain.store(Name("player"));
+        domain.store(Unregistered);
+    });
+    crate::publish(());
+    let after = crate::snapshot_domain_for_diff(&DefaultDomain);
+
+    assert_eq!(
+        vec![
+            crate::Changed::Added(std::any::type_name::<Name>()),
+            crate::Changed::Modified(std::any::type_name::<Score>()),
+        ],
+        before.diff(&after)
+    );
+    assert_eq!(
+        vec![
+            crate::Changed::Removed(std::any::type_name::<Name>()),
+            crate::Changed::Modified(std::any::type_name::<Score>()),
+        ],
+        after.diff(&before)
+    );
+}
+
+#[test]
+fn publish_with_in_handler_is_queued() {
+    struct Trigger;
+    struct Expensive(u32);
+    let built = Rc::new(Cell::new(0));
+    let a = new_activity(Vec::new());
+    let counter = built.clone();
+    a.subscribe(move |log: &mut Vec<&str>, _: &Trigger| {
+        let counter = counter.clone();
+        crate::publish_with(move || {
+            counter.set(counter.get() + 1);
+            Expensive(1)
+        });
+        log.push("trigger");
+    });
+    a.subscribe(|log, msg: &Expensive| {
+        assert_eq!(1, msg.0);
+        log.push("expensive");
+    });
+
+    crate::publish(Trigger);
+    assert_eq!(1, built.get());
+    assert_eq!(
+        Some(vec!["trigger", "expensive"]),
+        a.read(|log| log.clone())
+    );
+
+    a.set_status(LifecycleStatus::Inactive);
+    crate::publish_with(|| -> Expensive { panic!("constructed without receivers") });
+    assert_eq!(1, built.get());
+}
+
+#[test]
+#[should_panic(expected = "is already borrowed")]
+fn with_inside_own_handler_panics() {
+    let a = new_activity(0u32);
+    a.subscribe(move |_, _: &()| {
+        a.with(|_| ());
+    });
+    crate::publish(());
+}
+
+#[test]
+fn with_mut_from_other_handler() {
+    let a = new_activity(0u32);
+    let b = new_activity(());
+    b.subscribe(move |_, msg: &u32| a.with_mut(|n| *n += *msg));
+    crate::publish(5u32);
+    assert_eq!(5, a.with(|n| *n));
+}
+
+#[test]
+fn domain_remove_and_lazy_insert_in_handler() {
+    struct Pending(Vec<u32>);
+    let a = new_domained_activity(Vec::new(), &DefaultDomain);
+    a.subscribe_domained(|taken: &mut Vec<u32>, domain, msg: &u32| {
+        domain
+            .get_or_insert_with(|| Pending(Vec::new()))
+            .0
+            .push(*msg);
+        if *msg == 3 {
+            *taken = domain.remove::<Pending>().map(|p| p.0).unwrap_or_default();
+        }
+    });
+    for i in 1..=3u32 {
+        crate::publish(i);
+    }
+    assert_eq!(Some(vec![1, 2, 3]), a.read(|taken| taken.clone()));
+    crate::with_domain(&DefaultDomain, |domain| {
+        assert!(domain.try_get::<Pending>().is_none())
+    });
+}
+
+#[test]
+fn deferred_messages_wait_for_next_run() {
+    struct Frame(u32);
+    let a = new_activity(Vec::new());
+    a.subscribe(|frames, frame: &Frame| {
+        frames.push(frame.0);
+        crate::publish_deferred(Frame(frame.0 + 1));
+    });
+
+    crate::publish_deferred(Frame(0));
+    assert_eq!(0, crate::process_deferred_limited(10));
+    assert_eq!(Some(vec![]), a.read(|frames| frames.clone()));
+    assert_eq!(1, crate::run_deferred());
+    assert_eq!(1, crate::run_deferred());
+    assert_eq!(Some(vec![0, 1]), a.read(|frames| frames.clone()));
+}
+
+#[test]
+fn domained_variants_share_one_closure() {
+    #[derive(Clone)]
+    struct Deposit(u32);
+    #[derive(Clone)]
+    struct Withdraw(u32);
+    enum Booking {
+        In(Deposit),
+        Out(Withdraw),
+    }
+    crate::message_variants!(Booking { In(Deposit), Out(Withdraw) });
+
+    let bank = new_domained_activity(0u32, &DefaultDomain);
+    let ids = bank.subscribe_domained_variants(|bookings, domain, booking: Booking| {
+        *bookings += 1;
+        let balance = domain.get_or_default::<i64>();
+        match booking {
+            Booking::In(Deposit(n)) => *balance += i64::from(n),
+            Booking::Out(Withdraw(n)) => *balance -= i64::from(n),
+        }
+    });
+    assert_eq!(2, ids.len());
+
+    crate::publish(Deposit(10));
+    crate::publish(Withdraw(3));
+    assert_eq!(Some(2), bank.read(|bookings| *bookings));
+    crate::with_domain(&DefaultDomain, |domain| assert_eq!(7, *domain.get::<i64>()));
+}
+
+#[test]
+fn publish_checked_collects_errors_of_cascade() {
+    struct Validate(u32);
+    struct Nested;
+    #[derive(Debug, PartialEq)]
+    struct Invalid(u32);
+    let a = new_activity(());
+    a.subscribe_checked(|_, msg: &Validate| {
+        crate::publish(Nested);
+        if msg.0 > 10 {
+            Err(Invalid(msg.0))
+        } else {
+            Ok(())
+        }
+    });
+    a.subscribe_checked(|_, _: &Nested| Err(Invalid(0)));
+    a.subscribe_checked(|_, _: &Validate| Err("other error type"));
+
+    assert_eq!(
+        vec![Invalid(11), Invalid(0)],
+        crate::publish_checked::<_, Invalid>(Validate(11))
+    );
+    // Errors are only collected during `publish_checked`
+    crate::publish(Validate(12));
+    assert_eq!(
+        vec![Invalid(0)],
+        crate::publish_checked::<_, Invalid>(Nested)
+    );
+}
+
+#[test]
+fn lifecycle_events_precede_queued_messages() {
+    struct Toggle(bool);
+    struct Ping;
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let target = new_activity(log.clone());
+    target.on_enter(|log| log.borrow_mut().push("enter"));
+    target.on_leave(|log| log.borrow_mut().push("leave"));
+    target.subscribe(|log, _: &Ping| log.borrow_mut().push("ping"));
+    let controller = new_activity(log.clone());
+    controller.subscribe(move |log, msg: &Toggle| {
+        target.set_status(if msg.0 {
+            LifecycleStatus::Active
+        } else {
+            LifecycleStatus::Inactive
+        });
+        crate::publish(Ping);
+        log.borrow_mut().push("published");
+    });
+
+    crate::publish(Toggle(false));
+    crate::publish(Toggle(true));
+    assert_eq!(
+        vec!["published", "leave", "published", "enter", "ping"],
+        *log.borrow()
+    );
+}
+
+#[test]
+fn weak_activity_id_after_deletion_in_handler() {
+    struct Remove;
+    let target = new_activity(7u32);
+    let weak = target.downgrade();
+    let remover = new_activity(Vec::new());
+    remover.subscribe(move |upgraded, _: &Remove| {
+        target.set_status(LifecycleStatus::Deleted);
+        upgraded.push(weak.upgrade().is_some());
+    });
+
+    crate::publish(Remove);
+    assert_eq!(Some(vec![true]), remover.read(|upgraded| upgraded.clone()));
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn publish_batch_matches_repeated_publish() {
+    struct Spark(u32);
+    struct Echo(u32);
+    let a = new_activity(Vec::new());
+    a.subscribe_once(|log: &mut Vec<String>, spark: &Spark| log.push(format!("once {}", spark.0)));
+    a.subscribe(|log, spark: &Spark| {
+        log.push(format!("spark {}", spark.0));
+        crate::publish(Echo(spark.0));
+    });
+    a.subscribe(|log, echo: &Echo| {
+        log.push(format!(
+            "echo {} gen {}",
+            echo.0,
+            crate::cascade_generation()
+        ))
+    });
+
+    crate::publish_batch(vec![Spark(1), Spark(2)]);
+    assert_eq!(
+        Some(vec![
+            "once 1".to_owned(),
+            "spark 1".to_owned(),
+            "echo 1 gen 1".to_owned(),
+            "spark 2".to_owned(),
+            "echo 2 gen 1".to_owned(),
+        ]),
+        a.read(|log| log.clone())
+    );
+}
+
+#[test]
+fn dispatch_observer_reports_each_dispatch() {
+    struct Tick;
+    let now = Rc::new(Cell::new(0));
+    let clock = now.clone();
+    crate::set_clock(move || clock.get());
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let log = events.clone();
+    crate::set_dispatch_observer(move |event: DispatchEvent| log.borrow_mut().push(event));
+
+    let a = new_activity(now.clone());
+    a.subscribe(|now, _: &Tick| now.set(now.get() + 5));
+    let b = new_activity(());
+    b.subscribe(|_, _: &Tick| {});
+    b.set_status(LifecycleStatus::Inactive);
+
+    crate::publish(Tick);
+    crate::publish_to(a, Tick);
+    let events = events.borrow();
+    assert_eq!(2, events.len());
+    assert_eq!(core::any::TypeId::of::<Tick>(), events[0].type_id);
+    assert_eq!(1, events[0].subscribers);
+    assert!(!events[0].queued);
+    assert_eq!(Some(5), events[0].elapsed_ms);
+    assert!(events[1].queued);
+}
+
+#[test]
+fn filtered_once_ignores_rejected_messages() {
+    let a = new_activity(Vec::new());
+    a.subscribe_once_filtered(|msg: &TestMessage| msg.0 > 1, |log, msg| log.push(msg.0));
+    crate::publish(TestMessage(1));
+    crate::publish(TestMessage(2));
+    crate::publish(TestMessage(3));
+    assert_eq!(Some(vec![2]), a.read(|log| log.clone()));
+}
+
+#[test]
+fn is_subscribed_per_activity() {
+    let a = new_activity(false);
+    let b = new_activity(());
+    a.subscribe(|_, _: &TestMessage| {});
+    b.subscribe(|_, _: &TestUpdateMsg| {});
+    b.subscribe(move |_, _: &TestUpdateMsg| crate::publish(a.is_subscribed::<TestMessage>()));
+    a.subscribe(|checked, inside: &bool| *checked = *inside);
+
+    assert!(a.is_subscribed::<TestMessage>());
+    assert!(!a.is_subscribed::<TestUpdateMsg>());
+    assert!(!b.is_subscribed::<TestMessage>());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(Some(true), a.read(|checked| *checked));
+
+    let id = a.subscribe(|_, _: &u8| {});
+    crate::unsubscribe(id);
+    assert!(!a.is_subscribed::<u8>());
+}
+
+#[test]
+fn replace_subscription_keeps_once() {
+    let a = new_activity(Vec::new());
+    let first = a.subscribe_once(|log, msg: &TestMessage| log.push(msg.0));
+    a.subscribe(|log, msg: &TestMessage| log.push(100 + msg.0));
+    assert!(first.replace(|log: &mut Vec<u32>, msg: &TestMessage| log.push(10 + msg.0)));
+    crate::publish(TestMessage(1));
+    crate::publish(TestMessage(2));
+    assert_eq!(Some(vec![11, 101, 102]), a.read(|log| log.clone()));
+    assert!(!first.replace(|_: &mut Vec<u32>, _: &TestMessage| {}));
+}
+
+#[test]
+#[should_panic(expected = "another activity type")]
+fn replace_subscription_wrong_activity_type() {
+    let a = new_activity(0u8);
+    let id = a.subscribe(|_, _: &TestMessage| {});
+    id.replace(|_: &mut u16, _: &TestMessage| {});
+}
+
+#[test]
+fn publish_counted_ignores_cascade() {
+    let a = new_activity(());
+    a.subscribe(|_, _: &TestMessage| crate::publish(TestUpdateMsg));
+    let b = new_activity(());
+    b.subscribe(|_, _: &TestMessage| {});
+    b.subscribe(|_, _: &TestUpdateMsg| {});
+    b.subscribe(|_, _: &TestUpdateMsg| {});
+    assert_eq!(2, crate::publish_counted(TestMessage(0)));
+    assert_eq!(0, crate::publish_counted(UnhandledMsg));
+}
+
+#[test]
+fn publish_counted_ignores_queued() {
+    let a = new_activity(());
+    a.subscribe(|_, _: &TestMessage| {});
+    a.subscribe(|_, _: &TestUpdateMsg| {});
+    crate::enqueue(TestUpdateMsg);
+    crate::enqueue(TestUpdateMsg);
+    assert_eq!(1, crate::publish_counted(TestMessage(0)));
+    assert_eq!(0, crate::process_deferred_limited(1));
+}
+
+#[test]
+#[should_panic(expected = "can only be called outside of activities")]
+fn publish_counted_inside_activity() {
+    let a = new_activity(());
+    a.subscribe(|_, _: &TestMessage| {
+        crate::publish_counted(TestUpdateMsg);
+    });
+    crate::publish(TestMessage(0));
+}
+
+#[test]
+fn subscribe_domained_with_reads_other_domain() {
+    crate::store_to_domain(&TestDomains::DomainB, 7u32);
+    let a = crate::new_domained_activity((), &TestDomains::DomainA);
+    a.subscribe_domained_with(&TestDomains::DomainB, |_, own, extra, msg: &TestMessage| {
+        own.store(extra.get::<u32>() + msg.0);
+    });
+    crate::publish(TestMessage(1));
+    let b = crate::new_domained_activity(0u32, &TestDomains::DomainA);
+    b.subscribe_domained(|value, own, _: &TestUpdateMsg| *value = *own.get::<u32>());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(Some(8), b.read(|value| *value));
+}
+
+#[test]
+#[should_panic(expected = "another domain than the activity's own domain")]
+fn subscribe_domained_with_own_domain() {
+    let a = crate::new_domained_activity((), &TestDomains::DomainA);
+    a.subscribe_domained_with(&TestDomains::DomainA, |_, _, _, _: &TestMessage| {});
+}
+
+#[test]
+fn call_method_publishes_and_is_removed_on_delete() {
+    let a = new_activity(0u32);
+    a.add_method(
+        "bump",
+        crate::Method::new(|count, domain| {
+            assert!(domain.is_none());
+            *count += 1;
+            crate::publish(TestMessage(*count));
+        }),
+    );
+    let listener = new_activity(Vec::new());
+    listener.subscribe(|log, msg: &TestMessage| log.push(msg.0));
+
+    assert_eq!(Ok(()), a.call_method("bump"));
+    assert_eq!(Ok(()), a.call_method("bump"));
+    assert_eq!(Some(vec![1, 2]), listener.read(|log| log.clone()));
+
+    a.delete();
+    let b = new_activity(0u32);
+    assert_eq!(Err(NutsError::UnknownMethod), b.call_method("bump"));
+}
+
+#[test]
+#[should_panic(expected = "Methods can only be called from outside of activities")]
+fn call_method_inside_activity() {
+    let a = new_activity(());
+    a.add_method("noop", crate::Method::new(|_, _| {}));
+    let b = new_activity(());
+    b.subscribe(move |_, _: &TestMessage| {
+        let _ = a.call_method("noop");
+    });
+    crate::publish(TestMessage(0));
+}
+
+#[test]
+fn queue_order_across_types_in_cascade() {
+    struct A(u32);
+    struct B(u32);
+    struct C(u32);
+    let log = new_activity(Vec::new());
+    log.subscribe(|log, msg: &A| {
+        log.push(format!("A{}", msg.0));
+        match msg.0 {
+            0 => {
+                crate::publish(B(0));
+                crate::publish(C(0));
+                crate::publish(A(1));
+            }
+            1 => crate::publish(C(2)),
+            _ => {}
+        }
+    });
+    log.subscribe(|log, msg: &B| {
+        log.push(format!("B{}", msg.0));
+        if msg.0 == 0 {
+            crate::publish(C(10));
+        }
+    });
+    log.subscribe(|log, msg: &C| {
+        log.push(format!("C{}", msg.0));
+        if msg.0 == 0 {
+            crate::publish(B(1));
+        }
+    });
+
+    crate::publish(A(0));
+    assert_eq!(
+        Some("A0 B0 C0 A1 C10 B1 C2".to_owned()),
+        log.read(|log| log.join(" "))
+    );
+}
+
+#[test]
+fn queue_order_across_subscribers() {
+    struct A(u32);
+    struct B(u32);
+    let log = Rc::new(RefCell::new(Vec::new()));
+    for name in ["first", "second"] {
+        let log = log.clone();
+        let id = new_activity(());
+        id.subscribe(move |_, msg: &A| {
+            log.borrow_mut().push(format!("{} A{}", name, msg.0));
+            crate::publish(B(msg.0));
+            if name == "second" && msg.0 == 0 {
+                crate::publish(A(1));
+            }
+        });
+    }
+    let b_log = log.clone();
+    new_activity(()).subscribe(move |_, msg: &B| b_log.borrow_mut().push(format!("B{}", msg.0)));
+
+    crate::publish(A(0));
+    assert_eq!(
+        vec![
+            "first A0",
+            "second A0",
+            "B0",
+            "B0",
+            "first A1",
+            "second A1",
+            "B1",
+            "B1"
+        ],
+        *log.borrow()
+    );
+}
+
+#[test]
+fn dead_letter_after_unsubscribe() {
+    let received = Rc::new(Cell::new(0));
+    let count = received.clone();
+    crate::set_dead_letter(move |_, _| count.set(count.get() + 1));
+    let id = new_activity(());
+    let sub = id.subscribe(|_, _: &TestMessage| {});
+    crate::publish(TestMessage(0));
+    assert_eq!(0, received.get());
+
+    crate::unsubscribe(sub);
+    crate::publish(TestMessage(0));
+    assert_eq!(1, received.get());
+
+    id.subscribe_once(|_, _: &TestMessage| {});
+    crate::publish(TestMessage(0));
+    crate::publish(TestMessage(0));
+    assert_eq!(2, received.get());
+}
+
+#[test]
+#[should_panic(expected = "nobody subscribed")]
+fn strict_unhandled_after_delete() {
+    let id = new_activity(());
+    id.subscribe(|_, _: &TestMessage| {});
+    id.delete();
+    crate::set_strict_unhandled(true);
+    crate::publish(TestMessage(0));
+}
+
+#[test]
+fn subscribe_by_name_shared_activity() {
+    crate::register_message_type::<TestMessage>("test");
+    let shared = Rc::new(RefCell::new(0u32));
+    let id = crate::new_shared_activity(shared.clone());
+    crate::subscribe_by_name(
+        id.into(),
+        "test",
+        Box::new(|activity, msg| {
+            let count = activity
+                .downcast_mut::<u32>()
+                .expect("activity is unwrapped");
+            *count += msg.downcast_ref::<TestMessage>().expect("message").0;
+        }),
+    );
+    crate::publish(TestMessage(2));
+    assert_eq!(2, *shared.borrow());
+
+    // Skipped while borrowed outside of nuts
+    let guard = shared.borrow();
+    crate::publish(TestMessage(5));
+    drop(guard);
+    assert_eq!(2, *shared.borrow());
+}
+
+#[test]
+fn domain_mirror_ignores_earlier_writes() {
+    crate::store_to_domain(&TestDomains::DomainA, 1u32);
+    let id = crate::new_domained_activity((), &TestDomains::DomainA);
+    id.subscribe_domained(|_, domain, _msg: &TestUpdateMsg| *domain.get_mut::<u32>() += 1);
+    crate::publish(TestUpdateMsg);
+
+    let writes = Rc::new(Cell::new(0));
+    let mirror = writes.clone();
+    crate::set_domain_mirror(move |_, _| mirror.set(mirror.get() + 1));
+    crate::store_to_domain(&TestDomains::DomainB, 2u32);
+    assert_eq!(1, writes.get());
+    crate::publish(TestUpdateMsg);
+    assert_eq!(2, writes.get());
+}
+
+#[test]
+fn detect_cycles_after_removal() {
+    struct A;
+    struct B;
+    let id = crate::new_activity(());
+    id.subscribe_publishing::<A, B>(|_, _| {});
+    let back = id.subscribe_publishing::<B, A>(|_, _| {});
+    let other = crate::new_activity(());
+    other.subscribe_publishing::<B, A>(|_, _| {});
+    assert_eq!(1, crate::detect_cycles().len());
+
+    crate::unsubscribe(back);
+    assert_eq!(1, crate::detect_cycles().len());
+    other.delete();
+    assert!(crate::detect_cycles().is_empty());
+}
+
+#[test]
+fn unsubscribe_deferred_drops_queued_deliveries() {
+    #[derive(Clone)]
+    struct Analytics;
+    let id = crate::new_activity(0u32);
+    let deferred = id.subscribe_deferred(|count, _: &Analytics| *count += 1);
+    // Runs after the deferred delivery has been queued
+    id.subscribe(move |_, _: &Analytics| crate::unsubscribe(deferred));
+
+    crate::publish(Analytics);
+    crate::publish(Analytics);
+    assert_eq!(Some(0), id.read(|count| *count));
+}
@@ -0,0 +1,32 @@
+//! Optional check that nuts is only used on one thread, see `nuts::bind_to_current_thread`.
+//!
+//! The guard is process-wide, unlike the rest of the state which is thread-local.
+
+use std::sync::OnceLock;
+use std::thread::ThreadId;
+
+/// The only thread allowed to use nuts, once bound
+static OWNER: OnceLock<ThreadId> = OnceLock::new();
+
+pub(crate) fn bind_to_current_thread() {
+    let current = std::thread::current().id();
+    let owner = *OWNER.get_or_init(|| current);
+    assert_eq!(
+        owner, current,
+        "nuts has already been bound to another thread."
+    );
+}
+
+/// Panics if nuts is bound to another thread, only checked in debug builds.
+pub(crate) fn check_thread() {
+    #[cfg(debug_assertions)]
+    if let Some(owner) = OWNER.get() {
+        let current = std::thread::current();
+        assert!(
+            *owner == current.id(),
+            "nuts has been used on thread {:?}, but it is bound to another thread. \
+            All state of nuts is thread-local, activities and domains of the bound thread are not visible here.",
+            current.name().unwrap_or("<unnamed>"),
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::nut::Nut;
+use crate::*;
+use core::any::{Any, TypeId};
+use core::fmt;
+
+/// A structural problem found by [`nuts::validate_wiring`](fn.validate_wiring.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WiringIssue {
+    /// The activity has been assigned to a domain in which no data has been stored.
+    ///
+    /// This usually means that a call to `store_to_domain` is missing.
+    EmptyDomain {
+        /// The activity assigned to the domain
+        activity: UncheckedActivityId,
+        /// The empty domain
+        domain: DomainId,
+    },
+}
+
+impl fmt::Display for WiringIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyDomain { activity, domain } => write!(
+                f,
+                "{:?} has been assigned to {:?}, which contains no data.",
+                activity, domain
+            ),
+        }
+    }
+}
+
+impl Nut {
+    pub(crate) fn validate_wiring(&self) -> Result<(), Vec<WiringIssue>> {
+        let activities = self
+            .activities
+            .try_borrow()
+            .expect("The wiring cannot be validated from inside an activity.");
+        let managed_state = self
+            .managed_state
+            .try_borrow()
+            .expect("The wiring cannot be validated from inside an activity.");
+        let issues: Vec<_> = activities
+            .domains()
+            .filter(|(_, domain)| {
+                domain.index().is_some() && managed_state.is_domain_empty(*domain)
+            })
+            .map(|(activity, domain)| WiringIssue::EmptyDomain { activity, domain })
+            .collect();
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
+
+/// Declared publish relationships between message types, see `subscribe_publishing`
+#[derive(Default)]
+pub(crate) struct PublishGraph {
+    /// Message types in order of first declaration
+    nodes: Vec<(TypeId, &'static str)>,
+    /// Outgoing edges per node
+    edges: Vec<Vec<Edge>>,
+}
+
+/// A declaration, which is removed together with the subscription that made it
+struct Edge {
+    to: usize,
+    subscription: SubscriptionId,
+    activity: UncheckedActivityId,
+}
+
+impl PublishGraph {
+    pub(crate) fn declare<MSG: Any, PUBLISHES: Any>(
+        &mut self,
+        subscription: SubscriptionId,
+        activity: UncheckedActivityId,
+    ) {
+        let from = self.node::<MSG>();
+        let to = self.node::<PUBLISHES>();
+        self.edges[from].push(Edge {
+            to,
+            subscription,
+            activity,
+        });
+    }
+    pub(crate) fn remove_subscription(&mut self, id: SubscriptionId) {
+        for edges in &mut self.edges {
+            edges.retain(|edge| edge.subscription != id);
+        }
+    }
+    pub(crate) fn remove_activities(&mut self, ids: &[UncheckedActivityId]) {
+        for edges in &mut self.edges {
+            edges.retain(|edge| !ids.contains(&edge.activity));
+        }
+    }
+    /// Nodes reachable over one edge, without duplicates, in order of declaration
+    fn targets(&self, node: usize) -> Vec<usize> {
+        let mut targets: Vec<usize> = Vec::new();
+        for edge in &self.edges[node] {
+            if !targets.contains(&edge.to) {
+                targets.push(edge.to);
+            }
+        }
+        targets
+    }
+    fn node<T: Any>(&mut self) -> usize {
+        let id = TypeId::of::<T>();
+        if let Some(index) = self.nodes.iter().position(|(node, _)| *node == id) {
+            return index;
+        }
+        self.nodes.push((id, std::any::type_name::<T>()));
+        self.edges.push(vec![]);
+        self.nodes.len() - 1
+    }
+    /// All elementary cycles, each starting at its earliest declared message type
+    pub(crate) fn cycles(&self) -> Vec<Vec<&'static str>> {
+        let mut cycles = vec![];
+        let mut on_path = vec![false; self.nodes.len()];
+        for start in 0..self.nodes.len() {
+            let mut path = vec![start];
+            on_path[start] = true;
+            self.find_cycles(start, &mut path, &mut on_path, &mut cycles);
+            on_path[start] = false;
+        }
+        cycles
+    }
+    /// Extends the path with nodes after `start`, so that every cycle is found exactly once
+    fn find_cycles(
+        &self,
+        start: usize,
+        path: &mut Vec<usize>,
+        on_path: &mut [bool],
+        cycles: &mut Vec<Vec<&'static str>>,
+    ) {
+        let last = *path.last().expect("path starts with a node");
+        for next in self.targets(last) {
+            if next == start {
+                cycles.push(path.iter().map(|&node| self.nodes[node].1).collect());
+            } else if next > start && !on_path[next] {
+                path.push(next);
+                on_path[next] = true;
+                self.find_cycles(start, path, on_path, cycles);
+                on_path[next] = false;
+                path.pop();
+            }
+        }
+    }
+}